@@ -1,4 +1,4 @@
-use crate::commands::Commands;
+use crate::commands::{ApprovalMode, Commands};
 use clap::Parser;
 
 #[derive(Parser)]
@@ -17,6 +17,23 @@ pub(crate) struct Cli {
     #[arg(long)]
     pub model: Option<String>,
 
+    /// Model provider for the current session ("openai", "openrouter", "ollama", ...)
+    #[arg(long)]
+    pub provider: Option<String>,
+
+    /// Continue a recorded session, by id (or unique id prefix) or file path
+    #[arg(long, value_name = "SESSION")]
+    pub resume: Option<String>,
+
+    /// When to ask before running a command or applying a patch
+    #[arg(long, short = 'a', value_enum, value_name = "POLICY")]
+    pub ask_for_approval: Option<ApprovalMode>,
+
+    /// Embeddings for the code index: "sentence-transformers[:MODEL]", "hashing" (offline),
+    /// or "PROVIDER:MODEL" for a provider's /embeddings endpoint, e.g. "openai:text-embedding-3-small"
+    #[arg(long, value_name = "EMBEDDER")]
+    pub embedder: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
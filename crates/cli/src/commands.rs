@@ -1,4 +1,4 @@
-use clap::Subcommand;
+use clap::{Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Subcommand, Debug)]
@@ -10,9 +10,16 @@ pub(crate) enum Commands {
     },
     /// Check the health of your Corty Code auto-updater
     Doctor,
-    /// Refactor existing code
+    /// List recorded sessions, newest first
+    Sessions,
+    /// Tools for debugging Corty itself
+    Debug {
+        #[command(subcommand)]
+        action: DebugAction,
+    },
+    /// Build the semantic code index for a file or directory
     Index {
-        /// Path to the file or directory to Index
+        /// Path to the file or directory to index
         path: PathBuf,
     },
 }
@@ -61,3 +68,45 @@ pub(crate) enum ConfigAction {
         yes: bool,
     },
 }
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum DebugAction {
+    /// Run a command under the sandbox policy agent commands get
+    Sandbox {
+        /// What the command may do
+        #[arg(long, value_enum, default_value_t = SandboxMode::WorkspaceWrite)]
+        mode: SandboxMode,
+        /// Extra directory the command may write to (workspace-write only)
+        #[arg(long = "writable-root", value_name = "DIR")]
+        writable_roots: Vec<PathBuf>,
+        /// Allow network access (workspace-write only)
+        #[arg(long)]
+        network: bool,
+        /// Command to run, with its arguments
+        #[arg(trailing_var_arg = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum SandboxMode {
+    /// Read anything, write nothing, no network
+    ReadOnly,
+    /// Write only to the current directory and the temp dirs
+    WorkspaceWrite,
+    /// No restrictions
+    DangerFullAccess,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum ApprovalMode {
+    /// Never ask; failures go back to the model
+    Never,
+    /// Ask unless the command is known to only read
+    #[value(name = "untrusted")]
+    UnlessTrusted,
+    /// Ask to run a command outside the sandbox after it failed in it
+    OnFailure,
+    /// Ask before every command and patch
+    Always,
+}
@@ -0,0 +1,88 @@
+//! `corty index`: build the semantic code index with a progress bar.
+
+use color_eyre::{eyre::eyre, Result};
+use colored::Colorize;
+use corty_core::{
+    config::Config,
+    indexer::{index_path, IndexEvent},
+    storage::VectorDB,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::path::{Path, PathBuf};
+
+/// Errors listed after the summary; the rest are only counted.
+const MAX_LISTED_ERRORS: usize = 20;
+
+pub(crate) async fn run(config: &Config, path: &Path) -> Result<()> {
+    let index_dir = config
+        .index_dir
+        .as_ref()
+        .ok_or_else(|| eyre!("no index directory on this platform"))?;
+    let path = path
+        .canonicalize()
+        .map_err(|e| eyre!("cannot access {}: {e}", path.display()))?;
+    let root = project_root(config, &path);
+
+    let progress = ProgressBar::new_spinner().with_message("Loading the embedding model");
+    progress.enable_steady_tick(std::time::Duration::from_millis(100));
+    let db = VectorDB::connect(&index_dir.to_string_lossy(), &config.embedding).await?;
+    progress.set_message(format!("Scanning {}", path.display()));
+
+    let summary = index_path(&db, &root, &path, |event| match event {
+        IndexEvent::Scanned { files } => {
+            progress.set_length(files as u64);
+            progress.set_style(
+                ProgressStyle::with_template("{bar:40} {pos}/{len} files {wide_msg}")
+                    .expect("valid template"),
+            );
+        }
+        IndexEvent::FileDone { path } => {
+            progress.inc(1);
+            progress.set_message(
+                path.strip_prefix(&root)
+                    .unwrap_or(&path)
+                    .display()
+                    .to_string(),
+            );
+        }
+    })
+    .await;
+    progress.finish_and_clear();
+    let summary = summary?;
+
+    println!(
+        "{} {} added, {} updated, {} removed, {} unchanged files; {} chunks in {:.1}s ({} skipped, {} errors)",
+        "Indexed".green().bold(),
+        summary.added,
+        summary.updated,
+        summary.removed,
+        summary.unchanged,
+        summary.chunks,
+        summary.elapsed.as_secs_f64(),
+        summary.skipped,
+        summary.errors.len()
+    );
+    for (path, error) in summary.errors.iter().take(MAX_LISTED_ERRORS) {
+        eprintln!("{} {}: {error}", "error:".red(), path.display());
+    }
+    if summary.errors.len() > MAX_LISTED_ERRORS {
+        eprintln!("... and {} more", summary.errors.len() - MAX_LISTED_ERRORS);
+    }
+    Ok(())
+}
+
+/// The project `path` belongs to: the working directory when `path` lies in
+/// it, so `semantic_search` in a session started there finds the index;
+/// otherwise `path` itself, or its directory for a file.
+fn project_root(config: &Config, path: &Path) -> PathBuf {
+    if let Ok(cwd) = config.cwd.canonicalize() {
+        if path.starts_with(&cwd) {
+            return cwd;
+        }
+    }
+    if path.is_dir() {
+        path.to_path_buf()
+    } else {
+        path.parent().unwrap_or(path).to_path_buf()
+    }
+}
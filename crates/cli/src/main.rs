@@ -1,9 +1,19 @@
 mod cli;
+mod index;
 use crate::cli::Cli;
 use clap::Parser;
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
 pub(crate) mod commands;
+use crate::commands::{ApprovalMode, Commands, DebugAction, SandboxMode};
+use corty_core::{
+    config::Config,
+    protocol::{AskForApproval, SandboxPolicy},
+    sandbox::sandboxed_command,
+    session,
+    storage::EmbeddingProvider,
+};
 use corty_tui::run_tui;
+use std::path::PathBuf;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -16,12 +26,91 @@ async fn main() -> Result<()> {
 
     env_logger::init();
 
+    let mut config = Config::default();
+    if let Some(model) = &cli.model {
+        config.model = model.clone();
+    }
+    if let Some(provider) = &cli.provider {
+        config.model_provider = config
+            .model_providers
+            .get(provider)
+            .cloned()
+            .ok_or_else(|| eyre!("unknown model provider `{provider}`"))?;
+    }
+    if let Some(mode) = cli.ask_for_approval {
+        config.approval_policy = match mode {
+            ApprovalMode::Never => AskForApproval::Never,
+            ApprovalMode::UnlessTrusted => AskForApproval::UnlessTrusted,
+            ApprovalMode::OnFailure => AskForApproval::OnFailure,
+            ApprovalMode::Always => AskForApproval::Always,
+        };
+    }
+    if let Some(embedder) = &cli.embedder {
+        config.embedding = EmbeddingProvider::parse(embedder, &config.model_providers)?;
+    }
+    if let Some(resume) = &cli.resume {
+        config.resume_session = Some(resolve_session(&config, resume)?);
+    }
+
     match &cli.command {
+        Some(Commands::Sessions) => {
+            let data_dir = config
+                .data_dir
+                .ok_or_else(|| eyre!("no data directory on this platform"))?;
+            for info in session::list_sessions(&data_dir)? {
+                println!(
+                    "{}  {}  {}  {}",
+                    info.meta.id,
+                    info.meta.timestamp.format("%Y-%m-%d %H:%M"),
+                    info.meta.model,
+                    info.meta.cwd.display()
+                );
+            }
+        }
+        Some(Commands::Debug {
+            action:
+                DebugAction::Sandbox {
+                    mode,
+                    writable_roots,
+                    network,
+                    command,
+                },
+        }) => {
+            let policy = match mode {
+                SandboxMode::ReadOnly => SandboxPolicy::ReadOnly,
+                SandboxMode::WorkspaceWrite => SandboxPolicy::WorkspaceWrite {
+                    writable_roots: writable_roots.clone(),
+                    network_access: *network,
+                },
+                SandboxMode::DangerFullAccess => SandboxPolicy::DangerFullAccess,
+            };
+            let (program, args) = command
+                .split_first()
+                .ok_or_else(|| eyre!("no command given"))?;
+            let status = sandboxed_command(program, args, &config.cwd, &policy)?.status()?;
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        Some(Commands::Index { path }) => index::run(&config, path).await?,
         Some(_command) => {
             todo!()
         }
-        None => run_tui().await?,
+        None => run_tui(config).await?,
     };
 
     Ok(())
 }
+
+/// Resolve `--resume` to a session file: an existing path, or a session id.
+fn resolve_session(config: &Config, resume: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(resume);
+    if path.is_file() {
+        return Ok(path);
+    }
+    let data_dir = config
+        .data_dir
+        .as_ref()
+        .ok_or_else(|| eyre!("no data directory on this platform"))?;
+    session::find_session(data_dir, resume)?
+        .map(|info| info.path)
+        .ok_or_else(|| eyre!("no session matches `{resume}`"))
+}
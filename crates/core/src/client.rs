@@ -0,0 +1,442 @@
+//! Streaming model client.
+//!
+//! Talks to the configured provider over its wire API (Responses or Chat
+//! Completions) and turns the server-sent events into a single stream of
+//! [`ResponseEvent`]s, so the engine does not care which one is in use.
+
+use crate::{
+    config::Config,
+    error::{CortyErr, Result},
+    models::{ContentItem, ResponseItem},
+    protocol::{ModelProviderInfo, TokenUsage, WireApi},
+    tools::ToolSpec,
+};
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{sync::mpsc, task::AbortHandle};
+
+/// Capacity of the channel between the SSE parser and the turn loop.
+const RESPONSE_CHANNEL_CAPACITY: usize = 16;
+
+/// Everything the model needs to produce the next response.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Prompt {
+    /// System instructions.
+    pub instructions: String,
+    /// Conversation history, oldest first.
+    pub input: Vec<ResponseItem>,
+    /// Tools the model may call.
+    pub tools: Vec<ToolSpec>,
+}
+
+/// Incremental output of a streaming model response
+#[derive(Debug)]
+pub(crate) enum ResponseEvent {
+    /// A chunk of assistant text.
+    OutputTextDelta(String),
+    /// A complete item to append to the conversation history.
+    OutputItemDone(ResponseItem),
+    /// The response finished successfully. `token_usage` is `None` if the
+    /// provider did not report usage.
+    Completed { token_usage: Option<TokenUsage> },
+}
+
+/// Stream of [`ResponseEvent`]s for a single model request
+///
+/// Dropping the stream cancels the underlying HTTP request.
+pub(crate) struct ResponseStream {
+    rx_event: mpsc::Receiver<Result<ResponseEvent>>,
+    parser: AbortHandle,
+}
+
+impl Drop for ResponseStream {
+    fn drop(&mut self) {
+        self.parser.abort();
+    }
+}
+
+impl Stream for ResponseStream {
+    type Item = Result<ResponseEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx_event.poll_recv(cx)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ModelClient {
+    model: String,
+    provider: ModelProviderInfo,
+    http: reqwest::Client,
+}
+
+impl ModelClient {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            model: config.model.clone(),
+            provider: config.model_provider.clone(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Send `prompt` to the model and stream back the response.
+    pub async fn stream(&self, prompt: &Prompt) -> Result<ResponseStream> {
+        let mut payload = match self.provider.wire_api {
+            WireApi::Responses => json!({
+                "model": self.model,
+                "instructions": prompt.instructions,
+                "input": prompt.input,
+                "stream": true,
+                "store": false,
+            }),
+            WireApi::Chat => json!({
+                "model": self.model,
+                "messages": chat_messages(prompt),
+                "stream": true,
+                "stream_options": { "include_usage": true },
+            }),
+        };
+        // Some providers reject an empty `tools` array.
+        if !prompt.tools.is_empty() {
+            payload["tools"] = tools_json(self.provider.wire_api, &prompt.tools);
+        }
+
+        let mut request = self
+            .http
+            .post(self.provider.endpoint_url())
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .json(&payload);
+        if let Some(api_key) = self.provider.api_key()? {
+            request = request.bearer_auth(api_key);
+        }
+        let res = request.send().await?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(CortyErr::UnexpectedStatus(status, body));
+        }
+
+        let (tx_event, rx_event) = mpsc::channel(RESPONSE_CHANNEL_CAPACITY);
+        let parser = match self.provider.wire_api {
+            WireApi::Responses => tokio::spawn(process_responses_sse(res.bytes_stream(), tx_event)),
+            WireApi::Chat => tokio::spawn(process_chat_sse(res.bytes_stream(), tx_event)),
+        }
+        .abort_handle();
+        Ok(ResponseStream { rx_event, parser })
+    }
+}
+
+/// Tool definitions in the shape the wire API expects.
+fn tools_json(wire_api: WireApi, tools: &[ToolSpec]) -> Value {
+    tools
+        .iter()
+        .map(|tool| match wire_api {
+            WireApi::Responses => json!({
+                "type": "function",
+                "name": tool.name,
+                "description": tool.description,
+                "parameters": tool.parameters,
+            }),
+            WireApi::Chat => json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name,
+                    "description": tool.description,
+                    "parameters": tool.parameters,
+                },
+            }),
+        })
+        .collect()
+}
+
+/// Convert the prompt into Chat Completions `messages`.
+fn chat_messages(prompt: &Prompt) -> Vec<Value> {
+    let mut messages = vec![json!({ "role": "system", "content": prompt.instructions })];
+    for item in &prompt.input {
+        match item {
+            ResponseItem::FunctionCall {
+                name,
+                arguments,
+                call_id,
+            } => {
+                let tool_call = json!({
+                    "id": call_id,
+                    "type": "function",
+                    "function": { "name": name, "arguments": arguments },
+                });
+                // Calls from the same response belong to one assistant message.
+                match messages.last_mut() {
+                    Some(last) if last["role"] == "assistant" => {
+                        if !last["tool_calls"].is_array() {
+                            last["tool_calls"] = json!([]);
+                        }
+                        if let Some(tool_calls) = last["tool_calls"].as_array_mut() {
+                            tool_calls.push(tool_call);
+                        }
+                    }
+                    _ => messages.push(json!({
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [tool_call],
+                    })),
+                }
+            }
+            ResponseItem::FunctionCallOutput { call_id, output } => {
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": call_id,
+                    "content": output,
+                }));
+            }
+            ResponseItem::Message { role, content } => {
+                let has_image = content
+                    .iter()
+                    .any(|c| matches!(c, ContentItem::InputImage { .. }));
+                if has_image {
+                    // Multimodal messages need the array form of `content`.
+                    let parts: Vec<Value> = content
+                        .iter()
+                        .map(|c| match c {
+                            ContentItem::InputImage { image_url } => {
+                                json!({ "type": "image_url", "image_url": { "url": image_url } })
+                            }
+                            ContentItem::InputText { text } | ContentItem::OutputText { text } => {
+                                json!({ "type": "text", "text": text })
+                            }
+                        })
+                        .collect();
+                    messages.push(json!({ "role": role, "content": parts }));
+                } else {
+                    let text = content
+                        .iter()
+                        .filter_map(ContentItem::text)
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    messages.push(json!({ "role": role, "content": text }));
+                }
+            }
+        }
+    }
+    messages
+}
+
+/// A Chat Completions tool call being assembled from stream deltas.
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Parse Chat Completions SSE chunks and forward them as [`ResponseEvent`]s.
+async fn process_chat_sse<S, B, E>(stream: S, tx_event: mpsc::Sender<Result<ResponseEvent>>)
+where
+    S: Stream<Item = std::result::Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut stream = stream.eventsource();
+    let mut assistant_text = String::new();
+    let mut token_usage = None;
+    // Tool calls are streamed in pieces, keyed by their index.
+    let mut tool_calls: BTreeMap<u64, PartialToolCall> = BTreeMap::new();
+
+    loop {
+        let sse = match stream.next().await {
+            Some(Ok(sse)) => sse,
+            Some(Err(e)) => {
+                let _ = tx_event.send(Err(CortyErr::Stream(e.to_string()))).await;
+                return;
+            }
+            None => {
+                let _ = tx_event
+                    .send(Err(CortyErr::Stream(
+                        "stream closed before [DONE]".to_string(),
+                    )))
+                    .await;
+                return;
+            }
+        };
+
+        if sse.data.trim() == "[DONE]" {
+            if !assistant_text.is_empty() {
+                let item = ResponseItem::assistant_message(std::mem::take(&mut assistant_text));
+                let _ = tx_event.send(Ok(ResponseEvent::OutputItemDone(item))).await;
+            }
+            for call in std::mem::take(&mut tool_calls).into_values() {
+                let item = ResponseItem::FunctionCall {
+                    name: call.name,
+                    arguments: call.arguments,
+                    call_id: call.id,
+                };
+                let _ = tx_event.send(Ok(ResponseEvent::OutputItemDone(item))).await;
+            }
+            let _ = tx_event
+                .send(Ok(ResponseEvent::Completed { token_usage }))
+                .await;
+            return;
+        }
+
+        let Ok(chunk) = serde_json::from_str::<Value>(&sse.data) else {
+            continue;
+        };
+
+        // With `include_usage` the last chunk before [DONE] carries the usage.
+        if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+            token_usage = Some(parse_chat_usage(usage));
+        }
+
+        if let Some(deltas) = chunk
+            .pointer("/choices/0/delta/tool_calls")
+            .and_then(Value::as_array)
+        {
+            for delta in deltas {
+                let index = delta["index"].as_u64().unwrap_or(0);
+                let call = tool_calls.entry(index).or_default();
+                if let Some(id) = delta["id"].as_str() {
+                    call.id.push_str(id);
+                }
+                if let Some(name) = delta.pointer("/function/name").and_then(Value::as_str) {
+                    call.name.push_str(name);
+                }
+                if let Some(arguments) =
+                    delta.pointer("/function/arguments").and_then(Value::as_str)
+                {
+                    call.arguments.push_str(arguments);
+                }
+            }
+        }
+
+        if let Some(delta) = chunk
+            .pointer("/choices/0/delta/content")
+            .and_then(Value::as_str)
+            .filter(|delta| !delta.is_empty())
+        {
+            assistant_text.push_str(delta);
+            if tx_event
+                .send(Ok(ResponseEvent::OutputTextDelta(delta.to_string())))
+                .await
+                .is_err()
+            {
+                // The turn is gone; stop reading.
+                return;
+            }
+        }
+    }
+}
+
+/// Parse Responses API SSE events and forward them as [`ResponseEvent`]s.
+async fn process_responses_sse<S, B, E>(stream: S, tx_event: mpsc::Sender<Result<ResponseEvent>>)
+where
+    S: Stream<Item = std::result::Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut stream = stream.eventsource();
+
+    loop {
+        let sse = match stream.next().await {
+            Some(Ok(sse)) => sse,
+            Some(Err(e)) => {
+                let _ = tx_event.send(Err(CortyErr::Stream(e.to_string()))).await;
+                return;
+            }
+            None => {
+                let _ = tx_event
+                    .send(Err(CortyErr::Stream(
+                        "stream closed before response.completed".to_string(),
+                    )))
+                    .await;
+                return;
+            }
+        };
+
+        let Ok(event) = serde_json::from_str::<Value>(&sse.data) else {
+            continue;
+        };
+
+        let response_event = match event["type"].as_str().unwrap_or_default() {
+            "response.output_text.delta" => match event["delta"].as_str() {
+                Some(delta) => ResponseEvent::OutputTextDelta(delta.to_string()),
+                None => continue,
+            },
+            "response.output_item.done" => {
+                // Items we do not model (e.g. reasoning) are not replayed.
+                match serde_json::from_value::<ResponseItem>(event["item"].clone()) {
+                    Ok(item) => ResponseEvent::OutputItemDone(item),
+                    Err(_) => continue,
+                }
+            }
+            "response.completed" => {
+                let token_usage = event
+                    .pointer("/response/usage")
+                    .filter(|usage| !usage.is_null())
+                    .map(parse_responses_usage);
+                let _ = tx_event
+                    .send(Ok(ResponseEvent::Completed { token_usage }))
+                    .await;
+                return;
+            }
+            "response.failed" | "error" => {
+                let message = event
+                    .pointer("/response/error/message")
+                    .or_else(|| event.get("message"))
+                    .and_then(Value::as_str)
+                    .unwrap_or("response.failed event received");
+                let _ = tx_event
+                    .send(Err(CortyErr::Stream(message.to_string())))
+                    .await;
+                return;
+            }
+            _ => continue,
+        };
+
+        if tx_event.send(Ok(response_event)).await.is_err() {
+            // The turn is gone; stop reading.
+            return;
+        }
+    }
+}
+
+fn u64_at(value: &Value, pointer: &str) -> u64 {
+    value.pointer(pointer).and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// Read a Chat Completions `usage` object.
+fn parse_chat_usage(usage: &Value) -> TokenUsage {
+    let input_tokens = u64_at(usage, "/prompt_tokens");
+    let output_tokens = u64_at(usage, "/completion_tokens");
+    TokenUsage {
+        input_tokens,
+        cached_input_tokens: u64_at(usage, "/prompt_tokens_details/cached_tokens"),
+        output_tokens,
+        reasoning_output_tokens: u64_at(usage, "/completion_tokens_details/reasoning_tokens"),
+        total_tokens: usage
+            .get("total_tokens")
+            .and_then(Value::as_u64)
+            .unwrap_or(input_tokens + output_tokens),
+    }
+}
+
+/// Read a Responses API `usage` object.
+fn parse_responses_usage(usage: &Value) -> TokenUsage {
+    let input_tokens = u64_at(usage, "/input_tokens");
+    let output_tokens = u64_at(usage, "/output_tokens");
+    TokenUsage {
+        input_tokens,
+        cached_input_tokens: u64_at(usage, "/input_tokens_details/cached_tokens"),
+        output_tokens,
+        reasoning_output_tokens: u64_at(usage, "/output_tokens_details/reasoning_tokens"),
+        total_tokens: usage
+            .get("total_tokens")
+            .and_then(Value::as_u64)
+            .unwrap_or(input_tokens + output_tokens),
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{
+    model_provider_info::{built_in_model_providers, DEFAULT_PROVIDER_ID},
+    protocol::{AskForApproval, ModelProviderInfo, SandboxPolicy},
+    storage::EmbeddingProvider,
+};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+/// Model used when neither the CLI nor `ConfigureSession` picks one.
+pub const DEFAULT_MODEL: &str = "gpt-4.1";
+
+/// Default for [`Config::history_max_bytes`].
+pub const DEFAULT_HISTORY_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Default for [`Config::auto_compact_token_limit`].
+pub const DEFAULT_AUTO_COMPACT_TOKEN_LIMIT: u64 = 100_000;
+
+/// Default for [`Config::max_tool_iterations`].
+pub const DEFAULT_MAX_TOOL_ITERATIONS: usize = 50;
+
+/// Default for [`Config::max_turn_duration`].
+pub const DEFAULT_MAX_TURN_DURATION: Duration = Duration::from_secs(30 * 60);
+
+/// Default for [`Config::max_repeated_tool_calls`].
+pub const DEFAULT_MAX_REPEATED_TOOL_CALLS: usize = 3;
+
+/// Base instructions sent with every model request.
+const BASE_INSTRUCTIONS: &str = include_str!("../prompt.md");
+
+/// Runtime configuration for a Corty engine
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Model used for the session.
+    pub model: String,
+
+    /// Provider the model is served by.
+    pub model_provider: ModelProviderInfo,
+
+    /// Providers `ConfigureSession` may select, keyed by id.
+    pub model_providers: HashMap<String, ModelProviderInfo>,
+
+    /// Working directory of the session. Tools resolve relative paths
+    /// against it and must not escape it.
+    pub cwd: PathBuf,
+
+    /// System instructions for the model.
+    pub instructions: String,
+
+    /// Directory sessions and the prompt history are kept in. `None`
+    /// disables both.
+    pub data_dir: Option<PathBuf>,
+
+    /// LanceDB database holding the semantic code index. `None` disables
+    /// the `semantic_search` tool.
+    pub index_dir: Option<PathBuf>,
+
+    /// How the code index embeds code and queries.
+    pub embedding: EmbeddingProvider,
+
+    /// Session file to continue instead of starting a new session.
+    pub resume_session: Option<PathBuf>,
+
+    /// Size the global prompt history file is kept under, in bytes.
+    pub history_max_bytes: u64,
+
+    /// Estimated history size, in tokens, past which older turns are
+    /// summarized before the next turn. `None` disables auto-compaction.
+    pub auto_compact_token_limit: Option<u64>,
+
+    /// Model requests a single turn may make before it is stopped.
+    pub max_tool_iterations: usize,
+
+    /// Wall-clock time a single turn may take before it is stopped.
+    pub max_turn_duration: Duration,
+
+    /// How often the model may make the same tool call, with identical
+    /// arguments, within one turn.
+    pub max_repeated_tool_calls: usize,
+
+    /// Restrictions for commands the agent runs.
+    pub sandbox_policy: SandboxPolicy,
+
+    /// When the user is asked before a command runs or a patch is applied.
+    pub approval_policy: AskForApproval,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let model_providers = built_in_model_providers();
+        let model_provider = model_providers[DEFAULT_PROVIDER_ID].clone();
+        Self {
+            model: DEFAULT_MODEL.to_string(),
+            model_provider,
+            model_providers,
+            cwd: std::env::current_dir().unwrap_or_default(),
+            instructions: BASE_INSTRUCTIONS.to_string(),
+            data_dir: default_data_dir(),
+            index_dir: default_data_dir().map(|dir| dir.join("index")),
+            embedding: EmbeddingProvider::default(),
+            resume_session: None,
+            history_max_bytes: DEFAULT_HISTORY_MAX_BYTES,
+            auto_compact_token_limit: Some(DEFAULT_AUTO_COMPACT_TOKEN_LIMIT),
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            max_turn_duration: DEFAULT_MAX_TURN_DURATION,
+            max_repeated_tool_calls: DEFAULT_MAX_REPEATED_TOOL_CALLS,
+            sandbox_policy: SandboxPolicy::default(),
+            approval_policy: AskForApproval::default(),
+        }
+    }
+}
+
+/// Platform data directory for Corty, e.g. `~/.local/share/corty` on Linux.
+pub fn default_data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("corty"))
+}
@@ -0,0 +1,151 @@
+//! Conversation items replayed to the model, with a token estimate per item.
+//!
+//! The estimates decide when the history needs compacting and stand in for
+//! usage the provider does not report. They use the `o200k_base` tokenizer,
+//! which is exact for recent OpenAI models and close enough for others.
+
+use crate::{
+    client::Prompt,
+    models::{ContentItem, ResponseItem},
+    protocol::TokenUsage,
+};
+use tiktoken_rs::o200k_base_singleton;
+
+/// Opens the summary item that replaces compacted turns.
+pub(crate) const SUMMARY_PREFIX: &str =
+    "Earlier turns of this conversation were compacted. Summary of what happened so far:\n\n";
+
+/// Flat estimate for an image, whatever its size.
+const IMAGE_TOKENS: u64 = 765;
+
+/// Fixed per-message overhead for role and framing.
+const MESSAGE_OVERHEAD_TOKENS: u64 = 4;
+
+/// Estimate the number of tokens `text` takes up.
+pub(crate) fn approx_token_count(text: &str) -> u64 {
+    o200k_base_singleton().encode_ordinary(text).len() as u64
+}
+
+/// Estimate the number of tokens `item` takes up in a prompt.
+pub(crate) fn approx_item_tokens(item: &ResponseItem) -> u64 {
+    match item {
+        ResponseItem::Message { content, .. } => {
+            MESSAGE_OVERHEAD_TOKENS
+                + content
+                    .iter()
+                    .map(|c| match c {
+                        ContentItem::InputText { text } | ContentItem::OutputText { text } => {
+                            approx_token_count(text)
+                        }
+                        ContentItem::InputImage { .. } => IMAGE_TOKENS,
+                    })
+                    .sum::<u64>()
+        }
+        ResponseItem::FunctionCall {
+            name, arguments, ..
+        } => MESSAGE_OVERHEAD_TOKENS + approx_token_count(name) + approx_token_count(arguments),
+        ResponseItem::FunctionCallOutput { output, .. } => {
+            MESSAGE_OVERHEAD_TOKENS + approx_token_count(output)
+        }
+    }
+}
+
+/// Estimate the usage of a response to `prompt` that produced `output`.
+pub(crate) fn estimate_token_usage(prompt: &Prompt, output: &str) -> TokenUsage {
+    let input_tokens = approx_token_count(&prompt.instructions)
+        + prompt.input.iter().map(approx_item_tokens).sum::<u64>();
+    let output_tokens = approx_token_count(output);
+    TokenUsage {
+        input_tokens,
+        output_tokens,
+        total_tokens: input_tokens + output_tokens,
+        ..TokenUsage::default()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HistoryItem {
+    item: ResponseItem,
+    tokens: u64,
+}
+
+/// Conversation history of a session, oldest item first
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConversationHistory {
+    items: Vec<HistoryItem>,
+}
+
+impl ConversationHistory {
+    pub(crate) fn new(items: Vec<ResponseItem>) -> Self {
+        let mut history = Self::default();
+        history.record_items(&items);
+        history
+    }
+
+    pub(crate) fn record_items(&mut self, items: &[ResponseItem]) {
+        self.items.extend(items.iter().map(|item| HistoryItem {
+            tokens: approx_item_tokens(item),
+            item: item.clone(),
+        }));
+    }
+
+    /// All items, in order.
+    pub(crate) fn contents(&self) -> Vec<ResponseItem> {
+        self.items.iter().map(|h| h.item.clone()).collect()
+    }
+
+    /// Estimated size of the whole history, in tokens.
+    pub(crate) fn estimated_tokens(&self) -> u64 {
+        self.items.iter().map(|h| h.tokens).sum()
+    }
+
+    /// Index of the first item of the `keep_turns`-th most recent user turn,
+    /// or of a later turn if only earlier summaries come before that one.
+    /// Everything before it may be summarized; `0` means there is nothing
+    /// to compact. Summaries do not count as turns.
+    pub(crate) fn compaction_boundary(&self, keep_turns: usize) -> usize {
+        if keep_turns == 0 {
+            return self.items.len();
+        }
+        let mut turn_starts: Vec<usize> = self
+            .items
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, h)| is_user_message(&h.item) && !is_summary(&h.item))
+            .map(|(index, _)| index)
+            .take(keep_turns)
+            .collect();
+        turn_starts.reverse();
+        // Summarizing nothing but a summary gains nothing.
+        turn_starts
+            .into_iter()
+            .find(|&start| self.items[..start].iter().any(|h| !is_summary(&h.item)))
+            .unwrap_or(0)
+    }
+
+    /// Replace the first `boundary` items with `summary`.
+    pub(crate) fn replace_prefix(&mut self, boundary: usize, summary: ResponseItem) {
+        let summary = HistoryItem {
+            tokens: approx_item_tokens(&summary),
+            item: summary,
+        };
+        self.items.splice(..boundary, [summary]);
+    }
+}
+
+fn is_user_message(item: &ResponseItem) -> bool {
+    matches!(item, ResponseItem::Message { role, .. } if role == "user")
+}
+
+/// Whether `item` is the summary of compacted turns, which is stored as a
+/// user message.
+fn is_summary(item: &ResponseItem) -> bool {
+    match item {
+        ResponseItem::Message { role, content } if role == "user" => matches!(
+            content.first(),
+            Some(ContentItem::InputText { text }) if text.starts_with(SUMMARY_PREFIX)
+        ),
+        _ => false,
+    }
+}
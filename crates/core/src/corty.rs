@@ -0,0 +1,1166 @@
+//! The Corty engine.
+//!
+//! [`Corty::spawn`] starts a background task that owns the session: model
+//! calls, conversation history and tools all live here. Frontends only
+//! talk to it through two channels: they send [`Submission`]s and receive
+//! [`Event`]s whose `id` is the id of the submission that caused them.
+
+use crate::{
+    client::{ModelClient, Prompt, ResponseEvent},
+    config::Config,
+    conversation_history::{estimate_token_usage, ConversationHistory, SUMMARY_PREFIX},
+    error::{CortyErr, Result},
+    message_history,
+    models::{ContentItem, ResponseItem},
+    protocol::{
+        AgentMessageDeltaEvent, AgentMessageEvent, ApplyPatchApprovalRequestEvent, AskForApproval,
+        ContextCompactedEvent, ErrorEvent, Event, EventMsg, ExecApprovalRequestEvent, FileChange,
+        HistoryEntry, HistoryEntryEvent, InputItem, ModelProviderInfo, Op, ProviderError,
+        ReviewDecision, SandboxPolicy, SessionConfiguredEvent, SubAgentEvent, Submission,
+        TaskCompleteEvent, TokenCountEvent, TokenUsage, ToolCallBeginEvent, ToolCallEndEvent,
+        TurnAbortReason, TurnAbortedEvent,
+    },
+    session::{load_session, RecordedItem, SessionMeta, SessionRecorder},
+    tools::{ToolContext, ToolRegistry},
+    user_input::resolve_input,
+};
+use chrono::Utc;
+use futures::StreamExt;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot,
+    },
+    task::AbortHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Number of submissions that can be queued before `send` waits.
+const SUBMISSION_CHANNEL_CAPACITY: usize = 64;
+
+/// Number of events that can be buffered before the engine waits on the frontend.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Appended to a partial assistant message when its turn is cut short.
+const INTERRUPTED_MARKER: &str = "\n\n[response interrupted]";
+
+/// Instructions for the model when it summarizes the history.
+const SUMMARIZATION_PROMPT: &str = include_str!("../compact_prompt.md");
+
+/// Most recent user turns that are never summarized.
+const COMPACT_KEEP_RECENT_TURNS: usize = 2;
+
+/// Instructions for sub-agents started by the `delegate` tool.
+const SUB_AGENT_INSTRUCTIONS: &str = include_str!("../sub_agent_prompt.md");
+
+/// Handle to the engine loop
+pub struct Corty {
+    session: Arc<Session>,
+}
+
+impl Corty {
+    /// Start the engine on the current tokio runtime.
+    ///
+    /// Returns the sender to submit operations on and the receiver events are
+    /// delivered on. The engine shuts down once every submission sender has
+    /// been dropped. Fails if the configured provider cannot be used or the
+    /// session file cannot be created or resumed.
+    ///
+    /// The configured provider is trusted as given and registered under its
+    /// id, so `ConfigureSession` can switch back to it.
+    pub fn spawn(mut config: Config) -> Result<(Sender<Submission>, Receiver<Event>)> {
+        config.model_providers.insert(
+            config.model_provider.id.clone(),
+            config.model_provider.clone(),
+        );
+        config.model_provider.validate(&config.model_providers)?;
+
+        let (tx_sub, rx_sub) = mpsc::channel(SUBMISSION_CHANNEL_CAPACITY);
+        let (tx_event, rx_event) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+
+        let corty = Corty {
+            session: Arc::new(Session::new(config, tx_event)?),
+        };
+        tokio::spawn(corty.submission_loop(rx_sub));
+
+        Ok((tx_sub, rx_event))
+    }
+
+    /// Process submissions in the order they arrive.
+    async fn submission_loop(self, mut rx_sub: Receiver<Submission>) {
+        while let Some(sub) = rx_sub.recv().await {
+            self.session.record(RecordedItem::Submission(sub.clone()));
+            match sub.op {
+                Op::ConfigureSession {
+                    provider,
+                    model,
+                    cwd,
+                } => {
+                    let msg = match self.session.configure(provider, model, cwd) {
+                        Ok(configured) => EventMsg::SessionConfigured(configured),
+                        Err(e) => EventMsg::ConfigureSessionError(e),
+                    };
+                    self.session.send_event(&sub.id, msg).await;
+                }
+                Op::Interrupt => self.interrupt().await,
+                Op::UserInput { items } => {
+                    self.session
+                        .enqueue_task(sub.id, TaskKind::UserInput(items));
+                }
+                Op::Compact => {
+                    self.session.enqueue_task(sub.id, TaskKind::Compact);
+                }
+                // History file access is awaited in place so that a lookup
+                // always sees the appends submitted before it.
+                Op::AddToHistory { text } => {
+                    let sess = Arc::clone(&self.session);
+                    let _ = tokio::task::spawn_blocking(move || sess.add_to_history(&text)).await;
+                }
+                Op::GetHistoryEntry { offset } => {
+                    let sess = Arc::clone(&self.session);
+                    let entry = tokio::task::spawn_blocking(move || sess.history_entry(offset))
+                        .await
+                        .unwrap_or_default();
+                    self.session
+                        .send_event(
+                            &sub.id,
+                            EventMsg::HistoryEntry(HistoryEntryEvent { offset, entry }),
+                        )
+                        .await;
+                }
+                Op::ExecApproval { id, decision } | Op::PatchApproval { id, decision } => {
+                    // Stop the task first so it cannot carry on with the
+                    // next tool call before it sees the interrupt.
+                    if decision == ReviewDecision::Abort {
+                        self.interrupt().await;
+                    }
+                    self.session.notify_approval(&id, decision);
+                }
+            }
+        }
+
+        self.session.abort_all();
+        log::debug!("submission channel closed; engine loop exiting");
+    }
+
+    /// Cancel the running task and report the queued inputs as aborted.
+    async fn interrupt(&self) {
+        for sub_id in self.session.interrupt() {
+            self.session
+                .send_event(
+                    &sub_id,
+                    EventMsg::TurnAborted(TurnAbortedEvent {
+                        reason: TurnAbortReason::Interrupted,
+                    }),
+                )
+                .await;
+        }
+    }
+}
+
+/// Per-turn snapshot of everything a task needs to talk to the model
+struct TurnContext {
+    client: ModelClient,
+    instructions: String,
+    /// Directory that relative paths are resolved against.
+    cwd: PathBuf,
+    tools: ToolRegistry,
+    max_tool_iterations: usize,
+    max_turn_duration: Duration,
+    max_repeated_tool_calls: usize,
+    sandbox_policy: SandboxPolicy,
+    approval_policy: AskForApproval,
+}
+
+impl TurnContext {
+    fn new(config: &Config) -> Self {
+        Self {
+            client: ModelClient::new(config),
+            instructions: config.instructions.clone(),
+            cwd: config.cwd.clone(),
+            tools: ToolRegistry::with_builtins(config),
+            max_tool_iterations: config.max_tool_iterations,
+            max_turn_duration: config.max_turn_duration,
+            max_repeated_tool_calls: config.max_repeated_tool_calls,
+            sandbox_policy: config.sandbox_policy.clone(),
+            approval_policy: config.approval_policy,
+        }
+    }
+
+    /// Context of a sub-agent: the same model, workspace and limits, with
+    /// `instructions` and only the tools `keep_tool` accepts.
+    ///
+    /// The tools are shared with this context rather than built again, so
+    /// the sub-agent reuses an index the session has already opened.
+    fn for_sub_agent(&self, instructions: &str, keep_tool: impl Fn(&str) -> bool) -> Self {
+        let mut tools = self.tools.clone();
+        tools.retain(keep_tool);
+        Self {
+            client: self.client.clone(),
+            instructions: instructions.to_string(),
+            cwd: self.cwd.clone(),
+            tools,
+            max_tool_iterations: self.max_tool_iterations,
+            max_turn_duration: self.max_turn_duration,
+            max_repeated_tool_calls: self.max_repeated_tool_calls,
+            sandbox_policy: self.sandbox_policy.clone(),
+            approval_policy: self.approval_policy,
+        }
+    }
+}
+
+/// A running user turn
+struct AgentTask {
+    sub_id: String,
+    handle: AbortHandle,
+    /// Cancelled by `Op::Interrupt`; the task winds down and reports it.
+    cancel: CancellationToken,
+}
+
+/// What a task does
+enum TaskKind {
+    /// Answer user input.
+    UserInput(Vec<InputItem>),
+    /// Summarize older turns (`Op::Compact`).
+    Compact,
+}
+
+/// Mutable session state, guarded by [`Session::state`]
+struct State {
+    config: Config,
+    turn_context: Arc<TurnContext>,
+    /// Conversation items replayed to the model on every turn.
+    history: ConversationHistory,
+    current_task: Option<AgentTask>,
+    /// Tasks submitted while another one was running.
+    pending_inputs: VecDeque<(String, TaskKind)>,
+    /// Usage of the running task.
+    turn_token_usage: TokenUsage,
+    /// Usage of the whole session.
+    session_token_usage: TokenUsage,
+    /// Approval requests waiting for an answer, by call id.
+    pending_approvals: HashMap<String, oneshot::Sender<ReviewDecision>>,
+    /// Commands the user approved for the rest of the session.
+    approved_commands: HashSet<Vec<String>>,
+    /// Files the user approved patches to for the rest of the session.
+    approved_patch_paths: HashSet<PathBuf>,
+}
+
+/// Session state shared between the engine loop and the running task
+pub(crate) struct Session {
+    id: String,
+    tx_event: Sender<Event>,
+    /// `None` when recording is disabled.
+    recorder: Option<SessionRecorder>,
+    state: Mutex<State>,
+}
+
+impl Session {
+    /// Start a new session, or continue `config.resume_session` with its
+    /// recorded history.
+    fn new(config: Config, tx_event: Sender<Event>) -> Result<Self> {
+        let turn_context = TurnContext::new(&config);
+        Self::with_turn_context(config, turn_context, tx_event)
+    }
+
+    /// Like [`Session::new`], with a ready-made `turn_context`.
+    fn with_turn_context(
+        config: Config,
+        turn_context: TurnContext,
+        tx_event: Sender<Event>,
+    ) -> Result<Self> {
+        let (id, history, token_usage, recorder) = match &config.resume_session {
+            Some(path) => {
+                let saved = load_session(path)?;
+                let recorder = SessionRecorder::resume(path)?;
+                (
+                    saved.meta.id,
+                    saved.items,
+                    saved.token_usage,
+                    Some(recorder),
+                )
+            }
+            None => {
+                let meta = SessionMeta {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    timestamp: Utc::now(),
+                    cwd: config.cwd.clone(),
+                    model: config.model.clone(),
+                    provider_id: config.model_provider.id.clone(),
+                };
+                let recorder = match &config.data_dir {
+                    Some(data_dir) => Some(SessionRecorder::create(data_dir, meta.clone())?),
+                    None => None,
+                };
+                (meta.id, Vec::new(), TokenUsage::default(), recorder)
+            }
+        };
+
+        let turn_context = Arc::new(turn_context);
+        Ok(Self {
+            id,
+            tx_event,
+            recorder,
+            state: Mutex::new(State {
+                config,
+                turn_context,
+                history: ConversationHistory::new(history),
+                current_task: None,
+                pending_inputs: VecDeque::new(),
+                turn_token_usage: TokenUsage::default(),
+                session_token_usage: token_usage,
+                pending_approvals: HashMap::new(),
+                approved_commands: HashSet::new(),
+                approved_patch_paths: HashSet::new(),
+            }),
+        })
+    }
+
+    /// Switch provider, model and working directory. A running task keeps
+    /// the settings it started with; on error nothing changes.
+    fn configure(
+        &self,
+        provider: ModelProviderInfo,
+        model: String,
+        cwd: PathBuf,
+    ) -> std::result::Result<SessionConfiguredEvent, ProviderError> {
+        let mut state = self.state.lock().unwrap();
+        provider.validate(&state.config.model_providers)?;
+
+        state.config.model_provider = provider;
+        if !model.is_empty() {
+            state.config.model = model;
+        }
+        state.config.cwd = cwd;
+        state.turn_context = Arc::new(TurnContext::new(&state.config));
+
+        let history_entry_count = match &state.config.data_dir {
+            Some(data_dir) => message_history::entry_count(data_dir).unwrap_or_else(|e| {
+                log::warn!("failed to read prompt history: {e}");
+                0
+            }),
+            None => 0,
+        };
+
+        Ok(SessionConfiguredEvent {
+            session_id: self.id.clone(),
+            model: state.config.model.clone(),
+            provider_id: state.config.model_provider.id.clone(),
+            cwd: state.config.cwd.clone(),
+            history_entry_count,
+        })
+    }
+
+    /// Append `text` to the global prompt history. Blocks on the file lock.
+    fn add_to_history(&self, text: &str) {
+        let (data_dir, max_bytes) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.config.data_dir.clone(),
+                state.config.history_max_bytes,
+            )
+        };
+        let Some(data_dir) = data_dir else {
+            return;
+        };
+        if let Err(e) = message_history::append_entry(&data_dir, &self.id, text, max_bytes) {
+            log::warn!("failed to append to prompt history: {e}");
+        }
+    }
+
+    /// Entry `offset` of the global prompt history. Blocks on the file lock.
+    fn history_entry(&self, offset: usize) -> Option<HistoryEntry> {
+        let data_dir = self.state.lock().unwrap().config.data_dir.clone()?;
+        message_history::lookup(&data_dir, offset).unwrap_or_else(|e| {
+            log::warn!("failed to read prompt history: {e}");
+            None
+        })
+    }
+
+    /// Start a task, or queue it behind the running one.
+    fn enqueue_task(self: &Arc<Self>, sub_id: String, kind: TaskKind) {
+        let mut state = self.state.lock().unwrap();
+        if state.current_task.is_some() {
+            state.pending_inputs.push_back((sub_id, kind));
+        } else {
+            self.start_task(&mut state, sub_id, kind);
+        }
+    }
+
+    fn start_task(self: &Arc<Self>, state: &mut State, sub_id: String, kind: TaskKind) {
+        let cancel = CancellationToken::new();
+        let handle = tokio::spawn(run_task(
+            Arc::clone(self),
+            sub_id.clone(),
+            kind,
+            cancel.clone(),
+        ))
+        .abort_handle();
+        state.turn_token_usage = TokenUsage::default();
+        state.current_task = Some(AgentTask {
+            sub_id,
+            handle,
+            cancel,
+        });
+    }
+
+    /// Cancel the running task and drop queued inputs. Returns the ids of
+    /// the dropped inputs; the running task reports its own abort.
+    fn interrupt(&self) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(task) = &state.current_task {
+            task.cancel.cancel();
+        }
+        state
+            .pending_inputs
+            .drain(..)
+            .map(|(sub_id, _)| sub_id)
+            .collect()
+    }
+
+    /// Called by a task when it is done; starts the next queued input.
+    fn task_finished(self: &Arc<Self>, sub_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .current_task
+            .as_ref()
+            .is_some_and(|task| task.sub_id == sub_id)
+        {
+            state.current_task = None;
+        }
+        if state.current_task.is_none() {
+            if let Some((next_id, kind)) = state.pending_inputs.pop_front() {
+                self.start_task(&mut state, next_id, kind);
+            }
+        }
+    }
+
+    fn abort_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending_inputs.clear();
+        if let Some(task) = state.current_task.take() {
+            task.handle.abort();
+        }
+    }
+
+    fn turn_context(&self) -> Arc<TurnContext> {
+        Arc::clone(&self.state.lock().unwrap().turn_context)
+    }
+
+    fn record_conversation_items(&self, items: &[ResponseItem]) {
+        self.state.lock().unwrap().history.record_items(items);
+        for item in items {
+            self.record(RecordedItem::ResponseItem(item.clone()));
+        }
+    }
+
+    fn record(&self, item: RecordedItem) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(item);
+        }
+    }
+
+    fn history_snapshot(&self) -> Vec<ResponseItem> {
+        self.state.lock().unwrap().history.contents()
+    }
+
+    fn estimated_history_tokens(&self) -> u64 {
+        self.state.lock().unwrap().history.estimated_tokens()
+    }
+
+    /// Add the usage of one model response to the turn and session totals.
+    fn add_token_usage(&self, usage: &TokenUsage, estimated: bool) -> TokenCountEvent {
+        let mut state = self.state.lock().unwrap();
+        state.turn_token_usage += usage;
+        state.session_token_usage += usage;
+        TokenCountEvent {
+            turn: state.turn_token_usage.clone(),
+            session: state.session_token_usage.clone(),
+            estimated,
+        }
+    }
+
+    /// Account for a finished model response and emit `TokenCount`. Usage
+    /// the provider did not report is estimated from `prompt` and `output`.
+    async fn report_token_usage(
+        &self,
+        sub_id: &str,
+        reported: Option<TokenUsage>,
+        prompt: &Prompt,
+        output: &str,
+    ) {
+        let estimated = reported.is_none();
+        let usage = reported.unwrap_or_else(|| estimate_token_usage(prompt, output));
+        let count = self.add_token_usage(&usage, estimated);
+        self.send_event(sub_id, EventMsg::TokenCount(count)).await;
+    }
+
+    fn auto_compact_token_limit(&self) -> Option<u64> {
+        self.state.lock().unwrap().config.auto_compact_token_limit
+    }
+
+    /// Items that compaction would summarize, keeping the most recent
+    /// `keep_turns` user turns. Empty if there is nothing to compact.
+    fn compaction_candidates(&self, keep_turns: usize) -> Vec<ResponseItem> {
+        let state = self.state.lock().unwrap();
+        let boundary = state.history.compaction_boundary(keep_turns);
+        state.history.contents()[..boundary].to_vec()
+    }
+
+    /// Replace the first `count` history items with `summary` and return the
+    /// new token estimate.
+    fn apply_compaction(&self, count: usize, summary: ResponseItem) -> u64 {
+        let (contents, tokens) = {
+            let mut state = self.state.lock().unwrap();
+            state.history.replace_prefix(count, summary);
+            (state.history.contents(), state.history.estimated_tokens())
+        };
+        self.record(RecordedItem::Compacted(contents));
+        tokens
+    }
+
+    /// Ask the user whether `command` may run and wait for the answer.
+    ///
+    /// Commands approved for the session are approved without asking. If
+    /// `cancel` fires first, the answer is `Abort`.
+    pub(crate) async fn request_command_approval(
+        &self,
+        sub_id: &str,
+        call_id: &str,
+        command: Vec<String>,
+        cwd: PathBuf,
+        reason: Option<String>,
+        cancel: &CancellationToken,
+    ) -> ReviewDecision {
+        let approved = self
+            .state
+            .lock()
+            .unwrap()
+            .approved_commands
+            .contains(&command);
+        let decision = if approved {
+            ReviewDecision::ApprovedForSession
+        } else {
+            let request = EventMsg::ExecApprovalRequest(ExecApprovalRequestEvent {
+                call_id: call_id.to_string(),
+                command: command.clone(),
+                cwd,
+                reason,
+            });
+            self.wait_for_approval(sub_id, call_id, request, cancel)
+                .await
+        };
+        if decision == ReviewDecision::ApprovedForSession {
+            self.state.lock().unwrap().approved_commands.insert(command);
+        }
+        self.record_decision(call_id, decision)
+    }
+
+    /// Ask the user whether a patch making `changes` may be applied and wait
+    /// for the answer.
+    ///
+    /// Patches that only touch files approved for the session are approved
+    /// without asking. If `cancel` fires first, the answer is `Abort`.
+    pub(crate) async fn request_patch_approval(
+        &self,
+        sub_id: &str,
+        call_id: &str,
+        changes: HashMap<PathBuf, FileChange>,
+        reason: Option<String>,
+        cancel: &CancellationToken,
+    ) -> ReviewDecision {
+        let paths: Vec<PathBuf> = changes
+            .iter()
+            .flat_map(|(path, change)| {
+                let move_path = match change {
+                    FileChange::Update { move_path, .. } => move_path.clone(),
+                    _ => None,
+                };
+                std::iter::once(path.clone()).chain(move_path)
+            })
+            .collect();
+        let approved = {
+            let state = self.state.lock().unwrap();
+            paths
+                .iter()
+                .all(|path| state.approved_patch_paths.contains(path))
+        };
+        let decision = if approved {
+            ReviewDecision::ApprovedForSession
+        } else {
+            let request = EventMsg::ApplyPatchApprovalRequest(ApplyPatchApprovalRequestEvent {
+                call_id: call_id.to_string(),
+                changes,
+                reason,
+            });
+            self.wait_for_approval(sub_id, call_id, request, cancel)
+                .await
+        };
+        if decision == ReviewDecision::ApprovedForSession {
+            self.state
+                .lock()
+                .unwrap()
+                .approved_patch_paths
+                .extend(paths);
+        }
+        self.record_decision(call_id, decision)
+    }
+
+    async fn wait_for_approval(
+        &self,
+        sub_id: &str,
+        call_id: &str,
+        request: EventMsg,
+        cancel: &CancellationToken,
+    ) -> ReviewDecision {
+        let (tx, rx) = oneshot::channel();
+        self.state
+            .lock()
+            .unwrap()
+            .pending_approvals
+            .insert(call_id.to_string(), tx);
+        self.send_event(sub_id, request).await;
+        let decision = tokio::select! {
+            decision = rx => decision.unwrap_or(ReviewDecision::Abort),
+            _ = cancel.cancelled() => ReviewDecision::Abort,
+        };
+        self.state.lock().unwrap().pending_approvals.remove(call_id);
+        decision
+    }
+
+    fn record_decision(&self, call_id: &str, decision: ReviewDecision) -> ReviewDecision {
+        self.record(RecordedItem::Approval {
+            call_id: call_id.to_string(),
+            decision,
+        });
+        decision
+    }
+
+    /// Deliver the answer to the approval request for `call_id`.
+    fn notify_approval(&self, call_id: &str, decision: ReviewDecision) {
+        match self.state.lock().unwrap().pending_approvals.remove(call_id) {
+            Some(tx) => {
+                let _ = tx.send(decision);
+            }
+            // E.g. the task was interrupted while the user was deciding.
+            None => log::debug!("no pending approval for call {call_id}"),
+        }
+    }
+
+    pub(crate) async fn send_event(&self, sub_id: &str, msg: EventMsg) {
+        let event = Event {
+            id: sub_id.to_string(),
+            msg,
+        };
+        self.record(RecordedItem::Event(event.clone()));
+        if let Err(e) = self.tx_event.send(event).await {
+            log::error!("failed to send event: {e}");
+        }
+    }
+}
+
+/// Run `task` in a sub-agent with a fresh history and only the tools
+/// `keep_tool` accepts, and return its final message.
+///
+/// The sub-agent's events are forwarded as `SubAgent` events nested under
+/// `call_id`, except streaming deltas. Its token usage is added to the
+/// session's once it is done.
+///
+/// `cancel` is the parent turn's, so the sub-agent stops when the parent is
+/// interrupted or runs out of time: it has no deadline beyond the parent's.
+pub(crate) async fn run_sub_agent(
+    sess: &Session,
+    sub_id: &str,
+    call_id: &str,
+    task: String,
+    keep_tool: impl Fn(&str) -> bool,
+    cancel: &CancellationToken,
+) -> Result<Option<String>> {
+    let config = Config {
+        instructions: SUB_AGENT_INSTRUCTIONS.to_string(),
+        data_dir: None,
+        resume_session: None,
+        ..sess.state.lock().unwrap().config.clone()
+    };
+    let turn_context = sess
+        .turn_context()
+        .for_sub_agent(SUB_AGENT_INSTRUCTIONS, keep_tool);
+    let (tx_event, mut rx_event) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+    let child = Session::with_turn_context(config, turn_context, tx_event)?;
+    let turn_context = child.turn_context();
+    child.record_conversation_items(&[ResponseItem::Message {
+        role: "user".to_string(),
+        content: vec![ContentItem::InputText { text: task }],
+    }]);
+
+    // The child is dropped when it is done, which ends the forwarding.
+    let run = async move {
+        let result = run_tool_loop(&child, &turn_context, sub_id, cancel).await;
+        let usage = child.state.lock().unwrap().session_token_usage.clone();
+        (result, usage)
+    };
+    let forward = async {
+        let mut estimated = false;
+        while let Some(event) = rx_event.recv().await {
+            match event.msg {
+                EventMsg::AgentMessageDelta(_) | EventMsg::ExecCommandOutputDelta(_) => {}
+                EventMsg::TokenCount(count) => estimated |= count.estimated,
+                msg => {
+                    sess.send_event(
+                        sub_id,
+                        EventMsg::SubAgent(SubAgentEvent {
+                            call_id: call_id.to_string(),
+                            msg: Box::new(msg),
+                        }),
+                    )
+                    .await
+                }
+            }
+        }
+        estimated
+    };
+    let ((result, usage), estimated) = tokio::join!(run, forward);
+
+    let count = sess.add_token_usage(&usage, estimated);
+    sess.send_event(sub_id, EventMsg::TokenCount(count)).await;
+    result
+}
+
+/// Run one task from `TaskStarted` to `TaskComplete` (or `TurnAborted`).
+async fn run_task(sess: Arc<Session>, sub_id: String, kind: TaskKind, cancel: CancellationToken) {
+    sess.send_event(&sub_id, EventMsg::TaskStarted).await;
+
+    let turn_context = sess.turn_context();
+    let result = match kind {
+        TaskKind::UserInput(input) => {
+            run_user_turn(&sess, &turn_context, &sub_id, input, &cancel).await
+        }
+        TaskKind::Compact => match compact(&sess, &turn_context, &sub_id, &cancel).await {
+            Ok(compacted) => {
+                sess.send_event(&sub_id, EventMsg::ContextCompacted(compacted))
+                    .await;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        },
+    };
+    let last_agent_message = match result {
+        Ok(message) => message,
+        Err(e @ (CortyErr::Interrupted | CortyErr::TurnLimit(_))) => {
+            let reason = match e {
+                CortyErr::TurnLimit(reason) => reason,
+                _ => TurnAbortReason::Interrupted,
+            };
+            sess.send_event(&sub_id, EventMsg::TurnAborted(TurnAbortedEvent { reason }))
+                .await;
+            sess.task_finished(&sub_id);
+            return;
+        }
+        Err(e) => {
+            sess.send_event(
+                &sub_id,
+                EventMsg::Error(ErrorEvent {
+                    message: e.to_string(),
+                }),
+            )
+            .await;
+            None
+        }
+    };
+
+    sess.send_event(
+        &sub_id,
+        EventMsg::TaskComplete(TaskCompleteEvent { last_agent_message }),
+    )
+    .await;
+    sess.task_finished(&sub_id);
+}
+
+/// Record the user input, then let the model answer.
+async fn run_user_turn(
+    sess: &Session,
+    turn_context: &TurnContext,
+    sub_id: &str,
+    input: Vec<InputItem>,
+    cancel: &CancellationToken,
+) -> Result<Option<String>> {
+    // Unreadable attachments fail the turn before anything is sent.
+    let item = resolve_input(input, &turn_context.cwd).await?;
+    sess.record_conversation_items(&[item]);
+    run_tool_loop(sess, turn_context, sub_id, cancel).await
+}
+
+/// Alternate model responses and tool calls until the model answers without
+/// calling a tool, or a limit is hit.
+///
+/// `cancel` is the user's; the loop stops on its own once
+/// `max_turn_duration` has passed.
+async fn run_tool_loop(
+    sess: &Session,
+    turn_context: &TurnContext,
+    sub_id: &str,
+    cancel: &CancellationToken,
+) -> Result<Option<String>> {
+    // Cancelled by the user, or by the timer once the turn is out of time.
+    let turn_cancel = cancel.child_token();
+    let timer = tokio::spawn({
+        let turn_cancel = turn_cancel.clone();
+        let max_turn_duration = turn_context.max_turn_duration;
+        async move {
+            tokio::time::sleep(max_turn_duration).await;
+            turn_cancel.cancel();
+        }
+    });
+    let result = run_tool_calls(sess, turn_context, sub_id, cancel, &turn_cancel).await;
+    timer.abort();
+
+    match result {
+        Err(CortyErr::Interrupted) if !cancel.is_cancelled() => {
+            Err(CortyErr::TurnLimit(TurnAbortReason::Timeout))
+        }
+        result => result,
+    }
+}
+
+/// The body of [`run_tool_loop`], stopped by `turn_cancel`.
+async fn run_tool_calls(
+    sess: &Session,
+    turn_context: &TurnContext,
+    sub_id: &str,
+    cancel: &CancellationToken,
+    turn_cancel: &CancellationToken,
+) -> Result<Option<String>> {
+    let mut call_counts: HashMap<(String, String), usize> = HashMap::new();
+
+    for _ in 0..turn_context.max_tool_iterations {
+        // Tool output can push the history past the limit within a turn.
+        if let Some(limit) = sess.auto_compact_token_limit() {
+            if sess.estimated_history_tokens() >= limit {
+                let compacted = compact(sess, turn_context, sub_id, turn_cancel).await?;
+                // Only the latest turns may be left; that is not worth a report.
+                if compacted.items_summarized > 0 {
+                    sess.send_event(sub_id, EventMsg::ContextCompacted(compacted))
+                        .await;
+                }
+            }
+        }
+        let output = run_turn(sess, turn_context, sub_id, cancel, turn_cancel).await?;
+        if output.function_calls.is_empty() {
+            return Ok(output.last_agent_message);
+        }
+
+        let mut calls = output.function_calls.into_iter();
+        while let Some(call) = calls.next() {
+            let count = call_counts
+                .entry((call.name.clone(), call.arguments.clone()))
+                .or_default();
+            *count += 1;
+            if *count > turn_context.max_repeated_tool_calls {
+                let reason = TurnAbortReason::RepeatedToolCall;
+                record_calls_not_run(sess, std::iter::once(call).chain(calls), reason.clone());
+                return Err(CortyErr::TurnLimit(reason));
+            }
+
+            let output =
+                handle_function_call(sess, turn_context, sub_id, call, cancel, turn_cancel).await;
+            sess.record_conversation_items(&[output]);
+            if turn_cancel.is_cancelled() {
+                record_calls_not_run(sess, calls, abort_reason(cancel));
+                return Err(CortyErr::Interrupted);
+            }
+        }
+    }
+
+    Err(CortyErr::TurnLimit(TurnAbortReason::MaxIterations))
+}
+
+/// Summarize all but the most recent turns with the model and replace them
+/// with the summary.
+async fn compact(
+    sess: &Session,
+    turn_context: &TurnContext,
+    sub_id: &str,
+    cancel: &CancellationToken,
+) -> Result<ContextCompactedEvent> {
+    let tokens_before = sess.estimated_history_tokens();
+    let mut input = sess.compaction_candidates(COMPACT_KEEP_RECENT_TURNS);
+    let items_summarized = input.len();
+
+    let tokens_after = if input.is_empty() {
+        tokens_before
+    } else {
+        input.push(ResponseItem::Message {
+            role: "user".to_string(),
+            content: vec![ContentItem::InputText {
+                text: "Summarize the conversation so far as instructed.".to_string(),
+            }],
+        });
+        let prompt = Prompt {
+            instructions: SUMMARIZATION_PROMPT.to_string(),
+            input,
+            tools: Vec::new(),
+        };
+        let (summary, token_usage) = collect_response_text(turn_context, &prompt, cancel).await?;
+        sess.report_token_usage(sub_id, token_usage, &prompt, &summary)
+            .await;
+        sess.apply_compaction(
+            items_summarized,
+            ResponseItem::Message {
+                role: "user".to_string(),
+                content: vec![ContentItem::InputText {
+                    text: format!("{}{}", SUMMARY_PREFIX, summary),
+                }],
+            },
+        )
+    };
+
+    Ok(ContextCompactedEvent {
+        tokens_before,
+        tokens_after,
+        items_summarized,
+    })
+}
+
+/// Send `prompt` and return the full assistant text and the reported usage,
+/// without emitting any events or touching the history.
+async fn collect_response_text(
+    turn_context: &TurnContext,
+    prompt: &Prompt,
+    cancel: &CancellationToken,
+) -> Result<(String, Option<TokenUsage>)> {
+    let mut stream = tokio::select! {
+        stream = turn_context.client.stream(prompt) => stream?,
+        _ = cancel.cancelled() => return Err(CortyErr::Interrupted),
+    };
+    let mut text = String::new();
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => event,
+            _ = cancel.cancelled() => return Err(CortyErr::Interrupted),
+        };
+        match event {
+            Some(Ok(ResponseEvent::OutputTextDelta(delta))) => text.push_str(&delta),
+            Some(Ok(ResponseEvent::OutputItemDone(item))) => {
+                if let Some(message) = item.assistant_text() {
+                    text = message;
+                }
+            }
+            Some(Ok(ResponseEvent::Completed { token_usage })) => return Ok((text, token_usage)),
+            Some(Err(e)) => return Err(e),
+            None => {
+                return Err(CortyErr::Stream(
+                    "stream closed before the response completed".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+/// Tool call requested by the model, not yet answered.
+struct PendingCall {
+    call_id: String,
+    name: String,
+    arguments: String,
+}
+
+/// What a single model response asked for
+struct TurnOutput {
+    /// Last assistant message of the response, if any.
+    last_agent_message: Option<String>,
+    /// Tool calls to run before the model can continue.
+    function_calls: Vec<PendingCall>,
+}
+
+/// Stream one model response into the history.
+///
+/// If `turn_cancel` fires, the in-flight request is dropped, any partial
+/// assistant text is recorded with [`INTERRUPTED_MARKER`] and
+/// `CortyErr::Interrupted` is returned. `cancel` tells whether the user
+/// stopped the turn.
+async fn run_turn(
+    sess: &Session,
+    turn_context: &TurnContext,
+    sub_id: &str,
+    cancel: &CancellationToken,
+    turn_cancel: &CancellationToken,
+) -> Result<TurnOutput> {
+    let prompt = Prompt {
+        instructions: turn_context.instructions.clone(),
+        input: sess.history_snapshot(),
+        tools: turn_context.tools.specs(),
+    };
+
+    let mut stream = tokio::select! {
+        stream = turn_context.client.stream(&prompt) => stream?,
+        _ = turn_cancel.cancelled() => return Err(CortyErr::Interrupted),
+    };
+    let mut output = TurnOutput {
+        last_agent_message: None,
+        function_calls: Vec::new(),
+    };
+    // Assistant text streamed so far that is not yet part of a done item.
+    let mut partial_message = String::new();
+    // All model output of this response, for estimating its usage.
+    let mut response_text = String::new();
+
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => event,
+            _ = turn_cancel.cancelled() => {
+                drop(stream);
+                if !partial_message.is_empty() {
+                    let message = format!("{}{}", partial_message, INTERRUPTED_MARKER);
+                    sess.record_conversation_items(&[ResponseItem::assistant_message(
+                        message.clone(),
+                    )]);
+                    sess.send_event(
+                        sub_id,
+                        EventMsg::AgentMessage(AgentMessageEvent { message }),
+                    )
+                    .await;
+                }
+                record_calls_not_run(sess, output.function_calls, abort_reason(cancel));
+                return Err(CortyErr::Interrupted);
+            }
+        };
+        let Some(event) = event else {
+            break;
+        };
+
+        match event? {
+            ResponseEvent::OutputTextDelta(delta) => {
+                partial_message.push_str(&delta);
+                sess.send_event(
+                    sub_id,
+                    EventMsg::AgentMessageDelta(AgentMessageDeltaEvent { delta }),
+                )
+                .await;
+            }
+            ResponseEvent::OutputItemDone(item) => {
+                match &item {
+                    ResponseItem::FunctionCall {
+                        name,
+                        arguments,
+                        call_id,
+                    } => {
+                        response_text.push_str(arguments);
+                        output.function_calls.push(PendingCall {
+                            call_id: call_id.clone(),
+                            name: name.clone(),
+                            arguments: arguments.clone(),
+                        });
+                    }
+                    _ => {
+                        if let Some(message) = item.assistant_text() {
+                            partial_message.clear();
+                            sess.send_event(
+                                sub_id,
+                                EventMsg::AgentMessage(AgentMessageEvent {
+                                    message: message.clone(),
+                                }),
+                            )
+                            .await;
+                            response_text.push_str(&message);
+                            output.last_agent_message = Some(message);
+                        }
+                    }
+                }
+                sess.record_conversation_items(&[item]);
+            }
+            ResponseEvent::Completed { token_usage } => {
+                sess.report_token_usage(sub_id, token_usage, &prompt, &response_text)
+                    .await;
+                return Ok(output);
+            }
+        }
+    }
+
+    Err(CortyErr::Stream(
+        "stream closed before the response completed".to_string(),
+    ))
+}
+
+/// Answer `calls` with a note that they were not run. Every function call in
+/// the history needs an output, or the next request is rejected.
+fn record_calls_not_run(
+    sess: &Session,
+    calls: impl IntoIterator<Item = PendingCall>,
+    reason: TurnAbortReason,
+) {
+    let outputs: Vec<ResponseItem> = calls
+        .into_iter()
+        .map(|call| ResponseItem::FunctionCallOutput {
+            call_id: call.call_id,
+            output: format!("tool call not run: turn stopped ({reason})"),
+        })
+        .collect();
+    sess.record_conversation_items(&outputs);
+}
+
+/// Why a turn stopped by its `turn_cancel` token was stopped, given the
+/// user's `cancel` token.
+fn abort_reason(cancel: &CancellationToken) -> TurnAbortReason {
+    if cancel.is_cancelled() {
+        TurnAbortReason::Interrupted
+    } else {
+        TurnAbortReason::Timeout
+    }
+}
+
+/// Run a tool call, reporting it with `ToolCallBegin` and `ToolCallEnd`, and
+/// return the output item for the history.
+async fn handle_function_call(
+    sess: &Session,
+    turn_context: &TurnContext,
+    sub_id: &str,
+    call: PendingCall,
+    cancel: &CancellationToken,
+    turn_cancel: &CancellationToken,
+) -> ResponseItem {
+    let arguments = serde_json::from_str(&call.arguments)
+        .unwrap_or_else(|_| serde_json::Value::String(call.arguments.clone()));
+    sess.send_event(
+        sub_id,
+        EventMsg::ToolCallBegin(ToolCallBeginEvent {
+            call_id: call.call_id.clone(),
+            tool: call.name.clone(),
+            arguments,
+        }),
+    )
+    .await;
+
+    let ctx = ToolContext {
+        sess,
+        sub_id,
+        call_id: &call.call_id,
+        cwd: &turn_context.cwd,
+        sandbox_policy: &turn_context.sandbox_policy,
+        approval_policy: turn_context.approval_policy,
+        cancel: turn_cancel,
+        interrupt: cancel,
+    };
+    let output = turn_context
+        .tools
+        .dispatch(&ctx, &call.name, &call.arguments)
+        .await;
+
+    sess.send_event(
+        sub_id,
+        EventMsg::ToolCallEnd(ToolCallEndEvent {
+            call_id: call.call_id.clone(),
+            success: output.success,
+            output: output.content.clone(),
+        }),
+    )
+    .await;
+    ResponseItem::FunctionCallOutput {
+        call_id: call.call_id,
+        output: output.content,
+    }
+}
@@ -0,0 +1,46 @@
+use crate::protocol::{ProviderError, TurnAbortReason};
+use reqwest::StatusCode;
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, CortyErr>;
+
+#[derive(Error, Debug)]
+pub enum CortyErr {
+    /// The engine loop has exited and can no longer accept submissions.
+    #[error("internal error; agent loop died unexpectedly")]
+    InternalAgentDied,
+
+    /// The turn was cancelled by `Op::Interrupt`.
+    #[error("turn interrupted")]
+    Interrupted,
+
+    /// The turn hit one of the configured limits.
+    #[error("turn stopped: {0}")]
+    TurnLimit(TurnAbortReason),
+
+    /// The model stream ended before a completed response was received.
+    #[error("stream disconnected before completion: {0}")]
+    Stream(String),
+
+    /// The provider answered with a non-success HTTP status.
+    #[error("unexpected status {0}: {1}")]
+    UnexpectedStatus(StatusCode, String),
+
+    /// A file or image referenced by an `InputItem` could not be attached.
+    #[error("cannot attach {}: {reason}", path.display())]
+    InvalidInput { path: PathBuf, reason: String },
+
+    /// The configured model provider cannot be used.
+    #[error(transparent)]
+    Provider(#[from] ProviderError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
@@ -0,0 +1,111 @@
+use super::syntax::{self, Span};
+use crate::storage::CodeChunk;
+use std::path::Path;
+
+/// Lines per chunk of files chunked by line.
+const WINDOW_LINES: usize = 60;
+
+/// Lines each chunk shares with the one before it, so code around a
+/// boundary is whole in at least one chunk.
+const OVERLAP_LINES: usize = 10;
+
+/// Lowercase language name of the file at `path`, from its extension.
+pub fn language_for(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "ts" | "tsx" | "mts" | "cts" => "typescript",
+        "js" | "jsx" | "mjs" | "cjs" => "javascript",
+        "go" => "go",
+        "java" => "java",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "sh" | "bash" | "zsh" => "shell",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" | "scss" => "css",
+        "md" | "markdown" => "markdown",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "json" => "json",
+        _ => "text",
+    }
+}
+
+/// Chunks of the file at `path` (relative to the project root) holding
+/// `text`.
+///
+/// Languages tree-sitter can parse are cut at item boundaries, and chunks
+/// of nested items start with the signatures of the items around them;
+/// other files are cut into overlapping line windows.
+pub fn chunk_file(path: &str, language: &str, text: &str) -> Vec<CodeChunk> {
+    match syntax::spans(language, text) {
+        Some(spans) => syntax_chunks(path, language, text, spans),
+        None => line_windows(path, language, text),
+    }
+}
+
+fn syntax_chunks(path: &str, language: &str, text: &str, spans: Vec<Span>) -> Vec<CodeChunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    spans
+        .into_iter()
+        .filter(|span| span.start <= span.end && span.end < lines.len())
+        .filter(|span| {
+            lines[span.start..=span.end]
+                .iter()
+                .any(|line| !line.trim().is_empty())
+        })
+        .map(|span| {
+            let mut content = span.context.join("\n");
+            if !content.is_empty() {
+                content.push('\n');
+            }
+            content.push_str(&lines[span.start..=span.end].join("\n"));
+            CodeChunk::new(
+                path,
+                language,
+                span.start as u32 + 1,
+                span.end as u32 + 1,
+                content,
+                span.symbol,
+            )
+        })
+        .collect()
+}
+
+/// Overlapping windows of [`WINDOW_LINES`] lines; blank windows are
+/// dropped.
+fn line_windows(path: &str, language: &str, text: &str) -> Vec<CodeChunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + WINDOW_LINES).min(lines.len());
+        let window = &lines[start..end];
+        if window.iter().any(|line| !line.trim().is_empty()) {
+            chunks.push(CodeChunk::new(
+                path,
+                language,
+                start as u32 + 1,
+                end as u32,
+                window.join("\n"),
+                None,
+            ));
+        }
+        if end == lines.len() {
+            break;
+        }
+        start = end - OVERLAP_LINES;
+    }
+    chunks
+}
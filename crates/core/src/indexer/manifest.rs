@@ -0,0 +1,89 @@
+//! What the index holds for each file, so a re-index only embeds files that
+//! changed.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A file as it was last indexed
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(super) struct ManifestEntry {
+    /// `content_hash` of the whole file.
+    pub(super) hash: String,
+    /// Ids of the file's chunks in the table.
+    pub(super) chunks: Vec<String>,
+}
+
+/// The indexed files of one project table, keyed by their path in the index
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(super) struct Manifest {
+    pub(super) files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Takes an exclusive advisory lock for the manifest at `path`, waiting
+    /// while another run, such as `corty index` next to a session's
+    /// watcher, holds it. The lock is released when the file is dropped.
+    pub(super) async fn lock(path: &Path) -> io::Result<File> {
+        let mut lock_path = PathBuf::from(path);
+        lock_path.set_extension("lock");
+        tokio::task::spawn_blocking(move || {
+            if let Some(dir) = lock_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)?;
+            file.lock()?;
+            Ok(file)
+        })
+        .await?
+    }
+
+    /// The manifest at `path`, or an empty one if there is none yet. A
+    /// manifest that cannot be read is treated as empty, so every file is
+    /// indexed again.
+    pub(super) fn load(path: &Path) -> Self {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log::warn!("cannot read index manifest {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&json).unwrap_or_else(|e| {
+            log::warn!("ignoring invalid index manifest {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    /// Writes the manifest to `path`, replacing it in one step.
+    pub(super) fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut partial = PathBuf::from(path);
+        partial.set_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec(self)?)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Paths of the files under `scope`, a path in the index; an empty scope
+    /// is the whole project.
+    pub(super) fn files_under<'a>(&'a self, scope: &'a str) -> impl Iterator<Item = &'a str> {
+        self.files.keys().map(String::as_str).filter(move |path| {
+            scope.is_empty()
+                || *path == scope
+                || path
+                    .strip_prefix(scope)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
@@ -0,0 +1,264 @@
+//! Builds the semantic code index `semantic_search` queries.
+//!
+//! Files under a path are walked like `grep` walks them, cut into chunks,
+//! embedded in batches and written to the project's table, replacing the
+//! chunks they had before. Files that did not change since the last run are
+//! left alone.
+
+mod chunker;
+mod manifest;
+mod syntax;
+mod watch;
+pub use chunker::{chunk_file, language_for};
+pub use watch::{IndexWatcher, WatchEvent, WATCH_DEBOUNCE};
+
+use crate::{
+    storage::{content_hash, project_table_name, CodeChunk, Table, VectorDB},
+    tools::{display_path, workspace_walker},
+};
+use color_eyre::Result;
+use manifest::{Manifest, ManifestEntry};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Larger files are not indexed.
+pub const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Bytes at the start of a file checked for NUL bytes.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+/// Chunks embedded and written together.
+const EMBED_BATCH_SIZE: usize = 128;
+
+/// Progress of [`index_path`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexEvent {
+    /// The walk found `files` candidate files.
+    Scanned { files: usize },
+    /// A file was handled: indexed, unchanged, skipped or failed.
+    FileDone { path: PathBuf },
+}
+
+/// Outcome of [`index_path`]
+#[derive(Debug, Default)]
+pub struct IndexSummary {
+    /// Files indexed for the first time.
+    pub added: usize,
+    /// Files indexed again because their content changed.
+    pub updated: usize,
+    /// Files whose chunks were deleted because they are gone, or are now
+    /// ignored, binary or oversized.
+    pub removed: usize,
+    /// Files left as they were.
+    pub unchanged: usize,
+    /// Chunks embedded and written.
+    pub chunks: usize,
+    /// Binary or oversized files.
+    pub skipped: usize,
+    /// Files that could not be indexed, with the reason.
+    pub errors: Vec<(PathBuf, String)>,
+    pub elapsed: Duration,
+}
+
+/// Indexes the files under `path` into the table of the project at `root`.
+///
+/// Paths in the index are relative to `root`, which `path` should lie in.
+/// Only new and changed files are embedded: a manifest next to the table
+/// records the content hash and chunks of every indexed file. Chunks of
+/// files under `path` that are no longer indexable are deleted.
+///
+/// Errors with single files are collected in the summary; failing to embed
+/// or write a batch ends the run, keeping what was written before. Runs on
+/// the same project wait for each other, even across processes.
+pub async fn index_path(
+    db: &VectorDB,
+    root: &Path,
+    path: &Path,
+    mut on_event: impl FnMut(IndexEvent),
+) -> Result<IndexSummary> {
+    let started = Instant::now();
+    let files = collect_files(path);
+    on_event(IndexEvent::Scanned { files: files.len() });
+
+    let table_name = project_table_name(root);
+    let manifest_path = Path::new(db.path()).join(format!("{table_name}.manifest.json"));
+    let _lock = Manifest::lock(&manifest_path).await?;
+    // A new table holds none of the files the manifest lists, for instance
+    // after the old one was deleted to rebuild the index.
+    let (table, manifest) = match db.find_table(&table_name).await? {
+        Some(table) => (table, Manifest::load(&manifest_path)),
+        None => (
+            db.open_or_create_chunk_table(&table_name).await?,
+            Manifest::default(),
+        ),
+    };
+    let mut run = Run {
+        db,
+        table: &table,
+        root,
+        manifest,
+        summary: IndexSummary::default(),
+        batch: Vec::new(),
+        pending: Vec::new(),
+    };
+    let result = run
+        .index(&index_path_of(root, path), files, &mut on_event)
+        .await;
+    run.manifest.save(&manifest_path)?;
+    result?;
+
+    run.summary.elapsed = started.elapsed();
+    Ok(run.summary)
+}
+
+/// `path` as stored in the index of the project at `root`.
+fn index_path_of(root: &Path, path: &Path) -> String {
+    display_path(root, path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// State of one [`index_path`] call.
+struct Run<'a> {
+    db: &'a VectorDB,
+    table: &'a Table,
+    root: &'a Path,
+    manifest: Manifest,
+    summary: IndexSummary,
+    /// Chunks waiting to be embedded.
+    batch: Vec<CodeChunk>,
+    /// The files `batch` holds, with their path in the index and hash.
+    pending: Vec<(PathBuf, String, String)>,
+}
+
+impl Run<'_> {
+    async fn index(
+        &mut self,
+        scope: &str,
+        files: Vec<PathBuf>,
+        on_event: &mut impl FnMut(IndexEvent),
+    ) -> Result<()> {
+        // Files that keep their chunks: indexed now, unchanged, or not
+        // readable this time.
+        let mut kept = HashSet::new();
+        for file in files {
+            let relative = index_path_of(self.root, &file);
+            match read_source(&file) {
+                Ok(Some(text)) => {
+                    kept.insert(relative.clone());
+                    let hash = content_hash(&text);
+                    if self
+                        .manifest
+                        .files
+                        .get(&relative)
+                        .is_some_and(|entry| entry.hash == hash)
+                    {
+                        self.summary.unchanged += 1;
+                        on_event(IndexEvent::FileDone { path: file });
+                        continue;
+                    }
+                    self.batch
+                        .extend(chunk_file(&relative, language_for(&file), &text));
+                    self.pending.push((file, relative, hash));
+                }
+                Ok(None) => {
+                    self.summary.skipped += 1;
+                    on_event(IndexEvent::FileDone { path: file });
+                }
+                Err(e) => {
+                    kept.insert(relative);
+                    self.summary.errors.push((file.clone(), e.to_string()));
+                    on_event(IndexEvent::FileDone { path: file });
+                }
+            }
+            if self.batch.len() >= EMBED_BATCH_SIZE {
+                self.write_batch(on_event).await?;
+            }
+        }
+        self.write_batch(on_event).await?;
+
+        let removed: Vec<String> = self
+            .manifest
+            .files_under(scope)
+            .filter(|path| !kept.contains(*path))
+            .map(str::to_string)
+            .collect();
+        let stale: Vec<String> = removed
+            .iter()
+            .flat_map(|path| self.manifest.files[path].chunks.iter().cloned())
+            .collect();
+        self.db.delete_chunks(self.table, &stale).await?;
+        for path in &removed {
+            self.manifest.files.remove(path);
+        }
+        self.summary.removed += removed.len();
+        Ok(())
+    }
+
+    /// Replaces the chunks of the pending files with the batch.
+    async fn write_batch(&mut self, on_event: &mut impl FnMut(IndexEvent)) -> Result<()> {
+        let mut stale = Vec::new();
+        // Files the manifest does not know may still have rows if it could
+        // not be read.
+        let mut unknown = Vec::new();
+        for (_, relative, _) in &self.pending {
+            match self.manifest.files.get(relative) {
+                Some(entry) => stale.extend(entry.chunks.iter().cloned()),
+                None => unknown.push(relative.clone()),
+            }
+        }
+        self.db.delete_chunks(self.table, &stale).await?;
+        self.db.delete_files(self.table, &unknown).await?;
+        for chunks in self.batch.chunks(EMBED_BATCH_SIZE) {
+            self.db.add_chunks(self.table, chunks).await?;
+        }
+        self.summary.chunks += self.batch.len();
+
+        let batch = std::mem::take(&mut self.batch);
+        for (path, relative, hash) in self.pending.drain(..) {
+            let chunks = batch
+                .iter()
+                .filter(|chunk| chunk.path == relative)
+                .map(|chunk| chunk.id.clone())
+                .collect();
+            let entry = ManifestEntry { hash, chunks };
+            match self.manifest.files.insert(relative, entry) {
+                Some(_) => self.summary.updated += 1,
+                None => self.summary.added += 1,
+            }
+            on_event(IndexEvent::FileDone { path });
+        }
+        Ok(())
+    }
+}
+
+/// Files under `path`, without ignored and hidden ones.
+pub fn collect_files(path: &Path) -> Vec<PathBuf> {
+    workspace_walker(path)
+        .build()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                log::debug!("skipping unreadable entry: {e}");
+                None
+            }
+        })
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Text of the file at `path`, or `None` for binary and oversized files.
+pub fn read_source(path: &Path) -> std::io::Result<Option<String>> {
+    if std::fs::metadata(path)?.len() > MAX_FILE_BYTES {
+        return Ok(None);
+    }
+    let bytes = std::fs::read(path)?;
+    if bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
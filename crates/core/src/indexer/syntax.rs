@@ -0,0 +1,347 @@
+//! Syntax-aware chunking with tree-sitter.
+//!
+//! Each top-level item (function, type, impl, class, ...) becomes one chunk,
+//! together with the comments and attributes right above it. Items longer
+//! than [`MAX_CHUNK_LINES`] are split at the items or statements in their
+//! body, and each part starts with the signature of the item it is in, so
+//! a method still says which type it belongs to. Code between items, such
+//! as imports and module docs, is grouped into chunks of its own.
+
+use tree_sitter::{Language, Node, Parser};
+
+/// Items longer than this are split.
+pub(super) const MAX_CHUNK_LINES: usize = 80;
+
+/// A chunk as lines of the file, before it is turned into a `CodeChunk`.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Span {
+    /// First and last line, 0-based and inclusive.
+    pub(super) start: usize,
+    pub(super) end: usize,
+    pub(super) symbol: Option<String>,
+    /// Signatures of the items the span is in, outermost first.
+    pub(super) context: Vec<String>,
+}
+
+struct Grammar {
+    language: Language,
+    /// Node kinds that become chunks of their own.
+    items: &'static [&'static str],
+    /// Node kinds wrapping an item in their `declaration` or `definition`
+    /// field, e.g. `export` or decorators.
+    wrappers: &'static [&'static str],
+    /// Node kinds kept with the item that follows them.
+    preludes: &'static [&'static str],
+    /// Joins nested symbol names, e.g. `Type::method`.
+    separator: &'static str,
+}
+
+fn grammar(language: &str) -> Option<Grammar> {
+    const JS_ITEMS: &[&str] = &[
+        "function_declaration",
+        "generator_function_declaration",
+        "class_declaration",
+        "abstract_class_declaration",
+        "interface_declaration",
+        "type_alias_declaration",
+        "enum_declaration",
+        "internal_module",
+        "method_definition",
+        "lexical_declaration",
+    ];
+    Some(match language {
+        "rust" => Grammar {
+            language: tree_sitter_rust::LANGUAGE.into(),
+            items: &[
+                "function_item",
+                "function_signature_item",
+                "struct_item",
+                "enum_item",
+                "union_item",
+                "trait_item",
+                "impl_item",
+                "mod_item",
+                "macro_definition",
+                "const_item",
+                "static_item",
+                "type_item",
+            ],
+            wrappers: &[],
+            preludes: &["line_comment", "block_comment", "attribute_item"],
+            separator: "::",
+        },
+        "python" => Grammar {
+            language: tree_sitter_python::LANGUAGE.into(),
+            items: &["function_definition", "class_definition"],
+            wrappers: &["decorated_definition"],
+            preludes: &["comment"],
+            separator: ".",
+        },
+        "typescript" => Grammar {
+            language: tree_sitter_typescript::LANGUAGE_TSX.into(),
+            items: JS_ITEMS,
+            wrappers: &["export_statement"],
+            preludes: &["comment"],
+            separator: ".",
+        },
+        "javascript" => Grammar {
+            language: tree_sitter_javascript::LANGUAGE.into(),
+            items: JS_ITEMS,
+            wrappers: &["export_statement"],
+            preludes: &["comment"],
+            separator: ".",
+        },
+        "go" => Grammar {
+            language: tree_sitter_go::LANGUAGE.into(),
+            items: &[
+                "function_declaration",
+                "method_declaration",
+                "type_declaration",
+            ],
+            wrappers: &[],
+            preludes: &["comment"],
+            separator: ".",
+        },
+        "java" => Grammar {
+            language: tree_sitter_java::LANGUAGE.into(),
+            items: &[
+                "class_declaration",
+                "interface_declaration",
+                "enum_declaration",
+                "record_declaration",
+                "annotation_type_declaration",
+                "method_declaration",
+                "constructor_declaration",
+            ],
+            wrappers: &[],
+            preludes: &["line_comment", "block_comment"],
+            separator: ".",
+        },
+        _ => return None,
+    })
+}
+
+/// Spans of `text`, or `None` when `language` has no grammar or the file
+/// does not parse.
+pub(super) fn spans(language: &str, text: &str) -> Option<Vec<Span>> {
+    let grammar = grammar(language)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language).ok()?;
+    let tree = parser.parse(text, None)?;
+    let root = tree.root_node();
+    if root.has_error() {
+        return None;
+    }
+    let chunker = Chunker {
+        grammar: &grammar,
+        text,
+    };
+    let mut spans = Vec::new();
+    chunker.children(root, None, &[], &mut spans);
+    Some(spans)
+}
+
+struct Chunker<'a> {
+    grammar: &'a Grammar,
+    text: &'a str,
+}
+
+impl Chunker<'_> {
+    /// Chunks the named children of `parent`, the body of the item `symbol`
+    /// (`None` at the top level).
+    fn children(
+        &self,
+        parent: Node,
+        symbol: Option<&str>,
+        context: &[String],
+        spans: &mut Vec<Span>,
+    ) {
+        let mut cursor = parent.walk();
+        // Code that is not an item, waiting to be emitted as one chunk.
+        let mut glue: Option<(usize, usize)> = None;
+        // Lines of the comments and attributes above the next item.
+        let mut prelude: Option<(usize, usize)> = None;
+        let flush = |glue: &mut Option<(usize, usize)>, spans: &mut Vec<Span>| {
+            if let Some((start, end)) = glue.take() {
+                self.windows(start, end, symbol.map(str::to_string), context, spans);
+            }
+        };
+
+        for node in parent.named_children(&mut cursor) {
+            let (start, end) = rows(node);
+            if self.grammar.preludes.contains(&node.kind()) {
+                prelude = Some((prelude.map_or(start, |(start, _)| start), end));
+                continue;
+            }
+            let start = prelude.take().map_or(start, |(start, _)| start);
+            match self.item(node) {
+                Some(item) => {
+                    flush(&mut glue, spans);
+                    let name = self.name(item).map(|name| match symbol {
+                        Some(parent) => format!("{parent}{}{name}", self.grammar.separator),
+                        None => name,
+                    });
+                    self.item_spans(item, start, end, name, context, spans);
+                }
+                None => {
+                    glue = match glue {
+                        Some((glue_start, glue_end)) if end + 1 - glue_start <= MAX_CHUNK_LINES => {
+                            Some((glue_start, end.max(glue_end)))
+                        }
+                        Some(_) => {
+                            flush(&mut glue, spans);
+                            Some((start, end))
+                        }
+                        None => Some((start, end)),
+                    };
+                }
+            }
+        }
+        // Comments after the last item.
+        if let Some((start, end)) = prelude {
+            flush(&mut glue, spans);
+            glue = Some((start, end));
+        }
+        flush(&mut glue, spans);
+    }
+
+    /// Spans of the item `node`, whose chunk covers lines `start..=end`.
+    fn item_spans(
+        &self,
+        node: Node,
+        start: usize,
+        end: usize,
+        symbol: Option<String>,
+        context: &[String],
+        spans: &mut Vec<Span>,
+    ) {
+        if end + 1 - start <= MAX_CHUNK_LINES {
+            spans.push(Span {
+                start,
+                end,
+                symbol,
+                context: context.to_vec(),
+            });
+            return;
+        }
+        let Some(body) = node
+            .child_by_field_name("body")
+            .filter(|body| body.named_child_count() > 0)
+        else {
+            self.windows(start, end, symbol, context, spans);
+            return;
+        };
+        // The lines from the item's start up to where its body opens; a
+        // body starting its own line, like a Python block, is not part of
+        // it.
+        let (item_row, body_start) = (node.start_position().row, body.start_position());
+        let body_starts_line = self
+            .text
+            .lines()
+            .nth(body_start.row)
+            .and_then(|line| line.get(..body_start.column))
+            .is_some_and(|before| before.trim().is_empty());
+        let signature_end = if body_starts_line && body_start.row > item_row {
+            body_start.row - 1
+        } else {
+            body_start.row
+        };
+        let signature = self.lines(item_row, signature_end);
+        let mut context = context.to_vec();
+        context.push(signature);
+        self.children(body, symbol.as_deref(), &context, spans);
+    }
+
+    /// Windows of at most [`MAX_CHUNK_LINES`] over `start..=end`, for code
+    /// with no structure to split at.
+    fn windows(
+        &self,
+        start: usize,
+        end: usize,
+        symbol: Option<String>,
+        context: &[String],
+        spans: &mut Vec<Span>,
+    ) {
+        let mut window_start = start;
+        while window_start <= end {
+            let window_end = (window_start + MAX_CHUNK_LINES - 1).min(end);
+            spans.push(Span {
+                start: window_start,
+                end: window_end,
+                symbol: symbol.clone(),
+                context: context.to_vec(),
+            });
+            window_start = window_end + 1;
+        }
+    }
+
+    /// The item `node` is or wraps.
+    fn item<'tree>(&self, node: Node<'tree>) -> Option<Node<'tree>> {
+        if self.grammar.wrappers.contains(&node.kind()) {
+            return node
+                .child_by_field_name("declaration")
+                .or_else(|| node.child_by_field_name("definition"))
+                .and_then(|inner| self.item(inner))
+                // Keep the whole wrapper, e.g. with its decorators.
+                .map(|_| node);
+        }
+        if !self.grammar.items.contains(&node.kind()) {
+            return None;
+        }
+        // `const f = () => ...` is an item, other bindings are not.
+        if node.kind() == "lexical_declaration" {
+            let value = node
+                .named_child(0)
+                .and_then(|declarator| declarator.child_by_field_name("value"))?;
+            if !matches!(
+                value.kind(),
+                "arrow_function" | "function_expression" | "class"
+            ) {
+                return None;
+            }
+        }
+        Some(node)
+    }
+
+    /// Name of the item `node`, as written in the source.
+    fn name(&self, node: Node) -> Option<String> {
+        let text = |node: Node| self.text[node.byte_range()].to_string();
+        if self.grammar.wrappers.contains(&node.kind()) {
+            let inner = node
+                .child_by_field_name("declaration")
+                .or_else(|| node.child_by_field_name("definition"))?;
+            return self.name(inner);
+        }
+        match node.kind() {
+            "impl_item" => node.child_by_field_name("type").map(text),
+            "type_declaration" | "lexical_declaration" => node
+                .named_child(0)
+                .and_then(|spec| spec.child_by_field_name("name"))
+                .map(text),
+            _ => node.child_by_field_name("name").map(text),
+        }
+    }
+
+    /// Lines `start..=end` of the file, trimmed.
+    fn lines(&self, start: usize, end: usize) -> String {
+        self.text
+            .lines()
+            .skip(start)
+            .take(end + 1 - start)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+}
+
+/// First and last line of `node`. A node ending at the start of a line,
+/// after its newline, ends on the line before.
+fn rows(node: Node) -> (usize, usize) {
+    let (start, end) = (node.start_position(), node.end_position());
+    if end.column == 0 && end.row > start.row {
+        (start.row, end.row - 1)
+    } else {
+        (start.row, end.row)
+    }
+}
@@ -0,0 +1,215 @@
+//! Keeps a project's index up to date while files change.
+//!
+//! Filesystem events are debounced, so a burst of writes (a save, a
+//! checkout, a patch touching many files) becomes one update. Each update
+//! re-indexes only the touched paths, in the background; a project without
+//! an index is left alone.
+
+use super::{index_path, IndexSummary};
+use crate::storage::{project_table_name, EmbeddingProvider, VectorDB};
+use color_eyre::Result;
+use ignore::gitignore::GitignoreBuilder;
+use notify_debouncer_mini::{
+    new_debouncer, notify::RecommendedWatcher, notify::RecursiveMode, DebounceEventResult,
+    Debouncer,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::{
+    mpsc::{self, Sender, UnboundedReceiver},
+    OnceCell,
+};
+
+/// Quiet time after the last change before the index is updated.
+pub const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// What the watcher did, for notifications
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// Re-indexing of `paths` changed files or directories started.
+    Started { paths: usize },
+    /// The index was updated.
+    Finished(IndexSummary),
+    /// Updating the index failed; later changes are still picked up.
+    Failed(String),
+}
+
+/// Watches the project at `root` for as long as it is alive
+pub struct IndexWatcher {
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+impl IndexWatcher {
+    /// Starts watching `root`, whose index is in the database at
+    /// `index_dir`, and reports updates on `events`.
+    ///
+    /// The embedder is only loaded once a file changes in a project that has
+    /// an index, so watching costs nothing until then.
+    pub fn spawn(
+        root: &Path,
+        index_dir: &Path,
+        embedding: EmbeddingProvider,
+        events: Sender<WatchEvent>,
+    ) -> Result<Self> {
+        // Events arrive with resolved paths, so both sides are compared in
+        // that form. The database directory may not exist yet.
+        let root = root.canonicalize()?;
+        std::fs::create_dir_all(index_dir)?;
+        let index_dir = index_dir.canonicalize()?;
+        let (changes_tx, changes_rx) = mpsc::unbounded_channel();
+        let mut debouncer =
+            new_debouncer(
+                WATCH_DEBOUNCE,
+                move |result: DebounceEventResult| match result {
+                    Ok(changes) => {
+                        let paths = changes.into_iter().map(|change| change.path).collect();
+                        let _ = changes_tx.send(paths);
+                    }
+                    Err(e) => log::warn!("file watcher error: {e}"),
+                },
+            )?;
+        debouncer.watcher().watch(&root, RecursiveMode::Recursive)?;
+
+        let updater = Updater {
+            root,
+            index_dir,
+            embedding,
+            db: OnceCell::new(),
+            events,
+        };
+        tokio::spawn(updater.run(changes_rx));
+        Ok(Self {
+            _debouncer: debouncer,
+        })
+    }
+}
+
+/// The background task re-indexing changed paths.
+struct Updater {
+    root: PathBuf,
+    index_dir: PathBuf,
+    embedding: EmbeddingProvider,
+    /// Opened on the first change to an indexed project; loading the
+    /// embedding model takes a while. `None` if that failed, which is
+    /// reported once rather than on every change.
+    db: OnceCell<Option<VectorDB>>,
+    events: Sender<WatchEvent>,
+}
+
+impl Updater {
+    async fn run(self, mut changes: UnboundedReceiver<Vec<PathBuf>>) {
+        while let Some(mut paths) = changes.recv().await {
+            // Take in what piled up while the last update ran.
+            while let Ok(more) = changes.try_recv() {
+                paths.extend(more);
+            }
+            paths.sort();
+            paths.dedup();
+            paths.retain(|path| self.is_watched(path));
+            if paths.is_empty() {
+                continue;
+            }
+            let event = match self.update(&paths).await {
+                Ok(Some(summary)) => WatchEvent::Finished(summary),
+                Ok(None) => continue,
+                Err(e) => WatchEvent::Failed(e.to_string()),
+            };
+            if self.events.send(event).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Re-indexes `paths`, or returns `None` if the project has no index or
+    /// it cannot be opened.
+    async fn update(&self, paths: &[PathBuf]) -> Result<Option<IndexSummary>> {
+        let index_dir = self.index_dir.to_string_lossy();
+        let table_name = project_table_name(&self.root);
+        if self.db.get().is_none() && !VectorDB::has_table(&index_dir, &table_name).await? {
+            log::debug!("{} has no index to update", self.root.display());
+            return Ok(None);
+        }
+        let mut failure = None;
+        let db = self
+            .db
+            .get_or_init(|| async {
+                VectorDB::connect(&index_dir, &self.embedding)
+                    .await
+                    .map_err(|e| failure = Some(e))
+                    .ok()
+            })
+            .await;
+        let Some(db) = db else {
+            return failure.map_or(Ok(None), Err);
+        };
+        if db.find_table(&table_name).await?.is_none() {
+            log::debug!("{} has no index to update", self.root.display());
+            return Ok(None);
+        }
+        let _ = self
+            .events
+            .send(WatchEvent::Started { paths: paths.len() })
+            .await;
+
+        let mut total = IndexSummary::default();
+        for path in paths {
+            let summary = index_path(db, &self.root, path, |_| {}).await?;
+            total.added += summary.added;
+            total.updated += summary.updated;
+            total.removed += summary.removed;
+            total.unchanged += summary.unchanged;
+            total.chunks += summary.chunks;
+            total.skipped += summary.skipped;
+            total.errors.extend(summary.errors);
+            total.elapsed += summary.elapsed;
+        }
+        Ok(Some(total))
+    }
+
+    /// Whether a change to `path` can affect the index: it is in the
+    /// project, not hidden and not ignored, the same files
+    /// [`collect_files`](super::collect_files) walks.
+    fn is_watched(&self, path: &Path) -> bool {
+        if path.starts_with(&self.index_dir) {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+        {
+            return false;
+        }
+        // Ignore files further down override those above.
+        let is_dir = path.is_dir();
+        let mut ignored = false;
+        let mut dir = self.root.clone();
+        let mut components = relative.components();
+        loop {
+            let mut builder = GitignoreBuilder::new(&dir);
+            for name in [".gitignore", ".ignore"] {
+                let file = dir.join(name);
+                if file.is_file() {
+                    builder.add(file);
+                }
+            }
+            if let Ok(gitignore) = builder.build() {
+                let matched = gitignore.matched_path_or_any_parents(path, is_dir);
+                if matched.is_ignore() {
+                    ignored = true;
+                } else if matched.is_whitelist() {
+                    ignored = false;
+                }
+            }
+            match components.next() {
+                Some(component) if dir.join(component).as_path() != path => dir.push(component),
+                _ => break,
+            }
+        }
+        !ignored
+    }
+}
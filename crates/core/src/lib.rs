@@ -1,7 +1,17 @@
-mod config;
+mod client;
+pub mod config;
+mod conversation_history;
 pub mod corty;
-mod error;
+pub mod error;
+pub mod indexer;
+mod message_history;
+pub mod model_provider_info;
+pub mod models;
 pub mod protocol;
-mod session;
+pub mod sandbox;
+pub mod session;
+mod spawn;
 pub mod storage;
+mod tools;
+mod user_input;
 mod utils;
@@ -0,0 +1,118 @@
+//! Prompt history shared by all sessions.
+//!
+//! Every prompt sent with `Op::AddToHistory` is appended as one JSON line to
+//! `<data_dir>/history.jsonl`. Several Corty instances may write at once, so
+//! every access takes an advisory lock on the file: exclusive for writes,
+//! shared for reads. Once the file grows past the configured limit the
+//! oldest entries are dropped.
+
+use crate::protocol::HistoryEntry;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the history file inside the data dir.
+pub const HISTORY_FILENAME: &str = "history.jsonl";
+
+fn history_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(HISTORY_FILENAME)
+}
+
+/// Append `text` to the history file, trimming it to `max_bytes` if needed.
+pub(crate) fn append_entry(
+    data_dir: &Path,
+    session_id: &str,
+    text: &str,
+    max_bytes: u64,
+) -> io::Result<()> {
+    std::fs::create_dir_all(data_dir)?;
+
+    let entry = HistoryEntry {
+        session_id: session_id.to_string(),
+        ts: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        text: text.to_string(),
+    };
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(history_path(data_dir))?;
+    file.lock()?;
+    // The whole line goes out in a single write so a reader never sees a
+    // partial entry.
+    file.write_all(line.as_bytes())?;
+    file.flush()?;
+
+    if file.metadata()?.len() > max_bytes {
+        trim(&mut file, max_bytes)?;
+    }
+    Ok(())
+}
+
+/// Drop the oldest entries so the file ends up at or below three quarters of
+/// `max_bytes`, leaving room for new entries before the next rewrite.
+/// The caller must hold the exclusive lock.
+fn trim(file: &mut File, max_bytes: u64) -> io::Result<()> {
+    let mut contents = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut contents)?;
+
+    let target = (max_bytes / 4 * 3) as usize;
+    let mut start = contents.len().saturating_sub(target);
+    // Only cut at a line boundary.
+    if start > 0 && contents[start - 1] != b'\n' {
+        start = match contents[start..].iter().position(|&b| b == b'\n') {
+            Some(pos) => start + pos + 1,
+            None => contents.len(),
+        };
+    }
+
+    file.set_len(0)?;
+    file.write_all(&contents[start..])?;
+    file.flush()
+}
+
+/// Number of entries in the history file.
+pub(crate) fn entry_count(data_dir: &Path) -> io::Result<usize> {
+    let file = match File::open(history_path(data_dir)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    file.lock_shared()?;
+    let mut count = 0;
+    for line in BufReader::new(&file).split(b'\n') {
+        line?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Entry at `offset` (0 = oldest), if there is one.
+pub(crate) fn lookup(data_dir: &Path, offset: usize) -> io::Result<Option<HistoryEntry>> {
+    let file = match File::open(history_path(data_dir)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    file.lock_shared()?;
+    match BufReader::new(&file).lines().nth(offset) {
+        Some(line) => match serde_json::from_str(&line?) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) => {
+                log::warn!("malformed history entry at offset {offset}: {e}");
+                Ok(None)
+            }
+        },
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,101 @@
+//! Registry of model providers the engine knows how to talk to.
+//!
+//! `Op::ConfigureSession` carries a full [`ModelProviderInfo`]; the engine only
+//! accepts it if it is registered, unchanged, here (or in
+//! `Config::model_providers`) and is usable. A client cannot point a known
+//! id at another host or API key.
+
+use crate::protocol::{ApiKeySource, ModelProviderInfo, ProviderError, WireApi};
+use std::collections::HashMap;
+
+/// Id of the provider used when none is configured.
+pub const DEFAULT_PROVIDER_ID: &str = "openai";
+
+/// Providers that are available without any configuration.
+pub fn built_in_model_providers() -> HashMap<String, ModelProviderInfo> {
+    [
+        ModelProviderInfo {
+            id: "openai".to_string(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            api_key: ApiKeySource::Env {
+                var: "OPENAI_API_KEY".to_string(),
+            },
+            wire_api: WireApi::Responses,
+        },
+        ModelProviderInfo {
+            id: "openrouter".to_string(),
+            base_url: "https://openrouter.ai/api/v1".to_string(),
+            api_key: ApiKeySource::Env {
+                var: "OPENROUTER_API_KEY".to_string(),
+            },
+            wire_api: WireApi::Chat,
+        },
+        ModelProviderInfo {
+            id: "ollama".to_string(),
+            base_url: "http://localhost:11434/v1".to_string(),
+            api_key: ApiKeySource::None,
+            wire_api: WireApi::Chat,
+        },
+    ]
+    .into_iter()
+    .map(|provider| (provider.id.clone(), provider))
+    .collect()
+}
+
+impl ModelProviderInfo {
+    /// Check that `registry` holds this very descriptor and make sure it
+    /// can be used.
+    pub fn validate(
+        &self,
+        registry: &HashMap<String, ModelProviderInfo>,
+    ) -> Result<(), ProviderError> {
+        match registry.get(&self.id) {
+            None => {
+                return Err(ProviderError::UnknownProvider {
+                    id: self.id.clone(),
+                })
+            }
+            Some(registered) if registered != self => {
+                return Err(ProviderError::ProviderMismatch {
+                    id: self.id.clone(),
+                })
+            }
+            Some(_) => {}
+        }
+
+        let url =
+            reqwest::Url::parse(&self.base_url).map_err(|e| ProviderError::InvalidBaseUrl {
+                base_url: self.base_url.clone(),
+                reason: e.to_string(),
+            })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(ProviderError::InvalidBaseUrl {
+                base_url: self.base_url.clone(),
+                reason: format!("unsupported scheme `{}`", url.scheme()),
+            });
+        }
+
+        self.api_key().map(|_| ())
+    }
+
+    /// Resolve the API key, if the provider needs one.
+    pub fn api_key(&self) -> Result<Option<String>, ProviderError> {
+        match &self.api_key {
+            ApiKeySource::None => Ok(None),
+            ApiKeySource::Env { var } => std::env::var(var)
+                .ok()
+                .filter(|key| !key.trim().is_empty())
+                .map(Some)
+                .ok_or_else(|| ProviderError::MissingApiKey { var: var.clone() }),
+        }
+    }
+
+    /// Full URL for the endpoint of this provider's wire API.
+    pub(crate) fn endpoint_url(&self) -> String {
+        let path = match self.wire_api {
+            WireApi::Responses => "responses",
+            WireApi::Chat => "chat/completions",
+        };
+        format!("{}/{}", self.base_url.trim_end_matches('/'), path)
+    }
+}
@@ -0,0 +1,70 @@
+//! Conversation items as seen by the model.
+//!
+//! These are the items kept in the session history and replayed to the
+//! provider on every turn. They are deliberately independent of the wire
+//! format of any particular provider.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseItem {
+    Message {
+        role: String,
+        content: Vec<ContentItem>,
+    },
+    /// A tool call requested by the model.
+    FunctionCall {
+        name: String,
+        /// JSON-encoded arguments, exactly as the model produced them.
+        arguments: String,
+        call_id: String,
+    },
+    /// The result of a tool call, sent back to the model.
+    FunctionCallOutput { call_id: String, output: String },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentItem {
+    InputText {
+        text: String,
+    },
+    /// Image as a `data:` URL.
+    InputImage {
+        image_url: String,
+    },
+    OutputText {
+        text: String,
+    },
+}
+
+impl ResponseItem {
+    /// Build an assistant message from its full text.
+    pub fn assistant_message(text: String) -> Self {
+        ResponseItem::Message {
+            role: "assistant".to_string(),
+            content: vec![ContentItem::OutputText { text }],
+        }
+    }
+
+    /// Concatenated text of an assistant message, `None` for anything else.
+    pub fn assistant_text(&self) -> Option<String> {
+        match self {
+            ResponseItem::Message { role, content } if role == "assistant" => {
+                Some(content.iter().filter_map(ContentItem::text).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ContentItem {
+    /// Text of the item, `None` for images.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            ContentItem::InputText { text } | ContentItem::OutputText { text } => Some(text),
+            ContentItem::InputImage { .. } => None,
+        }
+    }
+}
@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Submission {
@@ -16,8 +20,9 @@ pub struct Submission {
 pub enum Op {
     /// Configure the model session.
     ConfigureSession {
-        /// Provider identifier ("openai", "openrouter", ...).
-        provider: (),
+        /// Provider to talk to. It must be registered in the engine's
+        /// provider registry, exactly as given here.
+        provider: ModelProviderInfo,
 
         /// If not specified, server will use its default model.
         model: String,
@@ -25,6 +30,8 @@ pub enum Op {
         cwd: std::path::PathBuf,
     },
 
+    /// Abort the running task. Partial output is kept and the task ends
+    /// with `TurnAborted`; queued inputs are dropped.
     Interrupt,
 
     /// Input from the user
@@ -37,6 +44,118 @@ pub enum Op {
         /// The message text to be stored.
         text: String,
     },
+
+    /// Summarize older turns to free up context. Runs as a task, reporting
+    /// `ContextCompacted` between `TaskStarted` and `TaskComplete`.
+    Compact,
+
+    /// Fetch an entry of the global prompt history. Answered with
+    /// `HistoryEntry`.
+    GetHistoryEntry {
+        /// Offset of the entry, 0 being the oldest.
+        offset: usize,
+    },
+
+    /// Answer to an `ExecApprovalRequest`.
+    ExecApproval {
+        /// `call_id` of the request.
+        id: String,
+        decision: ReviewDecision,
+    },
+
+    /// Answer to an `ApplyPatchApprovalRequest`.
+    PatchApproval {
+        /// `call_id` of the request.
+        id: String,
+        decision: ReviewDecision,
+    },
+}
+
+/// When the user is asked before the agent runs a command or applies a patch
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AskForApproval {
+    /// Never ask. Failures, including ones caused by the sandbox, go back
+    /// to the model.
+    Never,
+    /// Ask before everything except known read-only commands.
+    #[serde(rename = "untrusted")]
+    UnlessTrusted,
+    /// Run commands in the sandbox without asking; ask to run them again
+    /// without it if they fail. Patches the sandbox policy allows are
+    /// applied without asking.
+    #[default]
+    OnFailure,
+    /// Ask before every command and patch.
+    Always,
+}
+
+/// The user's answer to an approval request
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewDecision {
+    /// Go ahead this time.
+    Approved,
+    /// Go ahead, and do not ask again for the same command, or for patches
+    /// to the same files, in this session.
+    ApprovedForSession,
+    /// Do not run it; the model is told and may try something else.
+    Denied,
+    /// Do not run it and stop the task, as with `Op::Interrupt`.
+    Abort,
+}
+
+/// Description of a model provider endpoint
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ModelProviderInfo {
+    /// Registry identifier ("openai", "openrouter", "ollama", ...).
+    pub id: String,
+
+    /// Base URL of the API, e.g. `https://api.openai.com/v1`.
+    pub base_url: String,
+
+    /// Where the API key comes from.
+    pub api_key: ApiKeySource,
+
+    /// Wire protocol the endpoint speaks.
+    pub wire_api: WireApi,
+}
+
+/// Source of the API key for a provider
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApiKeySource {
+    /// The endpoint does not need a key (e.g. a local server).
+    None,
+    /// Read the key from an environment variable of the engine process.
+    Env { var: String },
+}
+
+/// Wire protocol used to talk to a provider
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WireApi {
+    /// OpenAI Responses API (`/responses`).
+    Responses,
+    /// OpenAI-compatible Chat Completions API (`/chat/completions`).
+    Chat,
+}
+
+/// Reason a provider descriptor was rejected
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, thiserror::Error)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProviderError {
+    #[error("unknown model provider `{id}`")]
+    UnknownProvider { id: String },
+
+    #[error("model provider `{id}` differs from the registered one")]
+    ProviderMismatch { id: String },
+
+    #[error("invalid base URL `{base_url}`: {reason}")]
+    InvalidBaseUrl { base_url: String, reason: String },
+
+    #[error("missing environment variable `{var}` for the provider API key")]
+    MissingApiKey { var: String },
 }
 
 /// User input
@@ -44,5 +163,385 @@ pub enum Op {
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum InputItem {
-    Text { text: String },
+    Text {
+        text: String,
+    },
+
+    /// A whole file, relative to the session `cwd` unless absolute.
+    File {
+        path: PathBuf,
+    },
+
+    /// Lines `start_line..=end_line` (1-based) of a file.
+    FileRange {
+        path: PathBuf,
+        start_line: usize,
+        end_line: usize,
+    },
+
+    /// An image on the local filesystem, sent to multimodal models.
+    LocalImage {
+        path: PathBuf,
+    },
+}
+
+/// Event Queue Entry - events from the engine to the client
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Event {
+    /// Submission `id` that this event is correlated with.
+    pub id: String,
+    /// Payload
+    pub msg: EventMsg,
+}
+
+/// Response event from the engine
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum EventMsg {
+    /// Error while executing a submission
+    Error(ErrorEvent),
+
+    /// Ack that a `ConfigureSession` was applied.
+    SessionConfigured(SessionConfiguredEvent),
+
+    /// A `ConfigureSession` was rejected; the previous configuration stays active.
+    ConfigureSessionError(ProviderError),
+
+    /// Agent has started a task
+    TaskStarted,
+
+    /// Agent has completed all actions
+    TaskComplete(TaskCompleteEvent),
+
+    /// The task was stopped before completion. Ends the task in place of
+    /// `TaskComplete`.
+    TurnAborted(TurnAbortedEvent),
+
+    /// Agent text output message
+    AgentMessage(AgentMessageEvent),
+
+    /// Incremental chunk of the agent text output
+    AgentMessageDelta(AgentMessageDeltaEvent),
+
+    /// Notification that the agent is about to run a tool.
+    ToolCallBegin(ToolCallBeginEvent),
+
+    /// Notification that a tool call has finished.
+    ToolCallEnd(ToolCallEndEvent),
+
+    /// Incremental output of a command run by the `exec` tool.
+    ExecCommandOutputDelta(ExecCommandOutputDeltaEvent),
+
+    /// The agent created or changed its plan for the task.
+    PlanUpdate(PlanUpdateEvent),
+
+    /// Event of a sub-agent started by the `delegate` tool.
+    SubAgent(SubAgentEvent),
+
+    /// Usage update, sent after every model response.
+    TokenCount(TokenCountEvent),
+
+    /// Ask the user whether to run a command.
+    ExecApprovalRequest(ExecApprovalRequestEvent),
+
+    /// Ask the user whether to apply a patch.
+    ApplyPatchApprovalRequest(ApplyPatchApprovalRequestEvent),
+
+    /// Older turns were replaced by a summary.
+    ContextCompacted(ContextCompactedEvent),
+
+    /// Answer to `Op::GetHistoryEntry`.
+    HistoryEntry(HistoryEntryEvent),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ErrorEvent {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionConfiguredEvent {
+    /// Id of the session, as recorded under the data dir.
+    pub session_id: String,
+    /// Model the session now uses.
+    pub model: String,
+    /// Id of the provider the session now uses.
+    pub provider_id: String,
+    /// Working directory of the session.
+    pub cwd: PathBuf,
+    /// Number of entries in the global prompt history, for `GetHistoryEntry`.
+    pub history_entry_count: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TaskCompleteEvent {
+    /// Final message of the agent for this task, if any.
+    pub last_agent_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TurnAbortedEvent {
+    pub reason: TurnAbortReason,
+}
+
+/// Why a task was stopped early
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TurnAbortReason {
+    /// The user sent `Op::Interrupt`.
+    Interrupted,
+    /// The model kept calling tools past `Config::max_tool_iterations`.
+    MaxIterations,
+    /// The turn ran longer than `Config::max_turn_duration`.
+    Timeout,
+    /// The model made the same tool call more than
+    /// `Config::max_repeated_tool_calls` times.
+    RepeatedToolCall,
+}
+
+impl std::fmt::Display for TurnAbortReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TurnAbortReason::Interrupted => "interrupted by the user",
+            TurnAbortReason::MaxIterations => "too many tool iterations",
+            TurnAbortReason::Timeout => "time limit reached",
+            TurnAbortReason::RepeatedToolCall => "the same tool call was repeated too often",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AgentMessageEvent {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AgentMessageDeltaEvent {
+    pub delta: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCallBeginEvent {
+    /// Identifier so this can be paired with the ToolCallEnd event.
+    pub call_id: String,
+    /// Name of the tool as exposed to the model.
+    pub tool: String,
+    /// Arguments the model passed to the tool.
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCallEndEvent {
+    /// Identifier for the ToolCallBegin that finished.
+    pub call_id: String,
+    /// Whether the tool reported success.
+    pub success: bool,
+    /// Output returned to the model.
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubAgentEvent {
+    /// `call_id` of the `delegate` call that started the sub-agent.
+    pub call_id: String,
+    /// What the sub-agent reported. Streaming deltas are not forwarded.
+    pub msg: Box<EventMsg>,
+}
+
+/// The agent's whole plan, sent after every `update_plan` call
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PlanUpdateEvent {
+    /// Why the plan was made or changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+    /// Steps in the order they are meant to be done.
+    pub plan: Vec<PlanItem>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PlanItem {
+    pub step: String,
+    pub status: StepStatus,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    InProgress,
+    Done,
+}
+
+/// What commands run by the agent may do
+///
+/// Enforced with Landlock and seccomp on Linux; see `corty_core::sandbox`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+pub enum SandboxPolicy {
+    /// No restrictions at all.
+    DangerFullAccess,
+    /// Read anything, write nothing, no network.
+    ReadOnly,
+    /// Read anything; write only below the working directory, the temp
+    /// dirs and `writable_roots`.
+    WorkspaceWrite {
+        /// Extra directories commands may write to.
+        #[serde(default)]
+        writable_roots: Vec<PathBuf>,
+        /// Allow network access.
+        #[serde(default)]
+        network_access: bool,
+    },
+}
+
+impl Default for SandboxPolicy {
+    fn default() -> Self {
+        SandboxPolicy::WorkspaceWrite {
+            writable_roots: Vec::new(),
+            network_access: false,
+        }
+    }
+}
+
+impl SandboxPolicy {
+    pub fn has_full_disk_write_access(&self) -> bool {
+        matches!(self, SandboxPolicy::DangerFullAccess)
+    }
+
+    pub fn has_full_network_access(&self) -> bool {
+        match self {
+            SandboxPolicy::DangerFullAccess => true,
+            SandboxPolicy::ReadOnly => false,
+            SandboxPolicy::WorkspaceWrite { network_access, .. } => *network_access,
+        }
+    }
+
+    /// Directories commands run in `cwd` may write to. Meaningless with
+    /// full disk write access.
+    pub fn writable_roots_with_cwd(&self, cwd: &Path) -> Vec<PathBuf> {
+        let SandboxPolicy::WorkspaceWrite { writable_roots, .. } = self else {
+            return Vec::new();
+        };
+        let mut roots = vec![cwd.to_path_buf(), std::env::temp_dir()];
+        if cfg!(unix) {
+            roots.push(PathBuf::from("/tmp"));
+        }
+        roots.extend(writable_roots.iter().cloned());
+        roots.dedup();
+        roots
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExecCommandOutputDeltaEvent {
+    /// Identifier of the `exec` tool call producing the output.
+    pub call_id: String,
+    /// Which stream the chunk was read from.
+    pub stream: ExecOutputStream,
+    /// The output, as read; a chunk never ends inside a UTF-8 character.
+    pub chunk: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecOutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub cached_input_tokens: u64,
+    pub output_tokens: u64,
+    pub reasoning_output_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl std::ops::AddAssign<&TokenUsage> for TokenUsage {
+    fn add_assign(&mut self, other: &TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.cached_input_tokens += other.cached_input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.reasoning_output_tokens += other.reasoning_output_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct TokenCountEvent {
+    /// Usage of the current task so far.
+    pub turn: TokenUsage,
+    /// Usage of the whole session so far, including resumed turns.
+    pub session: TokenUsage,
+    /// True if the provider did not report usage for the latest response
+    /// and it was estimated locally.
+    pub estimated: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExecApprovalRequestEvent {
+    /// Identifier for the associated tool call.
+    pub call_id: String,
+    /// The command to be executed.
+    pub command: Vec<String>,
+    /// The command's working directory.
+    pub cwd: PathBuf,
+    /// Optional human-readable reason for the approval (e.g. retry without sandbox).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApplyPatchApprovalRequestEvent {
+    /// Identifier for the associated tool call.
+    pub call_id: String,
+    /// Per-file changes the patch would make.
+    pub changes: HashMap<PathBuf, FileChange>,
+    /// Optional explanatory reason (e.g. request for extra write access).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// A change to a single file, as shown to the user before it is applied.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileChange {
+    Add {
+        content: String,
+    },
+    Delete,
+    Update {
+        unified_diff: String,
+        move_path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ContextCompactedEvent {
+    /// Estimated size of the history before compaction, in tokens.
+    pub tokens_before: u64,
+    /// Estimated size of the history after compaction, in tokens.
+    pub tokens_after: u64,
+    /// Number of conversation items that were replaced by the summary.
+    pub items_summarized: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryEntryEvent {
+    /// Offset that was requested.
+    pub offset: usize,
+    /// The entry, `None` if there is none at `offset`.
+    pub entry: Option<HistoryEntry>,
+}
+
+/// An entry of the global prompt history
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct HistoryEntry {
+    /// Session the prompt was sent in.
+    pub session_id: String,
+    /// Unix timestamp, in seconds.
+    pub ts: u64,
+    pub text: String,
 }
@@ -0,0 +1,154 @@
+//! Confinement of commands run by the agent.
+//!
+//! On Linux a [`SandboxPolicy`] is enforced on the child process right before
+//! it executes the command: Landlock limits where it may write and a seccomp
+//! filter refuses to create sockets that could reach the network. Both are
+//! inherited by everything the command starts and cannot be lifted again.
+//!
+//! The rules are built in the parent. Between `fork` and `exec` the child
+//! only installs them, since little else is safe to do there.
+
+use crate::protocol::SandboxPolicy;
+use std::{io, path::Path, process::Command};
+
+/// Build a command that runs `program` with `args` in `cwd` under `policy`.
+///
+/// Fails where sandboxing is not supported, unless `policy` grants full
+/// access. Spawning the command fails if the kernel cannot enforce the
+/// policy.
+pub fn sandboxed_command(
+    program: &str,
+    args: &[String],
+    cwd: &Path,
+    policy: &SandboxPolicy,
+) -> io::Result<Command> {
+    let mut cmd = Command::new(program);
+    cmd.args(args).current_dir(cwd);
+    if *policy != SandboxPolicy::DangerFullAccess {
+        confine(&mut cmd, policy, cwd)?;
+    }
+    Ok(cmd)
+}
+
+#[cfg(target_os = "linux")]
+fn confine(cmd: &mut Command, policy: &SandboxPolicy, cwd: &Path) -> io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let mut ruleset = if policy.has_full_disk_write_access() {
+        None
+    } else {
+        Some(linux::filesystem_ruleset(
+            &policy.writable_roots_with_cwd(cwd),
+        )?)
+    };
+    let filter = if policy.has_full_network_access() {
+        None
+    } else {
+        Some(linux::network_filter()?)
+    };
+
+    // SAFETY: the hook only makes syscalls with the rules prepared above;
+    // it allocates on error paths only.
+    unsafe {
+        cmd.pre_exec(move || {
+            // Landlock first: it also sets `no_new_privs`, which an
+            // unprivileged process needs to install a seccomp filter.
+            if let Some(ruleset) = ruleset.take() {
+                linux::restrict_self(ruleset)?;
+            }
+            if let Some(filter) = &filter {
+                seccompiler::apply_filter(filter).map_err(io::Error::other)?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn confine(_cmd: &mut Command, _policy: &SandboxPolicy, _cwd: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sandboxing is only supported on Linux; use the `danger-full-access` sandbox policy \
+         to run commands unconfined",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr,
+        RulesetCreated, RulesetCreatedAttr, RulesetError, RulesetStatus, ABI,
+    };
+    use seccompiler::{
+        BackendError, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+        SeccompFilter, SeccompRule, TargetArch,
+    };
+    use std::{collections::BTreeMap, io, path::PathBuf};
+
+    /// Newest Landlock ABI we know about; older kernels get what they support.
+    const LANDLOCK_ABI: ABI = ABI::V5;
+
+    /// Syscalls refused outright without network access. io_uring can
+    /// create and connect sockets without going through `socket`, out of
+    /// sight of the filter.
+    const NETWORK_SYSCALLS: &[i64] = &[libc::SYS_io_uring_setup];
+
+    /// Read access everywhere, write access below `writable_roots` and to
+    /// `/dev/null`.
+    pub(super) fn filesystem_ruleset(writable_roots: &[PathBuf]) -> io::Result<RulesetCreated> {
+        let build = || -> Result<RulesetCreated, RulesetError> {
+            let read_write = AccessFs::from_all(LANDLOCK_ABI);
+            let read_only = AccessFs::from_read(LANDLOCK_ABI);
+            Ruleset::default()
+                .set_compatibility(CompatLevel::BestEffort)
+                .handle_access(read_write)?
+                .create()?
+                .add_rules(path_beneath_rules(["/"], read_only))?
+                .add_rules(path_beneath_rules(["/dev/null"], read_write))?
+                .add_rules(path_beneath_rules(writable_roots, read_write))
+                .map(|ruleset| ruleset.set_no_new_privs(true))
+        };
+        build().map_err(io::Error::other)
+    }
+
+    pub(super) fn restrict_self(ruleset: RulesetCreated) -> io::Result<()> {
+        let status = ruleset.restrict_self().map_err(io::Error::other)?;
+        if status.ruleset == RulesetStatus::NotEnforced {
+            return Err(io::Error::other(
+                "the sandbox cannot be enforced: Landlock is not available in this kernel",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Filter failing the creation of sockets other than Unix sockets with
+    /// `EPERM`. Without such a socket nothing can reach the network, so the
+    /// calls that use sockets are left alone: Unix sockets stay fully usable
+    /// since many build tools use them to talk to themselves.
+    pub(super) fn network_filter() -> io::Result<BpfProgram> {
+        let build = || -> Result<BpfProgram, BackendError> {
+            let mut rules: BTreeMap<i64, Vec<SeccompRule>> = NETWORK_SYSCALLS
+                .iter()
+                .map(|&syscall| (syscall, Vec::new()))
+                .collect();
+            let not_unix = SeccompRule::new(vec![SeccompCondition::new(
+                0,
+                SeccompCmpArgLen::Dword,
+                SeccompCmpOp::Ne,
+                libc::AF_UNIX as u64,
+            )?])?;
+            rules.insert(libc::SYS_socket, vec![not_unix.clone()]);
+            rules.insert(libc::SYS_socketpair, vec![not_unix]);
+
+            SeccompFilter::new(
+                rules,
+                SeccompAction::Allow,
+                SeccompAction::Errno(libc::EPERM as u32),
+                TargetArch::try_from(std::env::consts::ARCH)?,
+            )?
+            .try_into()
+        };
+        build().map_err(io::Error::other)
+    }
+}
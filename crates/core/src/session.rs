@@ -0,0 +1,265 @@
+//! Session recording and resume.
+//!
+//! Every session is appended, one JSON object per line, to a file under
+//! `<data_dir>/sessions/`. The first line holds the [`SessionMeta`]; after it
+//! come the submissions, events and conversation items in the order the
+//! engine saw them. Only the conversation items are needed to continue a
+//! session, the rest is kept so a session can be inspected after the fact.
+
+use crate::{
+    error::Result,
+    models::ResponseItem,
+    protocol::{Event, EventMsg, ReviewDecision, Submission, TokenUsage},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+/// Directory below the data dir that session files are written to.
+pub const SESSIONS_SUBDIR: &str = "sessions";
+
+/// Metadata written as the first line of every session file
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct SessionMeta {
+    /// Unique id of the session.
+    pub id: String,
+    /// When the session was created.
+    pub timestamp: DateTime<Utc>,
+    /// Working directory the session was started in.
+    pub cwd: PathBuf,
+    /// Model the session was started with.
+    pub model: String,
+    /// Provider the session was started with.
+    pub provider_id: String,
+}
+
+/// One entry of a session file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum RecordedItem {
+    Meta(SessionMeta),
+    Submission(Submission),
+    Event(Event),
+    ResponseItem(ResponseItem),
+    /// The whole history after a compaction; replaces the items recorded
+    /// before it.
+    Compacted(Vec<ResponseItem>),
+    /// How an approval request was decided, including requests answered by
+    /// an earlier `ApprovedForSession`.
+    Approval {
+        call_id: String,
+        decision: ReviewDecision,
+    },
+}
+
+/// A line of a session file: a [`RecordedItem`] and when it was recorded.
+#[derive(Debug, Deserialize, Serialize)]
+struct RecordedLine {
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    item: RecordedItem,
+}
+
+/// A session file found by [`list_sessions`]
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub path: PathBuf,
+    pub meta: SessionMeta,
+}
+
+/// A session rebuilt from its file by [`load_session`]
+#[derive(Debug, Clone)]
+pub struct SavedSession {
+    pub meta: SessionMeta,
+    /// Conversation items to seed the history with, oldest first.
+    pub items: Vec<ResponseItem>,
+    /// Token usage of the session so far, from its last `TokenCount` event.
+    pub token_usage: TokenUsage,
+}
+
+/// Appends [`RecordedItem`]s to a session file
+///
+/// Writes happen on a background task so recording never blocks the engine;
+/// each line is flushed as soon as it is written.
+#[derive(Debug, Clone)]
+pub(crate) struct SessionRecorder {
+    tx: mpsc::UnboundedSender<RecordedItem>,
+}
+
+impl SessionRecorder {
+    /// Create a new session file under `data_dir` and write `meta` to it.
+    pub(crate) fn create(data_dir: &Path, meta: SessionMeta) -> io::Result<Self> {
+        let dir = data_dir.join(SESSIONS_SUBDIR);
+        fs::create_dir_all(&dir)?;
+        let file_name = format!(
+            "{}-{}.jsonl",
+            meta.timestamp.format("%Y-%m-%dT%H-%M-%S"),
+            meta.id
+        );
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(dir.join(file_name))?;
+
+        let recorder = Self::spawn(file);
+        recorder.record(RecordedItem::Meta(meta));
+        Ok(recorder)
+    }
+
+    /// Continue appending to an existing session file.
+    pub(crate) fn resume(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self::spawn(file))
+    }
+
+    fn spawn(file: File) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<RecordedItem>();
+        tokio::spawn(async move {
+            let mut file = tokio::fs::File::from_std(file);
+            while let Some(item) = rx.recv().await {
+                let line = RecordedLine {
+                    timestamp: Utc::now(),
+                    item,
+                };
+                let mut json = match serde_json::to_string(&line) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        log::error!("failed to serialize session item: {e}");
+                        continue;
+                    }
+                };
+                json.push('\n');
+                if let Err(e) = async {
+                    file.write_all(json.as_bytes()).await?;
+                    file.flush().await
+                }
+                .await
+                {
+                    log::error!("failed to write session file: {e}");
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    pub(crate) fn record(&self, item: RecordedItem) {
+        // Deltas are repeated in full by the final `AgentMessage` or
+        // `ToolCallEnd`.
+        if let RecordedItem::Event(Event {
+            msg: EventMsg::AgentMessageDelta(_) | EventMsg::ExecCommandOutputDelta(_),
+            ..
+        }) = item
+        {
+            return;
+        }
+        if self.tx.send(item).is_err() {
+            log::error!("session recorder stopped; item not recorded");
+        }
+    }
+}
+
+/// List the sessions recorded under `data_dir`, newest first.
+///
+/// Files that cannot be read or do not start with a [`SessionMeta`] are
+/// skipped.
+pub fn list_sessions(data_dir: &Path) -> io::Result<Vec<SessionInfo>> {
+    let dir = data_dir.join(SESSIONS_SUBDIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut sessions = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "jsonl") {
+            continue;
+        }
+        match read_meta(&path) {
+            Ok(meta) => sessions.push(SessionInfo { path, meta }),
+            Err(e) => log::warn!("skipping session file {}: {e}", path.display()),
+        }
+    }
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.meta.timestamp));
+    Ok(sessions)
+}
+
+/// Find a recorded session by its id, or by a unique prefix of it.
+pub fn find_session(data_dir: &Path, id: &str) -> io::Result<Option<SessionInfo>> {
+    let mut matches = list_sessions(data_dir)?
+        .into_iter()
+        .filter(|session| session.meta.id.starts_with(id));
+    match (matches.next(), matches.next()) {
+        (Some(session), None) => Ok(Some(session)),
+        (None, _) => Ok(None),
+        (Some(_), Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("session id `{id}` is ambiguous"),
+        )),
+    }
+}
+
+/// Rebuild a session from its file.
+///
+/// A torn last line (e.g. after a crash mid-write) is ignored.
+pub fn load_session(path: &Path) -> Result<SavedSession> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut meta = None;
+    let mut items = Vec::new();
+    let mut token_usage = TokenUsage::default();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let recorded = match serde_json::from_str::<RecordedLine>(&line) {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                log::warn!("skipping malformed line in {}: {e}", path.display());
+                continue;
+            }
+        };
+        match recorded.item {
+            RecordedItem::Meta(m) => meta = meta.or(Some(m)),
+            RecordedItem::ResponseItem(item) => items.push(item),
+            RecordedItem::Compacted(compacted) => items = compacted,
+            RecordedItem::Event(Event {
+                msg: EventMsg::TokenCount(count),
+                ..
+            }) => token_usage = count.session,
+            RecordedItem::Submission(_)
+            | RecordedItem::Event(_)
+            | RecordedItem::Approval { .. } => {}
+        }
+    }
+
+    let meta = meta.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a session file", path.display()),
+        )
+    })?;
+    Ok(SavedSession {
+        meta,
+        items,
+        token_usage,
+    })
+}
+
+fn read_meta(path: &Path) -> io::Result<SessionMeta> {
+    let mut first_line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut first_line)?;
+    match serde_json::from_str::<RecordedLine>(&first_line)?.item {
+        RecordedItem::Meta(meta) => Ok(meta),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "first line is not session metadata",
+        )),
+    }
+}
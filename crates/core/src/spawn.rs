@@ -0,0 +1,83 @@
+//! Spawning tool child processes.
+//!
+//! Every child runs in its own process group so an interrupt can take down
+//! the command together with anything it started (test runners, compilers,
+//! shells), not just the direct child.
+
+use crate::{protocol::SandboxPolicy, sandbox::sandboxed_command};
+use std::{
+    io,
+    path::Path,
+    process::{ExitStatus, Stdio},
+};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+/// Spawn `program` in `cwd` under `policy`, with piped stdout/stderr and no
+/// stdin.
+pub(crate) fn spawn_child_async(
+    program: &str,
+    args: &[String],
+    cwd: &Path,
+    policy: &SandboxPolicy,
+) -> io::Result<Child> {
+    let mut cmd = Command::from(sandboxed_command(program, args, cwd, policy)?);
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    // Lead a new process group so the whole tree can be signalled at once.
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    cmd.spawn()
+}
+
+/// Kill the process group led by the child with id `pgid`.
+///
+/// Takes the id rather than the `Child` because the group can outlive its
+/// leader: once the child is reaped `Child::id` returns `None`, while
+/// anything it backgrounded is still running.
+pub(crate) fn kill_process_group(pgid: Option<u32>) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(pgid) = pgid {
+        // SAFETY: plain syscall; the child leads its own group (see `spawn_child_async`).
+        let rc = unsafe { libc::killpg(pgid as libc::pid_t, libc::SIGKILL) };
+        if rc == -1 {
+            let err = io::Error::last_os_error();
+            // ESRCH: the group is already gone.
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(err);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = pgid;
+    Ok(())
+}
+
+/// Kill `child` together with its process group.
+pub(crate) fn kill_child_process_group(child: &mut Child) -> io::Result<()> {
+    kill_process_group(child.id())?;
+
+    // Covers non-unix platforms; an already exited child is not an error here.
+    let _ = child.start_kill();
+    Ok(())
+}
+
+/// Wait for `child` to exit. If `cancel` fires first the process group is
+/// killed and `None` is returned.
+pub(crate) async fn wait_or_kill(
+    child: &mut Child,
+    cancel: &CancellationToken,
+) -> io::Result<Option<ExitStatus>> {
+    tokio::select! {
+        status = child.wait() => status.map(Some),
+        _ = cancel.cancelled() => {
+            kill_child_process_group(child)?;
+            child.wait().await?;
+            Ok(None)
+        }
+    }
+}
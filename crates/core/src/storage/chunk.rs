@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+/// A piece of a source file, as stored in the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeChunk {
+    /// Unique within a table: the path and the line range.
+    pub id: String,
+    /// Path of the file, relative to the project root, with `/` separators.
+    pub path: String,
+    /// Lowercase language name, e.g. `rust`; `text` for unknown files.
+    pub language: String,
+    /// First and last line of the chunk in the file, 1-based.
+    pub start_line: u32,
+    pub end_line: u32,
+    /// The lines, after the signatures of the items they are in, if any.
+    pub content: String,
+    /// [`content_hash`] of `content`.
+    pub content_hash: String,
+    /// Name of the function, type or other item the chunk holds, if any.
+    pub symbol: Option<String>,
+    pub indexed_at: DateTime<Utc>,
+}
+
+impl CodeChunk {
+    /// Chunk of `content`, indexed now.
+    pub fn new(
+        path: impl Into<String>,
+        language: impl Into<String>,
+        start_line: u32,
+        end_line: u32,
+        content: impl Into<String>,
+        symbol: Option<String>,
+    ) -> Self {
+        let path = path.into();
+        let content = content.into();
+        Self {
+            id: format!("{path}:{start_line}-{end_line}"),
+            content_hash: content_hash(&content),
+            path,
+            language: language.into(),
+            start_line,
+            end_line,
+            content,
+            symbol,
+            indexed_at: Utc::now(),
+        }
+    }
+}
+
+/// Hex SHA-256 of `content`, to tell whether a chunk or file changed.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
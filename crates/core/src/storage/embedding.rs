@@ -0,0 +1,109 @@
+use crate::protocol::ModelProviderInfo;
+use color_eyre::{eyre::eyre, Result};
+use std::{collections::HashMap, fmt};
+
+/// Default for [`EmbeddingProvider::Hashing`]'s `dimensions`.
+pub const DEFAULT_HASHING_DIMENSIONS: usize = 384;
+
+/// How code chunks and search queries are turned into vectors
+///
+/// An index can only be searched with the provider it was built with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmbeddingProvider {
+    /// A local sentence-transformers model, downloaded on first use.
+    /// `None` picks the library's default model.
+    SentenceTransformers { model: Option<String> },
+
+    /// `POST /embeddings` of an OpenAI-compatible provider, e.g. OpenAI
+    /// itself or Ollama.
+    OpenAi {
+        provider: ModelProviderInfo,
+        model: String,
+    },
+
+    /// Hashed words and character trigrams; see [`hash_embedding`]. Needs
+    /// no model or network, so indexes can be built on CI and air-gapped
+    /// machines.
+    Hashing { dimensions: usize },
+}
+
+impl Default for EmbeddingProvider {
+    fn default() -> Self {
+        Self::SentenceTransformers { model: None }
+    }
+}
+
+impl EmbeddingProvider {
+    /// The provider `spec` names: `sentence-transformers[:MODEL]`,
+    /// `hashing`, or `PROVIDER:MODEL` for one of `providers`.
+    pub fn parse(spec: &str, providers: &HashMap<String, ModelProviderInfo>) -> Result<Self> {
+        let (name, model) = match spec.split_once(':') {
+            Some((name, model)) => (name, Some(model.to_string())),
+            None => (spec, None),
+        };
+        match (name, model) {
+            ("hashing", None) => Ok(Self::Hashing {
+                dimensions: DEFAULT_HASHING_DIMENSIONS,
+            }),
+            ("sentence-transformers", model) => Ok(Self::SentenceTransformers { model }),
+            (provider, Some(model)) => Ok(Self::OpenAi {
+                provider: providers
+                    .get(provider)
+                    .cloned()
+                    .ok_or_else(|| eyre!("unknown model provider `{provider}`"))?,
+                model,
+            }),
+            (_, None) => Err(eyre!(
+                "unknown embedder `{spec}`; use sentence-transformers, hashing or PROVIDER:MODEL"
+            )),
+        }
+    }
+}
+
+/// The spelling [`EmbeddingProvider::parse`] accepts.
+impl fmt::Display for EmbeddingProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SentenceTransformers { model: None } => write!(f, "sentence-transformers"),
+            Self::SentenceTransformers { model: Some(model) } => {
+                write!(f, "sentence-transformers:{model}")
+            }
+            Self::OpenAi { provider, model } => write!(f, "{}:{model}", provider.id),
+            Self::Hashing { .. } => write!(f, "hashing"),
+        }
+    }
+}
+
+/// Deterministic embedding of `text` with `dimensions` components.
+///
+/// Every lowercased word and each of its character trigrams is hashed to a
+/// component and a sign, and the sum is normalized to unit length. Texts
+/// that share identifiers end up close, which is enough for keyword-like
+/// queries; it does not know about synonyms.
+pub fn hash_embedding(text: &str, dimensions: usize) -> Vec<f32> {
+    let mut vector = vec![0.0_f32; dimensions];
+    if dimensions == 0 {
+        return vector;
+    }
+    let mut add = |feature: &[u8]| {
+        let hash = super::fnv1a(feature);
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % dimensions as u64) as usize] += sign;
+    };
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let word = word.to_lowercase();
+        add(word.as_bytes());
+        let padded: Vec<char> = format!("^{word}$").chars().collect();
+        for trigram in padded.windows(3) {
+            add(trigram.iter().collect::<String>().as_bytes());
+        }
+    }
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
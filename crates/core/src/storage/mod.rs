@@ -1,2 +1,33 @@
+mod chunk;
+mod embedding;
 mod vector;
-pub use vector::VectorDB;
+pub use chunk::{content_hash, CodeChunk};
+pub use embedding::{hash_embedding, EmbeddingProvider, DEFAULT_HASHING_DIMENSIONS};
+pub use lancedb::Table;
+pub use vector::{file_filter, SearchHit, VectorDB};
+
+use std::path::Path;
+
+/// Name of the table holding the index of the project at `root`.
+///
+/// Readable for humans, and unique per directory: the name of the
+/// directory followed by a hash of its full path.
+pub fn project_table_name(root: &Path) -> String {
+    let name: String = root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let hash = fnv1a(root.to_string_lossy().as_bytes());
+    format!("{name}_{hash:016x}")
+}
+
+/// FNV-1a hash of `bytes`; unlike `std`'s hashers it stays the same across
+/// Rust versions, so it can name things on disk.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
@@ -1,125 +1,323 @@
-#![allow(dead_code)]
-
-use arrow_array::{RecordBatch, RecordBatchIterator, StringArray};
-use arrow_schema::{DataType, Field, Schema};
-use color_eyre::Result;
+use super::embedding::{vector_type, Embedder};
+use crate::storage::{CodeChunk, EmbeddingProvider};
+use arrow_array::{
+    Array, Float32Array, RecordBatch, RecordBatchIterator, RecordBatchReader, StringArray,
+    TimestampMillisecondArray, UInt32Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use color_eyre::{eyre::eyre, Result};
 use futures::StreamExt;
 use lancedb::{
-    embeddings::{
-        sentence_transformers::SentenceTransformersEmbeddings, EmbeddingDefinition,
-        EmbeddingFunction,
-    },
     query::{ExecutableQuery, QueryBase},
-    Table,
+    DistanceType, Table,
 };
-use std::{collections::HashMap, iter::once, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+/// Column holding the vector of each chunk's `content`.
+const VECTOR_COLUMN: &str = "embeddings";
+
+/// Chunk ids per delete, which keeps its SQL predicate short.
+const DELETE_BATCH_SIZE: usize = 128;
+
+/// Schema metadata naming the embedder a table was built with.
+const EMBEDDER_METADATA_KEY: &str = "corty.embedder";
+
+/// One result of [`VectorDB::search`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    /// Id of the [`CodeChunk`] found.
+    pub id: String,
+    pub content: String,
+    /// File the chunk comes from, relative to the project root.
+    pub path: String,
+    /// First and last line of the chunk in the file, 1-based.
+    pub start_line: u32,
+    pub end_line: u32,
+    pub language: String,
+    pub symbol: Option<String>,
+    /// Cosine similarity to the query; 1.0 for the same direction.
+    pub score: f32,
+}
 
 pub struct VectorDB {
     path: String,
     db: lancedb::Connection,
-    embedding: Arc<SentenceTransformersEmbeddings>,
+    embedder: Embedder,
 }
 
 impl VectorDB {
-    pub async fn connect(path: &str) -> Result<Self> {
+    /// Whether the database at `path` has a table `name`, found without
+    /// loading an embedder.
+    pub async fn has_table(path: &str, name: &str) -> Result<bool> {
+        if !Path::new(path).is_dir() {
+            return Ok(false);
+        }
         let db = lancedb::connect(path).execute().await?;
-        let embedding = Arc::new(SentenceTransformersEmbeddings::builder().build()?);
-        db.embedding_registry()
-            .register("sentence-transformers", embedding.clone())?;
-        println!("Done new!");
+        let names = db.table_names().execute().await?;
+        Ok(names.iter().any(|existing| existing == name))
+    }
+
+    /// Opens the database at `path`, embedding with `provider`.
+    pub async fn connect(path: &str, provider: &EmbeddingProvider) -> Result<Self> {
+        let db = lancedb::connect(path).execute().await?;
+        let embedder = Embedder::connect(provider).await?;
         Ok(Self {
             path: path.to_string(),
             db,
-            embedding: embedding.clone(),
+            embedder,
         })
     }
 
-    pub async fn create_empty_table(&self, name: &str) -> Result<Table> {
-        // Create table with minimal data to establish embedding schema
-        let schema = Arc::new(Schema::new(vec![Field::new("item", DataType::Utf8, true)]));
-        let placeholder_values = StringArray::from_iter_values(vec!["__temp__".to_string()]);
-        let rb = RecordBatch::try_new(schema.clone(), vec![Arc::new(placeholder_values)]).unwrap();
-        let rb_iter = Box::new(RecordBatchIterator::new(vec![Ok(rb)], schema));
-
-        let table = self
+    /// The chunk table `name`, created empty if it does not exist yet.
+    pub async fn open_or_create_chunk_table(&self, name: &str) -> Result<Table> {
+        if let Some(table) = self.find_table(name).await? {
+            return Ok(table);
+        }
+        Ok(self
             .db
-            .create_table(name, rb_iter)
-            .add_embedding(EmbeddingDefinition::new(
-                "item",
-                "sentence-transformers",
-                Some("embeddings"),
-            ))?
+            .create_empty_table(name, self.chunk_schema())
             .execute()
-            .await?;
-
-        // Clear the placeholder data
-        table.delete("item = '__temp__'").await?;
-        Ok(table)
+            .await?)
     }
 
-    pub async fn create_table_with_data(
-        &self,
-        name: &str,
-        data: HashMap<String, String>,
-    ) -> Result<Table> {
-        let schema = Arc::new(Schema::new(vec![Field::new("item", DataType::Utf8, true)]));
-        let values = StringArray::from_iter_values(
-            data.iter()
-                .map(|f| f.1.to_string())
-                .collect::<Vec<String>>(),
-        );
-        let rb = RecordBatch::try_new(schema.clone(), vec![Arc::new(values)]).unwrap();
-        let rb_iter = Box::new(RecordBatchIterator::new(vec![Ok(rb)], schema));
-
-        let table = self
-            .db
-            .create_table(name, rb_iter)
-            .add_embedding(EmbeddingDefinition::new(
-                "item",
-                "sentence-transformers",
-                Some("embeddings"),
-            ))?
+    /// Embeds `chunks` and appends them to `table`.
+    pub async fn add_chunks(&self, table: &Table, chunks: &[CodeChunk]) -> Result<()> {
+        if chunks.is_empty() {
+            return Ok(());
+        }
+        let vectors = self
+            .embedder
+            .embed(chunks.iter().map(|chunk| chunk.content.clone()).collect())
+            .await?;
+        table
+            .add(chunk_batches(self.chunk_schema(), chunks, vectors)?)
             .execute()
             .await?;
-        Ok(table)
+        Ok(())
+    }
+
+    /// Removes every chunk of the files at `paths` from `table`.
+    pub async fn delete_files(&self, table: &Table, paths: &[String]) -> Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<String> = paths.iter().map(|path| sql_string(path)).collect();
+        table
+            .delete(&format!("path IN ({})", paths.join(", ")))
+            .await?;
+        Ok(())
     }
 
-    pub async fn insert(&self, table: &Table, data: HashMap<String, String>) -> Result<()> {
-        let schema = Arc::new(Schema::new(vec![Field::new("item", DataType::Utf8, true)]));
-        let values = StringArray::from_iter_values(
-            data.iter()
-                .map(|f| f.1.to_string())
-                .collect::<Vec<String>>(),
-        );
-        let rb = RecordBatch::try_new(schema.clone(), vec![Arc::new(values)]).unwrap();
-        let rb_iter = Box::new(RecordBatchIterator::new(vec![Ok(rb)], schema));
-        println!("Adding data");
-        table.add(rb_iter).execute().await?;
+    /// Removes the chunks with `ids` from `table`.
+    pub async fn delete_chunks(&self, table: &Table, ids: &[String]) -> Result<()> {
+        for batch in ids.chunks(DELETE_BATCH_SIZE) {
+            let ids: Vec<String> = batch.iter().map(|id| sql_string(id)).collect();
+            table.delete(&format!("id IN ({})", ids.join(", "))).await?;
+        }
         Ok(())
     }
 
-    pub async fn get_table(&self, name: &str) -> Table {
-        self.db.open_table(name).execute().await.unwrap()
+    /// Directory of the database.
+    pub fn path(&self) -> &str {
+        &self.path
     }
 
-    pub async fn query(&self, query: &str, table: &Table) -> Result<()> {
-        let query = Arc::new(StringArray::from_iter_values(once(query)));
-        let query_vector = self.embedding.compute_query_embeddings(query)?;
-        let mut results = table
+    /// The table `name`, or `None` if it has not been created yet.
+    ///
+    /// Fails if the table was built with another embedder, since its
+    /// vectors cannot be compared with this one's.
+    pub async fn find_table(&self, name: &str) -> Result<Option<Table>> {
+        let names = self.db.table_names().execute().await?;
+        if !names.iter().any(|existing| existing == name) {
+            return Ok(None);
+        }
+        let table = self.db.open_table(name).execute().await?;
+        let schema = table.schema().await?;
+        let embedder = schema
+            .metadata()
+            .get(EMBEDDER_METADATA_KEY)
+            .map_or("an unknown embedder", String::as_str);
+        let dimensions = match schema
+            .field_with_name(VECTOR_COLUMN)
+            .map(|field| field.data_type())
+        {
+            Ok(DataType::FixedSizeList(_, dimensions)) => *dimensions as usize,
+            _ => 0,
+        };
+        if embedder != self.embedder.id || dimensions != self.embedder.dimensions {
+            return Err(eyre!(
+                "the index {} was built with {embedder} ({dimensions} dimensions), not with \
+                 {} ({} dimensions); use that embedder, or delete the index to rebuild it",
+                Path::new(&self.path)
+                    .join(format!("{name}.lance"))
+                    .display(),
+                self.embedder.id,
+                self.embedder.dimensions
+            ));
+        }
+        Ok(Some(table))
+    }
+
+    /// The `limit` rows of `table` closest to `query`, best first.
+    ///
+    /// `filter` is an SQL predicate on the table's columns, e.g.
+    /// `language = 'rust'` or a [`file_filter`], applied before the nearest
+    /// rows are picked.
+    pub async fn search(
+        &self,
+        table: &Table,
+        query: &str,
+        limit: usize,
+        filter: Option<&str>,
+    ) -> Result<Vec<SearchHit>> {
+        let query_vector = self.embedder.embed_query(query).await?;
+        let mut search = table
             .vector_search(query_vector)?
-            .limit(1)
-            .execute()
-            .await?;
+            .column(VECTOR_COLUMN)
+            .distance_type(DistanceType::Cosine)
+            .limit(limit);
+        if let Some(filter) = filter {
+            search = search.only_if(filter);
+        }
+        let mut results = search.execute().await?;
 
-        let rb = results.next().await.unwrap()?;
-        let out = rb
-            .column_by_name("item")
-            .unwrap()
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .unwrap();
-        let text = out.iter().next().unwrap().unwrap();
-        println!("Answer: {}", text);
-        Ok(())
+        let mut hits = Vec::new();
+        while let Some(batch) = results.next().await {
+            hits.extend(search_hits(&batch?));
+        }
+        Ok(hits)
+    }
+
+    /// Columns of a chunk table, tagged with the embedder.
+    fn chunk_schema(&self) -> SchemaRef {
+        let mut fields = chunk_fields();
+        fields.push(Field::new(
+            VECTOR_COLUMN,
+            vector_type(self.embedder.dimensions),
+            true,
+        ));
+        let metadata =
+            HashMap::from([(EMBEDDER_METADATA_KEY.to_string(), self.embedder.id.clone())]);
+        Arc::new(Schema::new_with_metadata(fields, metadata))
     }
 }
+
+/// SQL predicate selecting the chunks of the file at `path`.
+pub fn file_filter(path: &str) -> String {
+    format!("path = {}", sql_string(path))
+}
+
+/// `value` as an SQL string literal.
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Columns of a chunk table, besides the vector of `content`.
+fn chunk_fields() -> Vec<Field> {
+    vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("language", DataType::Utf8, false),
+        Field::new("start_line", DataType::UInt32, false),
+        Field::new("end_line", DataType::UInt32, false),
+        Field::new("content", DataType::Utf8, false),
+        Field::new("content_hash", DataType::Utf8, false),
+        Field::new("symbol", DataType::Utf8, true),
+        Field::new(
+            "indexed_at",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+    ]
+}
+
+/// `chunks` with their `vectors` as rows of a table with `schema`.
+fn chunk_batches(
+    schema: SchemaRef,
+    chunks: &[CodeChunk],
+    vectors: Arc<dyn Array>,
+) -> Result<Box<dyn RecordBatchReader + Send>> {
+    let strings = |field: fn(&CodeChunk) -> &str| {
+        Arc::new(StringArray::from_iter_values(chunks.iter().map(field)))
+    };
+    let numbers = |field: fn(&CodeChunk) -> u32| {
+        Arc::new(UInt32Array::from_iter_values(chunks.iter().map(field)))
+    };
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            strings(|chunk| &chunk.id),
+            strings(|chunk| &chunk.path),
+            strings(|chunk| &chunk.language),
+            numbers(|chunk| chunk.start_line),
+            numbers(|chunk| chunk.end_line),
+            strings(|chunk| &chunk.content),
+            strings(|chunk| &chunk.content_hash),
+            Arc::new(StringArray::from_iter(
+                chunks.iter().map(|chunk| chunk.symbol.as_deref()),
+            )),
+            Arc::new(
+                TimestampMillisecondArray::from_iter_values(
+                    chunks
+                        .iter()
+                        .map(|chunk| chunk.indexed_at.timestamp_millis()),
+                )
+                .with_timezone("UTC"),
+            ),
+            vectors,
+        ],
+    )?;
+    Ok(Box::new(RecordBatchIterator::new(vec![Ok(batch)], schema)))
+}
+
+/// Rows of a search result batch.
+fn search_hits(batch: &RecordBatch) -> Vec<SearchHit> {
+    let strings = |name: &str| {
+        batch
+            .column_by_name(name)
+            .and_then(|column| column.as_any().downcast_ref::<StringArray>())
+    };
+    let numbers = |name: &str| {
+        batch
+            .column_by_name(name)
+            .and_then(|column| column.as_any().downcast_ref::<UInt32Array>())
+    };
+    let (
+        Some(id),
+        Some(content),
+        Some(path),
+        Some(language),
+        Some(symbol),
+        Some(start_line),
+        Some(end_line),
+        Some(distance),
+    ) = (
+        strings("id"),
+        strings("content"),
+        strings("path"),
+        strings("language"),
+        strings("symbol"),
+        numbers("start_line"),
+        numbers("end_line"),
+        batch
+            .column_by_name("_distance")
+            .and_then(|column| column.as_any().downcast_ref::<Float32Array>()),
+    )
+    else {
+        log::warn!("search results lack chunk columns; was the table built by `corty index`?");
+        return Vec::new();
+    };
+
+    (0..batch.num_rows())
+        .map(|row| SearchHit {
+            id: id.value(row).to_string(),
+            content: content.value(row).to_string(),
+            path: path.value(row).to_string(),
+            start_line: start_line.value(row),
+            end_line: end_line.value(row),
+            language: language.value(row).to_string(),
+            symbol: symbol.is_valid(row).then(|| symbol.value(row).to_string()),
+            score: 1.0 - distance.value(row),
+        })
+        .collect()
+}
@@ -0,0 +1,283 @@
+//! Computing embeddings for each [`EmbeddingProvider`].
+
+use crate::{
+    protocol::ModelProviderInfo,
+    storage::{hash_embedding, EmbeddingProvider},
+};
+use arrow_array::{cast::AsArray, types::Float32Type, Array, FixedSizeListArray, StringArray};
+use arrow_schema::{DataType, Field};
+use color_eyre::{eyre::eyre, Result};
+use lancedb::embeddings::{
+    sentence_transformers::SentenceTransformersEmbeddings, EmbeddingFunction,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{borrow::Cow, sync::Arc};
+
+/// Texts sent to an embeddings endpoint per request.
+const OPENAI_BATCH_SIZE: usize = 64;
+
+/// Turns chunks and queries into vectors for a [`super::VectorDB`]
+///
+/// Embeddings are computed before rows reach lancedb rather than by an
+/// embedding definition on the table: lancedb calls those synchronously
+/// from within the runtime, where a provider reached over HTTP cannot be
+/// awaited.
+pub(super) struct Embedder {
+    /// [`EmbeddingProvider`]'s `--embedder` spelling, recorded in the tables
+    /// built with it.
+    pub(super) id: String,
+    pub(super) dimensions: usize,
+    backend: Backend,
+}
+
+enum Backend {
+    /// Computes on the CPU, so it runs on the blocking thread pool.
+    Local(Arc<dyn EmbeddingFunction>),
+    OpenAi(OpenAiEmbeddings),
+}
+
+impl Embedder {
+    /// Loads the model of `provider`, or learns its dimensions from a first
+    /// request.
+    pub(super) async fn connect(provider: &EmbeddingProvider) -> Result<Self> {
+        let backend = match provider {
+            EmbeddingProvider::SentenceTransformers { model } => {
+                let mut builder = SentenceTransformersEmbeddings::builder();
+                if let Some(model) = model {
+                    builder = builder.model(model.clone());
+                }
+                let function = tokio::task::spawn_blocking(move || builder.build()).await??;
+                Backend::Local(Arc::new(function))
+            }
+            EmbeddingProvider::OpenAi { provider, model } => {
+                Backend::OpenAi(OpenAiEmbeddings::connect(provider, model).await?)
+            }
+            EmbeddingProvider::Hashing { dimensions } => {
+                Backend::Local(Arc::new(HashingEmbeddings {
+                    dimensions: *dimensions,
+                }))
+            }
+        };
+        let dimensions = match &backend {
+            Backend::Local(function) => match function.dest_type()?.as_ref() {
+                DataType::FixedSizeList(_, dimensions) => *dimensions as usize,
+                other => return Err(eyre!("embeddings of unexpected type {other}")),
+            },
+            Backend::OpenAi(embeddings) => embeddings.dimensions,
+        };
+        Ok(Self {
+            id: provider.to_string(),
+            dimensions,
+            backend,
+        })
+    }
+
+    /// Vectors of `texts` as stored in a table, in the same order.
+    pub(super) async fn embed(&self, texts: Vec<String>) -> Result<Arc<dyn Array>> {
+        self.compute(texts, false).await
+    }
+
+    /// Vector of the search query `query`.
+    pub(super) async fn embed_query(&self, query: &str) -> Result<Arc<dyn Array>> {
+        self.compute(vec![query.to_string()], true).await
+    }
+
+    async fn compute(&self, texts: Vec<String>, query: bool) -> Result<Arc<dyn Array>> {
+        match &self.backend {
+            Backend::Local(function) => {
+                let function = function.clone();
+                let input: Arc<dyn Array> = Arc::new(StringArray::from(texts));
+                let vectors = tokio::task::spawn_blocking(move || {
+                    if query {
+                        function.compute_query_embeddings(input)
+                    } else {
+                        function.compute_source_embeddings(input)
+                    }
+                })
+                .await??;
+                as_vectors(vectors.as_ref(), self.dimensions)
+            }
+            Backend::OpenAi(embeddings) => embeddings.embed(&texts).await,
+        }
+    }
+}
+
+/// Strings of a source or query array.
+fn texts(input: &dyn Array) -> lancedb::Result<Vec<String>> {
+    let texts: Vec<String> = match input.data_type() {
+        DataType::Utf8 => input
+            .as_string::<i32>()
+            .iter()
+            .map(Option::unwrap_or_default)
+            .map(str::to_string)
+            .collect(),
+        DataType::LargeUtf8 => input
+            .as_string::<i64>()
+            .iter()
+            .map(Option::unwrap_or_default)
+            .map(str::to_string)
+            .collect(),
+        other => {
+            return Err(lancedb::Error::InvalidInput {
+                message: format!("cannot embed a column of type {other}"),
+            })
+        }
+    };
+    Ok(texts)
+}
+
+pub(super) fn vector_type(dimensions: usize) -> DataType {
+    DataType::new_fixed_size_list(DataType::Float32, dimensions as i32, true)
+}
+
+/// `array` as [`vector_type`], whatever the embedding function named the
+/// list's items.
+fn as_vectors(array: &dyn Array, dimensions: usize) -> Result<Arc<dyn Array>> {
+    let list = array
+        .as_fixed_size_list_opt()
+        .ok_or_else(|| eyre!("embeddings of unexpected type {}", array.data_type()))?;
+    Ok(Arc::new(FixedSizeListArray::try_new(
+        Arc::new(Field::new("item", DataType::Float32, true)),
+        dimensions as i32,
+        list.values().clone(),
+        list.nulls().cloned(),
+    )?))
+}
+
+fn vectors(vectors: Vec<Vec<f32>>, dimensions: usize) -> Arc<dyn Array> {
+    Arc::new(
+        FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
+            vectors
+                .into_iter()
+                .map(|vector| Some(vector.into_iter().map(Some))),
+            dimensions as i32,
+        ),
+    )
+}
+
+/// [`EmbeddingProvider::Hashing`]
+#[derive(Debug)]
+struct HashingEmbeddings {
+    dimensions: usize,
+}
+
+impl HashingEmbeddings {
+    fn embed(&self, input: Arc<dyn Array>) -> lancedb::Result<Arc<dyn Array>> {
+        let embeddings = texts(&input)?
+            .iter()
+            .map(|text| hash_embedding(text, self.dimensions))
+            .collect();
+        Ok(vectors(embeddings, self.dimensions))
+    }
+}
+
+impl EmbeddingFunction for HashingEmbeddings {
+    fn name(&self) -> &str {
+        "hashing"
+    }
+
+    fn source_type(&self) -> lancedb::Result<Cow<DataType>> {
+        Ok(Cow::Owned(DataType::Utf8))
+    }
+
+    fn dest_type(&self) -> lancedb::Result<Cow<DataType>> {
+        Ok(Cow::Owned(vector_type(self.dimensions)))
+    }
+
+    fn compute_source_embeddings(&self, source: Arc<dyn Array>) -> lancedb::Result<Arc<dyn Array>> {
+        self.embed(source)
+    }
+
+    fn compute_query_embeddings(&self, input: Arc<dyn Array>) -> lancedb::Result<Arc<dyn Array>> {
+        self.embed(input)
+    }
+}
+
+/// [`EmbeddingProvider::OpenAi`]
+///
+/// lancedb's own OpenAI function only knows OpenAI's model names, so it
+/// cannot talk to other compatible providers.
+struct OpenAiEmbeddings {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+    /// Learned from a first request, since it depends on the model.
+    dimensions: usize,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl OpenAiEmbeddings {
+    async fn connect(provider: &ModelProviderInfo, model: &str) -> Result<Self> {
+        let mut embeddings = Self {
+            client: reqwest::Client::new(),
+            url: format!("{}/embeddings", provider.base_url.trim_end_matches('/')),
+            api_key: provider.api_key()?,
+            model: model.to_string(),
+            dimensions: 0,
+        };
+        let probe = embeddings.request(vec!["dimensions".to_string()]).await?;
+        embeddings.dimensions = probe.first().map(Vec::len).unwrap_or_default();
+        if embeddings.dimensions == 0 {
+            return Err(eyre!("{} returned an empty embedding", embeddings.url));
+        }
+        Ok(embeddings)
+    }
+
+    async fn request(&self, input: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let expected = input.len();
+        let mut request = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "model": self.model, "input": input }));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(eyre!("{} returned {status}: {body}", self.url));
+        }
+        let mut data = response.json::<EmbeddingsResponse>().await?.data;
+        if data.len() != expected {
+            return Err(eyre!(
+                "{} returned {} embeddings for {expected} inputs",
+                self.url,
+                data.len()
+            ));
+        }
+        data.sort_by_key(|item| item.index);
+        Ok(data.into_iter().map(|item| item.embedding).collect())
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Arc<dyn Array>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(OPENAI_BATCH_SIZE) {
+            embeddings.extend(
+                self.request(batch.to_vec())
+                    .await
+                    .map_err(|e| eyre!("embedding request failed: {e}"))?,
+            );
+        }
+        if let Some(vector) = embeddings.iter().find(|v| v.len() != self.dimensions) {
+            return Err(eyre!(
+                "expected embeddings of {} dimensions, got {}",
+                self.dimensions,
+                vector.len()
+            ));
+        }
+        Ok(vectors(embeddings, self.dimensions))
+    }
+}
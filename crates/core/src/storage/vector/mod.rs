@@ -1,2 +1,3 @@
 mod db;
-pub use db::VectorDB;
+mod embedding;
+pub use db::{file_filter, SearchHit, VectorDB};
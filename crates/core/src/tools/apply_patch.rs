@@ -0,0 +1,637 @@
+//! `apply_patch`: edit files with a context-based patch.
+//!
+//! The patch format is described in `apply_patch_tool.md`, which doubles as
+//! the tool description. Hunks are located by their context lines instead of
+//! line numbers, with increasingly lenient whitespace matching, so a patch
+//! still applies after unrelated edits elsewhere in the file.
+
+use super::{
+    check_decision, parse_arguments, resolve_workspace_path, ToolContext, ToolHandler, ToolOutput,
+    ToolSpec,
+};
+use crate::protocol::{AskForApproval, FileChange, SandboxPolicy};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use similar::TextDiff;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+};
+
+const DESCRIPTION: &str = include_str!("../../apply_patch_tool.md");
+
+const BEGIN_PATCH: &str = "*** Begin Patch";
+const END_PATCH: &str = "*** End Patch";
+const ADD_FILE: &str = "*** Add File: ";
+const DELETE_FILE: &str = "*** Delete File: ";
+const UPDATE_FILE: &str = "*** Update File: ";
+const MOVE_TO: &str = "*** Move to: ";
+const END_OF_FILE: &str = "*** End of File";
+
+pub(crate) struct ApplyPatchTool;
+
+#[derive(Debug, Deserialize)]
+struct ApplyPatchArgs {
+    patch: String,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[async_trait]
+impl ToolHandler for ApplyPatchTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "apply_patch".to_string(),
+            description: DESCRIPTION.to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "patch": {
+                        "type": "string",
+                        "description": "The patch, from `*** Begin Patch` to `*** End Patch`."
+                    },
+                    "dry_run": {
+                        "type": "boolean",
+                        "description": "Check the patch and report the changes without writing them."
+                    }
+                },
+                "required": ["patch"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let args: ApplyPatchArgs = match parse_arguments("apply_patch", arguments) {
+            Ok(args) => args,
+            Err(output) => return output,
+        };
+        let patch = match PreparedPatch::prepare(ctx.cwd, &args.patch).await {
+            Ok(patch) => patch,
+            Err(e) => return ToolOutput::error(format!("patch not applied: {e}")),
+        };
+        if args.dry_run {
+            return ToolOutput::success(format!(
+                "Dry run, nothing was written.\n{}",
+                patch.describe(true)
+            ));
+        }
+
+        // Patches are written by corty itself, outside the sandbox, so a
+        // read-only sandbox needs the user's permission to be lifted.
+        let read_only = *ctx.sandbox_policy == SandboxPolicy::ReadOnly;
+        let ask = match ctx.approval_policy {
+            AskForApproval::Always | AskForApproval::UnlessTrusted => true,
+            AskForApproval::OnFailure => read_only,
+            AskForApproval::Never if read_only => {
+                return ToolOutput::error(
+                    "patch not applied: the sandbox is read-only and approval is never asked",
+                )
+            }
+            AskForApproval::Never => false,
+        };
+        if ask {
+            let reason = read_only.then_some("The sandbox is read-only. Write these files anyway?");
+            let decision = ctx
+                .sess
+                .request_patch_approval(
+                    ctx.sub_id,
+                    ctx.call_id,
+                    patch.changes(),
+                    reason.map(str::to_string),
+                    ctx.cancel,
+                )
+                .await;
+            if let Err(output) = check_decision(decision, "apply this patch") {
+                return output;
+            }
+        }
+
+        match patch.apply().await {
+            Ok(()) => ToolOutput::success(format!("Patch applied.\n{}", patch.describe(false))),
+            Err(e) => ToolOutput::error(e),
+        }
+    }
+}
+
+/// One file section of a patch
+#[derive(Debug, PartialEq)]
+enum PatchOp {
+    Add {
+        path: String,
+        content: String,
+    },
+    Delete {
+        path: String,
+    },
+    Update {
+        path: String,
+        move_path: Option<String>,
+        chunks: Vec<Chunk>,
+    },
+}
+
+/// One `@@` hunk of an updated file
+#[derive(Debug, Default, PartialEq)]
+struct Chunk {
+    /// Line from the `@@` header that comes before the change.
+    anchor: Option<String>,
+    lines: Vec<HunkLine>,
+    /// The hunk must match at the end of the file.
+    end_of_file: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+/// Lines `hunk` expects to find in the file.
+fn old_lines(hunk: &[HunkLine]) -> Vec<String> {
+    hunk.iter()
+        .filter_map(|line| match line {
+            HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.clone()),
+            HunkLine::Add(_) => None,
+        })
+        .collect()
+}
+
+fn parse_patch(patch: &str) -> Result<Vec<PatchOp>, String> {
+    let lines: Vec<&str> = patch.trim().lines().collect();
+    let body = match lines.as_slice() {
+        [first, body @ .., last] if first.trim() == BEGIN_PATCH && last.trim() == END_PATCH => body,
+        _ => {
+            return Err(format!(
+                "a patch must start with `{BEGIN_PATCH}` and end with `{END_PATCH}`"
+            ))
+        }
+    };
+    // Line numbers in errors count from the `Begin Patch` line.
+    let line_no = |index: usize| index + 2;
+
+    let mut ops = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        let header = body[i];
+        i += 1;
+        if let Some(path) = header.strip_prefix(ADD_FILE) {
+            let mut content = String::new();
+            while i < body.len() && !body[i].starts_with("*** ") {
+                let Some(line) = body[i].strip_prefix('+') else {
+                    return Err(format!(
+                        "line {}: lines of an added file must start with `+`",
+                        line_no(i)
+                    ));
+                };
+                content.push_str(line);
+                content.push('\n');
+                i += 1;
+            }
+            ops.push(PatchOp::Add {
+                path: path.trim().to_string(),
+                content,
+            });
+        } else if let Some(path) = header.strip_prefix(DELETE_FILE) {
+            ops.push(PatchOp::Delete {
+                path: path.trim().to_string(),
+            });
+        } else if let Some(path) = header.strip_prefix(UPDATE_FILE) {
+            let move_path = match body.get(i).and_then(|line| line.strip_prefix(MOVE_TO)) {
+                Some(to) => {
+                    i += 1;
+                    Some(to.trim().to_string())
+                }
+                None => None,
+            };
+
+            let mut chunks = Vec::new();
+            let mut chunk: Option<Chunk> = None;
+            while i < body.len() {
+                let line = body[i];
+                if line.trim() == END_OF_FILE {
+                    match chunk.as_mut() {
+                        Some(chunk) => chunk.end_of_file = true,
+                        None => {
+                            return Err(format!(
+                                "line {}: `{END_OF_FILE}` outside of a hunk",
+                                line_no(i)
+                            ))
+                        }
+                    }
+                    i += 1;
+                    continue;
+                }
+                if line.starts_with("*** ") {
+                    break;
+                }
+                if let Some(anchor) = line.strip_prefix("@@") {
+                    chunks.extend(chunk.take());
+                    let anchor = anchor.trim();
+                    chunk = Some(Chunk {
+                        anchor: (!anchor.is_empty()).then(|| anchor.to_string()),
+                        ..Chunk::default()
+                    });
+                    i += 1;
+                    continue;
+                }
+
+                let current = chunk.get_or_insert_with(Chunk::default);
+                match line.chars().next() {
+                    Some('+') => current.lines.push(HunkLine::Add(line[1..].to_string())),
+                    Some('-') => current.lines.push(HunkLine::Remove(line[1..].to_string())),
+                    Some(' ') => current.lines.push(HunkLine::Context(line[1..].to_string())),
+                    // Models often drop the leading space of empty context lines.
+                    None => current.lines.push(HunkLine::Context(String::new())),
+                    Some(_) => {
+                        return Err(format!(
+                            "line {}: hunk lines must start with ` `, `-` or `+`",
+                            line_no(i)
+                        ))
+                    }
+                }
+                i += 1;
+            }
+            chunks.extend(chunk);
+            if chunks.is_empty() && move_path.is_none() {
+                return Err(format!("update of {} has no hunks", path.trim()));
+            }
+            ops.push(PatchOp::Update {
+                path: path.trim().to_string(),
+                move_path,
+                chunks,
+            });
+        } else if !header.trim().is_empty() {
+            return Err(format!(
+                "line {}: expected a file header, found `{header}`",
+                line_no(i - 1)
+            ));
+        }
+    }
+
+    if ops.is_empty() {
+        return Err("the patch contains no changes".to_string());
+    }
+    Ok(ops)
+}
+
+/// Line comparisons tried in order, from exact to most lenient.
+const NORMALIZERS: [fn(&str) -> String; 4] = [
+    |line| line.to_string(),
+    |line| line.trim_end().to_string(),
+    |line| line.split_whitespace().collect::<Vec<_>>().join(" "),
+    |line| {
+        line.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .map(|c| match c {
+                '\u{2010}'..='\u{2015}' | '\u{2212}' => '-',
+                '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => '\'',
+                '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => '"',
+                '\u{00A0}' | '\u{2002}'..='\u{200A}' | '\u{202F}' => ' ',
+                c => c,
+            })
+            .collect()
+    },
+];
+
+/// Index of the first occurrence of `pattern` in `lines` at or after `start`.
+/// With `end_of_file` a match that ends on the last line is preferred.
+fn seek(lines: &[String], pattern: &[String], start: usize, end_of_file: bool) -> Option<usize> {
+    if pattern.is_empty() {
+        return Some(start);
+    }
+    let last = lines.len().checked_sub(pattern.len())?;
+    for normalize in NORMALIZERS {
+        let pattern: Vec<String> = pattern.iter().map(|line| normalize(line)).collect();
+        let matches_at = |index: usize| {
+            lines[index..index + pattern.len()]
+                .iter()
+                .zip(&pattern)
+                .all(|(line, expected)| normalize(line) == *expected)
+        };
+        if end_of_file && last >= start && matches_at(last) {
+            return Some(last);
+        }
+        if let Some(index) = (start..=last).find(|&index| matches_at(index)) {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Apply the hunks of an update to `original`.
+fn apply_chunks(path: &str, original: &str, chunks: &[Chunk]) -> Result<String, String> {
+    let crlf = original.contains("\r\n");
+    // Files without a final newline keep lacking one.
+    let final_newline = original.is_empty() || original.ends_with('\n');
+    let mut lines: Vec<String> = original
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect();
+    if lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut replacements = Vec::new();
+    let mut cursor = 0;
+    for (n, chunk) in chunks.iter().enumerate() {
+        if let Some(anchor) = &chunk.anchor {
+            match seek(&lines, std::slice::from_ref(anchor), cursor, false) {
+                Some(index) => cursor = index + 1,
+                None => {
+                    return Err(format!(
+                        "{path}: hunk {} refers to `{anchor}`, which is not in the file",
+                        n + 1
+                    ))
+                }
+            }
+        }
+        let mut hunk = chunk.lines.as_slice();
+        let mut old = old_lines(hunk);
+        if old.is_empty() {
+            // Pure additions go after the anchor, or at the end of the file.
+            let at = if chunk.anchor.is_some() {
+                cursor
+            } else {
+                lines.len()
+            };
+            replacements.push((at, 0, new_lines(hunk, &[])));
+            continue;
+        }
+
+        let mut found = seek(&lines, &old, cursor, chunk.end_of_file);
+        // A trailing empty context line usually stands for the end of the file.
+        if found.is_none() && hunk.last() == Some(&HunkLine::Context(String::new())) {
+            hunk = &hunk[..hunk.len() - 1];
+            old = old_lines(hunk);
+            found = seek(&lines, &old, cursor, chunk.end_of_file);
+        }
+        let Some(index) = found else {
+            return Err(format!(
+                "{path}: hunk {} does not match the file; expected:\n{}",
+                n + 1,
+                old_lines(&chunk.lines).join("\n")
+            ));
+        };
+        let matched = &lines[index..index + old.len()];
+        replacements.push((index, old.len(), new_lines(hunk, matched)));
+        cursor = index + old.len();
+    }
+
+    replacements.sort_by_key(|&(index, _, _)| index);
+    for (index, len, new) in replacements.into_iter().rev() {
+        lines.splice(index..index + len, new);
+    }
+    if lines.is_empty() {
+        return Ok(String::new());
+    }
+    let newline = if crlf { "\r\n" } else { "\n" };
+    let mut content = lines.join(newline);
+    if final_newline {
+        content.push_str(newline);
+    }
+    Ok(content)
+}
+
+/// Replacement for the `matched` file lines. Context lines keep the file's
+/// version, which may differ from the patch in whitespace.
+fn new_lines(hunk: &[HunkLine], matched: &[String]) -> Vec<String> {
+    let mut matched = matched.iter();
+    let mut new = Vec::new();
+    for line in hunk {
+        match line {
+            HunkLine::Context(_) => new.extend(matched.next().cloned()),
+            HunkLine::Remove(_) => {
+                matched.next();
+            }
+            HunkLine::Add(text) => new.push(text.clone()),
+        }
+    }
+    new
+}
+
+/// A file the patch changes, as shown to the user
+#[derive(Debug)]
+struct PlannedChange {
+    /// Resolved path of the file.
+    path: PathBuf,
+    /// Path as written in the patch.
+    display: String,
+    /// Move target as written in the patch.
+    move_display: Option<String>,
+    change: FileChange,
+}
+
+/// A write to carry out; `None` removes the file.
+#[derive(Debug)]
+struct FileEdit {
+    path: PathBuf,
+    content: Option<String>,
+}
+
+/// A patch checked against the workspace, ready to be written
+#[derive(Debug)]
+pub(crate) struct PreparedPatch {
+    changes: Vec<PlannedChange>,
+    edits: Vec<FileEdit>,
+}
+
+impl PreparedPatch {
+    /// Parse `patch` and work out the new contents of every file it touches
+    /// without writing anything.
+    pub(crate) async fn prepare(cwd: &Path, patch: &str) -> Result<Self, String> {
+        let mut prepared = Self {
+            changes: Vec::new(),
+            edits: Vec::new(),
+        };
+        let mut touched = HashSet::new();
+        let mut resolve = |path: &str| -> Result<PathBuf, String> {
+            let resolved = resolve_workspace_path(cwd, path)?;
+            if !touched.insert(resolved.clone()) {
+                return Err(format!("{path} is changed more than once"));
+            }
+            Ok(resolved)
+        };
+
+        for op in parse_patch(patch)? {
+            match op {
+                PatchOp::Add { path, content } => {
+                    let resolved = resolve(&path)?;
+                    if resolved.exists() {
+                        return Err(format!("{path} already exists; update it instead"));
+                    }
+                    prepared.edits.push(FileEdit {
+                        path: resolved.clone(),
+                        content: Some(content.clone()),
+                    });
+                    prepared.changes.push(PlannedChange {
+                        path: resolved,
+                        display: path,
+                        move_display: None,
+                        change: FileChange::Add { content },
+                    });
+                }
+                PatchOp::Delete { path } => {
+                    let resolved = resolve(&path)?;
+                    if !resolved.is_file() {
+                        return Err(format!("{path} does not exist or is not a file"));
+                    }
+                    prepared.edits.push(FileEdit {
+                        path: resolved.clone(),
+                        content: None,
+                    });
+                    prepared.changes.push(PlannedChange {
+                        path: resolved,
+                        display: path,
+                        move_display: None,
+                        change: FileChange::Delete,
+                    });
+                }
+                PatchOp::Update {
+                    path,
+                    move_path,
+                    chunks,
+                } => {
+                    let resolved = resolve(&path)?;
+                    let original = tokio::fs::read_to_string(&resolved)
+                        .await
+                        .map_err(|e| format!("cannot read {path}: {e}"))?;
+                    let content = apply_chunks(&path, &original, &chunks)?;
+
+                    let target = match &move_path {
+                        Some(to) => {
+                            let target = resolve(to)?;
+                            if target.exists() {
+                                return Err(format!(
+                                    "cannot move {path} to {to}: it already exists"
+                                ));
+                            }
+                            prepared.edits.push(FileEdit {
+                                path: resolved.clone(),
+                                content: None,
+                            });
+                            target
+                        }
+                        None => resolved.clone(),
+                    };
+                    let unified_diff = TextDiff::from_lines(&original, &content)
+                        .unified_diff()
+                        .context_radius(3)
+                        .header(&path, move_path.as_deref().unwrap_or(&path))
+                        .to_string();
+                    prepared.edits.push(FileEdit {
+                        path: target.clone(),
+                        content: Some(content),
+                    });
+                    let move_target = (target != resolved).then_some(target);
+                    prepared.changes.push(PlannedChange {
+                        path: resolved,
+                        display: path,
+                        move_display: move_path,
+                        change: FileChange::Update {
+                            unified_diff,
+                            move_path: move_target,
+                        },
+                    });
+                }
+            }
+        }
+        Ok(prepared)
+    }
+
+    /// The changes by file, as sent with an approval request.
+    fn changes(&self) -> HashMap<PathBuf, FileChange> {
+        self.changes
+            .iter()
+            .map(|planned| (planned.path.clone(), planned.change.clone()))
+            .collect()
+    }
+
+    /// One line per file, followed by the diffs if `with_diffs` is set.
+    fn describe(&self, with_diffs: bool) -> String {
+        let mut out = String::new();
+        for planned in &self.changes {
+            let line = match (&planned.change, &planned.move_display) {
+                (FileChange::Add { .. }, _) => format!("A {}", planned.display),
+                (FileChange::Delete, _) => format!("D {}", planned.display),
+                (FileChange::Update { .. }, Some(to)) => format!("R {} -> {to}", planned.display),
+                (FileChange::Update { .. }, None) => format!("M {}", planned.display),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+        if with_diffs {
+            for planned in &self.changes {
+                match &planned.change {
+                    FileChange::Add { content } => {
+                        out.push_str(
+                            &TextDiff::from_lines("", content)
+                                .unified_diff()
+                                .header("/dev/null", &planned.display)
+                                .to_string(),
+                        );
+                    }
+                    FileChange::Update { unified_diff, .. } => out.push_str(unified_diff),
+                    FileChange::Delete => {}
+                }
+            }
+        }
+        out
+    }
+
+    /// Write every change, or none: if a write fails, the files written
+    /// before it are restored.
+    pub(crate) async fn apply(&self) -> Result<(), String> {
+        let mut undo: Vec<(&Path, Option<Vec<u8>>)> = Vec::new();
+        for edit in &self.edits {
+            let result = async {
+                let previous = match tokio::fs::read(&edit.path).await {
+                    Ok(bytes) => Some(bytes),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(e),
+                };
+                match &edit.content {
+                    Some(content) => write_file(&edit.path, content.as_bytes()).await?,
+                    None => tokio::fs::remove_file(&edit.path).await?,
+                }
+                Ok(previous)
+            }
+            .await;
+            match result {
+                Ok(previous) => undo.push((&edit.path, previous)),
+                Err(e) => {
+                    rollback(undo).await;
+                    return Err(format!(
+                        "cannot write {}: {e}; no files were changed",
+                        edit.path.display()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn write_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, content).await
+}
+
+async fn rollback(undo: Vec<(&Path, Option<Vec<u8>>)>) {
+    for (path, previous) in undo.into_iter().rev() {
+        let result = match previous {
+            Some(bytes) => write_file(path, &bytes).await,
+            None => tokio::fs::remove_file(path).await,
+        };
+        if let Err(e) = result {
+            log::error!("failed to restore {}: {e}", path.display());
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! `delegate`: hand a self-contained task to a sub-agent.
+//!
+//! The sub-agent starts from an empty history with its own instructions and
+//! only read-only tools, so broad explorations do not fill the parent's
+//! context: the parent only gets the final report back. It runs on the same
+//! tool loop as the main agent, with the session's model, sandbox and
+//! limits, and within the parent turn's deadline; its events are nested
+//! under the call as `SubAgent` events.
+
+use super::{parse_arguments, ToolContext, ToolHandler, ToolOutput, ToolSpec};
+use crate::{corty::run_sub_agent, error::CortyErr, protocol::TurnAbortReason};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Tools a sub-agent may be given. None of them changes anything, so a
+/// sub-agent never needs the user's approval.
+const SUB_AGENT_TOOLS: &[&str] = &["glob", "grep", "read_file", "semantic_search"];
+
+pub(crate) struct DelegateTool;
+
+#[derive(Debug, Deserialize)]
+struct DelegateArgs {
+    task: String,
+    tools: Option<Vec<String>>,
+}
+
+#[async_trait]
+impl ToolHandler for DelegateTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "delegate".to_string(),
+            description: "Hand a self-contained research task to a sub-agent, e.g. \"find every \
+                          caller of Session::configure and say how each uses the result\". The \
+                          sub-agent starts without this conversation, can only read the \
+                          workspace, and returns just its final report. Use it for broad \
+                          explorations whose details you do not need to keep. Put everything \
+                          the sub-agent needs to know into the task."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "task": {
+                        "type": "string",
+                        "description": "What to find out and what to report back."
+                    },
+                    "tools": {
+                        "type": "array",
+                        "items": {"type": "string", "enum": SUB_AGENT_TOOLS},
+                        "description": "Tools the sub-agent may use (default: all of them)."
+                    }
+                },
+                "required": ["task"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let args: DelegateArgs = match parse_arguments("delegate", arguments) {
+            Ok(args) => args,
+            Err(output) => return output,
+        };
+        if args.task.trim().is_empty() {
+            return ToolOutput::error("task must not be empty");
+        }
+        let tools = args.tools.unwrap_or_else(|| {
+            SUB_AGENT_TOOLS
+                .iter()
+                .map(|tool| tool.to_string())
+                .collect()
+        });
+        if let Some(tool) = tools
+            .iter()
+            .find(|tool| !SUB_AGENT_TOOLS.contains(&tool.as_str()))
+        {
+            return ToolOutput::error(format!(
+                "a sub-agent cannot use `{tool}`; choose from {}",
+                SUB_AGENT_TOOLS.join(", ")
+            ));
+        }
+
+        let result = run_sub_agent(
+            ctx.sess,
+            ctx.sub_id,
+            ctx.call_id,
+            args.task,
+            |name| tools.iter().any(|tool| tool == name),
+            ctx.cancel,
+        )
+        .await;
+        match result {
+            Ok(Some(report)) => ToolOutput::success(report),
+            Ok(None) => ToolOutput::error("the sub-agent finished without a report"),
+            Err(CortyErr::TurnLimit(reason)) => {
+                ToolOutput::error(format!("the sub-agent was stopped: {reason}"))
+            }
+            // The parent turn ran out of time, which is the sub-agent's too.
+            Err(CortyErr::Interrupted) if !ctx.interrupt.is_cancelled() => ToolOutput::error(
+                format!("the sub-agent was stopped: {}", TurnAbortReason::Timeout),
+            ),
+            Err(CortyErr::Interrupted) => ToolOutput::error("the sub-agent was interrupted"),
+            Err(e) => ToolOutput::error(format!("the sub-agent failed: {e}")),
+        }
+    }
+}
@@ -0,0 +1,508 @@
+//! `exec`: run a command in the workspace.
+//!
+//! Output is streamed to the frontend as [`ExecCommandOutputDeltaEvent`]s
+//! while the command runs. The model only gets the beginning and the end of
+//! it, so a noisy build cannot flood the context.
+
+use super::{
+    check_decision, parse_arguments, resolve_workspace_path, ToolContext, ToolHandler, ToolOutput,
+    ToolSpec,
+};
+use crate::{
+    protocol::{
+        AskForApproval, EventMsg, ExecCommandOutputDeltaEvent, ExecOutputStream, SandboxPolicy,
+    },
+    spawn::{kill_process_group, spawn_child_async, wait_or_kill},
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    io,
+    path::Path,
+    process::ExitStatus,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::sync::CancellationToken;
+
+/// Timeout of a call that does not set `timeout_ms`.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Upper bound for `timeout_ms`.
+const MAX_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Bytes kept from each end of the output for the model.
+const OUTPUT_HEAD_TAIL_BYTES: usize = 8 * 1024;
+
+/// How long output is still read after the command exits. Anything it left
+/// in the background holding the pipes open is killed after this.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How programs report the errors the sandbox causes: `EPERM` from seccomp,
+/// `EACCES` from Landlock, and `EROFS` from read-only mounts.
+const SANDBOX_DENIAL_MESSAGES: [&str; 3] = [
+    "Operation not permitted",
+    "Permission denied",
+    "Read-only file system",
+];
+
+pub(crate) struct ExecTool;
+
+#[derive(Debug, Deserialize)]
+struct ExecArgs {
+    command: Vec<String>,
+    workdir: Option<String>,
+    timeout_ms: Option<u64>,
+}
+
+#[async_trait]
+impl ToolHandler for ExecTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "exec".to_string(),
+            description: format!(
+                "Run a command in the workspace and return its exit code and output. The command \
+                 is run directly, not through a shell; use [\"bash\", \"-lc\", \"...\"] for pipes \
+                 or redirection. Stdin is closed. Long output is shortened to its first and last \
+                 {} KiB. Commands are killed after {} seconds unless timeout_ms says otherwise.",
+                OUTPUT_HEAD_TAIL_BYTES / 1024,
+                DEFAULT_TIMEOUT.as_secs()
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "minItems": 1,
+                        "description": "Program and arguments, e.g. [\"cargo\", \"test\"]."
+                    },
+                    "workdir": {
+                        "type": "string",
+                        "description": "Directory to run in, relative to the working directory."
+                    },
+                    "timeout_ms": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": MAX_TIMEOUT.as_millis() as u64,
+                        "description": "Kill the command after this many milliseconds."
+                    }
+                },
+                "required": ["command"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let args: ExecArgs = match parse_arguments("exec", arguments) {
+            Ok(args) => args,
+            Err(output) => return output,
+        };
+        if args.command.is_empty() {
+            return ToolOutput::error("command must not be empty");
+        }
+        let workdir = match &args.workdir {
+            Some(dir) => match resolve_workspace_path(ctx.cwd, dir) {
+                Ok(dir) if dir.is_dir() => dir,
+                Ok(_) => return ToolOutput::error(format!("{dir} is not a directory")),
+                Err(e) => return ToolOutput::error(e),
+            },
+            None => ctx.cwd.to_path_buf(),
+        };
+        let timeout = args
+            .timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_TIMEOUT)
+            .min(MAX_TIMEOUT);
+
+        let ask_first = match ctx.approval_policy {
+            AskForApproval::Always => true,
+            AskForApproval::UnlessTrusted => !is_known_safe_command(&args.command),
+            AskForApproval::OnFailure | AskForApproval::Never => false,
+        };
+        if ask_first {
+            if let Err(output) = approve(ctx, &args.command, &workdir, None).await {
+                return output;
+            }
+        }
+
+        let run = run_command(ctx, &args.command, &workdir, ctx.sandbox_policy, timeout).await;
+        let retry_unconfined = ctx.approval_policy == AskForApproval::OnFailure
+            && *ctx.sandbox_policy != SandboxPolicy::DangerFullAccess
+            && run.sandbox_denied();
+        let output = run.render(&args.command[0], ctx.sandbox_policy, timeout);
+        if !retry_unconfined {
+            return output;
+        }
+
+        let reason = "The sandbox may have blocked the command. Run it again without the sandbox?";
+        match approve(ctx, &args.command, &workdir, Some(reason.to_string())).await {
+            Ok(()) => {}
+            // The failure is more useful to the model than the refusal.
+            Err(_) if !ctx.cancel.is_cancelled() => return output,
+            Err(refusal) => return refusal,
+        }
+        let unconfined = SandboxPolicy::DangerFullAccess;
+        run_command(ctx, &args.command, &workdir, &unconfined, timeout)
+            .await
+            .render(&args.command[0], &unconfined, timeout)
+    }
+}
+
+/// Ask the user whether `command` may run in `workdir`.
+async fn approve(
+    ctx: &ToolContext<'_>,
+    command: &[String],
+    workdir: &Path,
+    reason: Option<String>,
+) -> Result<(), ToolOutput> {
+    let decision = ctx
+        .sess
+        .request_command_approval(
+            ctx.sub_id,
+            ctx.call_id,
+            command.to_vec(),
+            workdir.to_path_buf(),
+            reason,
+            ctx.cancel,
+        )
+        .await;
+    check_decision(decision, "run this command")
+}
+
+/// Why a running command was killed
+enum KillReason {
+    /// The user interrupted the turn.
+    Interrupted,
+    /// The turn ran out of time.
+    TurnTimedOut,
+    /// The call ran out of time.
+    TimedOut,
+}
+
+/// How a command run ended
+enum RunOutcome {
+    SpawnFailed(io::Error),
+    Exited {
+        status: ExitStatus,
+        elapsed: Duration,
+        output: String,
+    },
+    Killed {
+        reason: KillReason,
+        output: String,
+    },
+    WaitFailed(io::Error),
+}
+
+impl RunOutcome {
+    /// Whether the command failed in a way the sandbox may have caused: it
+    /// could not start for lack of permission, was killed by seccomp, or
+    /// exited unsuccessfully after reporting a denied operation.
+    ///
+    /// Ordinary failures, such as a failing test or a `grep` without a
+    /// match, are not worth running again without the sandbox.
+    fn sandbox_denied(&self) -> bool {
+        match self {
+            RunOutcome::SpawnFailed(e) => e.kind() == io::ErrorKind::PermissionDenied,
+            RunOutcome::Exited { status, output, .. } => {
+                !status.success()
+                    && (killed_by_seccomp(status)
+                        || SANDBOX_DENIAL_MESSAGES
+                            .iter()
+                            .any(|message| output.contains(message)))
+            }
+            RunOutcome::Killed { .. } | RunOutcome::WaitFailed(_) => false,
+        }
+    }
+
+    fn render(self, program: &str, policy: &SandboxPolicy, timeout: Duration) -> ToolOutput {
+        match self {
+            RunOutcome::SpawnFailed(e) => {
+                ToolOutput::error(format!("failed to start `{program}`: {e}"))
+            }
+            RunOutcome::Exited {
+                status,
+                elapsed,
+                output,
+            } => {
+                let code = status
+                    .code()
+                    .map(|code| code.to_string())
+                    .unwrap_or_else(|| "none (killed by a signal)".to_string());
+                let content = format!(
+                    "Exit code: {code}\nWall time: {:.1}s\nOutput:\n{output}",
+                    elapsed.as_secs_f64()
+                );
+                if status.success() {
+                    ToolOutput::success(content)
+                } else if let Some(note) = sandbox_note(policy) {
+                    ToolOutput::error(format!("{content}\n{note}"))
+                } else {
+                    ToolOutput::error(content)
+                }
+            }
+            RunOutcome::Killed {
+                reason: KillReason::Interrupted,
+                output,
+            } => ToolOutput::error(format!("Command interrupted.\nOutput:\n{output}")),
+            RunOutcome::Killed {
+                reason: KillReason::TurnTimedOut,
+                output,
+            } => ToolOutput::error(format!(
+                "Command killed because the turn ran out of time.\nOutput:\n{output}"
+            )),
+            RunOutcome::Killed {
+                reason: KillReason::TimedOut,
+                output,
+            } => ToolOutput::error(format!(
+                "Command timed out after {:.1}s and was killed.\nOutput:\n{output}",
+                timeout.as_secs_f64()
+            )),
+            RunOutcome::WaitFailed(e) => {
+                ToolOutput::error(format!("failed to wait for `{program}`: {e}"))
+            }
+        }
+    }
+}
+
+/// Whether `status` is that of a process seccomp killed for a forbidden
+/// system call.
+fn killed_by_seccomp(status: &ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal() == Some(libc::SIGSYS)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        false
+    }
+}
+
+/// Run `command` under `policy`, streaming its output to the frontend.
+async fn run_command(
+    ctx: &ToolContext<'_>,
+    command: &[String],
+    workdir: &Path,
+    policy: &SandboxPolicy,
+    timeout: Duration,
+) -> RunOutcome {
+    let (program, args) = command.split_first().expect("command is not empty");
+    let mut child = match spawn_child_async(program, args, workdir, policy) {
+        Ok(child) => child,
+        Err(e) => return RunOutcome::SpawnFailed(e),
+    };
+    let pgid = child.id();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    // Fires on interrupt or when the call runs out of time.
+    let kill = ctx.cancel.child_token();
+    let timer = tokio::spawn({
+        let kill = kill.clone();
+        async move {
+            tokio::time::sleep(timeout).await;
+            kill.cancel();
+        }
+    });
+
+    let started = Instant::now();
+    let output = Mutex::new(HeadTailBuffer::new(OUTPUT_HEAD_TAIL_BYTES));
+    // Fires once the command itself is gone. Anything it backgrounded can
+    // keep the pipes open, so the readers are not waited on unconditionally.
+    let exited = CancellationToken::new();
+    let (status, ()) = tokio::join!(
+        async {
+            let status = wait_or_kill(&mut child, &kill).await;
+            exited.cancel();
+            status
+        },
+        async {
+            let drain = async {
+                exited.cancelled().await;
+                tokio::select! {
+                    () = tokio::time::sleep(OUTPUT_DRAIN_TIMEOUT) => {}
+                    () = kill.cancelled() => {}
+                }
+                // The leader may be gone already; the rest of its group
+                // still holds the pipes.
+                if let Err(e) = kill_process_group(pgid) {
+                    log::warn!("failed to kill the process group of `{program}`: {e}");
+                }
+                // Bounds the wait on anything that left the group.
+                tokio::time::sleep(OUTPUT_DRAIN_TIMEOUT).await;
+            };
+            tokio::select! {
+                ((), ()) = async {
+                    tokio::join!(
+                        forward_output(ctx, stdout, ExecOutputStream::Stdout, &output),
+                        forward_output(ctx, stderr, ExecOutputStream::Stderr, &output),
+                    )
+                } => {}
+                () = drain => {}
+            }
+        },
+    );
+    timer.abort();
+    let elapsed = started.elapsed();
+    let output = output.into_inner().unwrap().render();
+
+    match status {
+        Ok(Some(status)) => RunOutcome::Exited {
+            status,
+            elapsed,
+            output,
+        },
+        Ok(None) => RunOutcome::Killed {
+            reason: if ctx.interrupt.is_cancelled() {
+                KillReason::Interrupted
+            } else if ctx.cancel.is_cancelled() {
+                KillReason::TurnTimedOut
+            } else {
+                KillReason::TimedOut
+            },
+            output,
+        },
+        Err(e) => RunOutcome::WaitFailed(e),
+    }
+}
+
+/// Whether `command` only reads, so the `untrusted` policy runs it without
+/// asking.
+fn is_known_safe_command(command: &[String]) -> bool {
+    let Some((program, args)) = command.split_first() else {
+        return false;
+    };
+    let program = Path::new(program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(program);
+    let has_any = |options: &[&str]| args.iter().any(|arg| options.contains(&arg.as_str()));
+    match program {
+        "cat" | "echo" | "false" | "grep" | "head" | "ls" | "nl" | "pwd" | "tail" | "true"
+        | "wc" | "which" => true,
+        "find" => !has_any(&[
+            "-exec", "-execdir", "-ok", "-okdir", "-delete", "-fls", "-fprint", "-fprint0",
+            "-fprintf",
+        ]),
+        // `--pre` runs a program on every file searched.
+        "rg" => !args.iter().any(|arg| arg.starts_with("--pre")),
+        // Not even `git status`: the repository's own config can run
+        // programs, through `core.fsmonitor`, `diff.external`, textconv and
+        // clean filters or `gpg.program`.
+        _ => false,
+    }
+}
+
+/// Hint for the model when a confined command fails.
+fn sandbox_note(policy: &SandboxPolicy) -> Option<&'static str> {
+    match policy {
+        SandboxPolicy::DangerFullAccess => None,
+        SandboxPolicy::ReadOnly => Some(
+            "(The command ran in a read-only sandbox without network access; \
+             it may have failed because of it.)",
+        ),
+        SandboxPolicy::WorkspaceWrite { .. } => Some(
+            "(The command ran in a sandbox that blocks writes outside the workspace \
+             and may block network access; it may have failed because of it.)",
+        ),
+    }
+}
+
+/// Read `reader` to the end, sending every chunk to the frontend and
+/// collecting it in `output`.
+async fn forward_output(
+    ctx: &ToolContext<'_>,
+    reader: Option<impl AsyncRead + Unpin>,
+    stream: ExecOutputStream,
+    output: &Mutex<HeadTailBuffer>,
+) {
+    let Some(mut reader) = reader else {
+        return;
+    };
+    let mut buf = vec![0; 8 * 1024];
+    // Start of a UTF-8 character split across reads.
+    let mut pending = Vec::new();
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                log::warn!("failed to read command output: {e}");
+                break;
+            }
+        };
+        output.lock().unwrap().push(&buf[..n]);
+
+        pending.extend_from_slice(&buf[..n]);
+        let complete = match std::str::from_utf8(&pending) {
+            Ok(_) => pending.len(),
+            // Keep an incomplete character for the next read; anything else
+            // is invalid and sent as is.
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => pending.len(),
+        };
+        let chunk = String::from_utf8_lossy(&pending[..complete]).into_owned();
+        pending.drain(..complete);
+        if chunk.is_empty() {
+            continue;
+        }
+        ctx.sess
+            .send_event(
+                ctx.sub_id,
+                EventMsg::ExecCommandOutputDelta(ExecCommandOutputDeltaEvent {
+                    call_id: ctx.call_id.to_string(),
+                    stream,
+                    chunk,
+                }),
+            )
+            .await;
+    }
+}
+
+/// Keeps the first and the last `limit` bytes of everything pushed to it
+struct HeadTailBuffer {
+    limit: usize,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    omitted: usize,
+}
+
+impl HeadTailBuffer {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            omitted: 0,
+        }
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) {
+        let room = self.limit - self.head.len();
+        if room > 0 {
+            let take = room.min(bytes.len());
+            self.head.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+        }
+        self.tail.extend(bytes);
+        let excess = self.tail.len().saturating_sub(self.limit);
+        self.tail.drain(..excess);
+        self.omitted += excess;
+    }
+
+    fn render(self) -> String {
+        let mut out = String::from_utf8_lossy(&self.head).into_owned();
+        if self.omitted > 0 {
+            out.push_str(&format!("\n[... {} bytes omitted ...]\n", self.omitted));
+        }
+        out.push_str(&String::from_utf8_lossy(&Vec::from(self.tail)));
+        out
+    }
+}
@@ -0,0 +1,131 @@
+//! `glob`: find files in the workspace by name pattern.
+//!
+//! Files that `.gitignore`, `.ignore` or hidden-file rules exclude are
+//! skipped, so build output and dependencies do not drown the results.
+
+use super::{
+    compile_glob, display_path, parse_arguments, resolve_workspace_path, workspace_walker,
+    ToolContext, ToolHandler, ToolOutput, ToolSpec,
+};
+use async_trait::async_trait;
+use globset::GlobMatcher;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
+
+/// Most paths returned by one call.
+const MAX_RESULTS: usize = 500;
+
+pub(crate) struct GlobTool;
+
+#[derive(Debug, Deserialize)]
+struct GlobArgs {
+    pattern: String,
+    path: Option<String>,
+}
+
+#[async_trait]
+impl ToolHandler for GlobTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "glob".to_string(),
+            description: format!(
+                "Find files whose path matches a glob pattern, e.g. \"*.rs\" or \
+                 \"src/**/test_*.py\". Patterns without a / match file names in any directory. \
+                 Ignored and hidden files are skipped. Returns at most {MAX_RESULTS} paths, \
+                 relative to the working directory."
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "Glob pattern, relative to the searched directory."
+                    },
+                    "path": {
+                        "type": "string",
+                        "description": "Directory to search, relative to the working directory (default: the working directory)."
+                    }
+                },
+                "required": ["pattern"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let args: GlobArgs = match parse_arguments("glob", arguments) {
+            Ok(args) => args,
+            Err(output) => return output,
+        };
+        let matcher = match compile_glob(&args.pattern) {
+            Ok(matcher) => matcher,
+            Err(e) => return ToolOutput::error(e),
+        };
+        let (root, dir) = match (
+            resolve_workspace_path(ctx.cwd, "."),
+            resolve_workspace_path(ctx.cwd, args.path.as_deref().unwrap_or(".")),
+        ) {
+            (Ok(root), Ok(dir)) if dir.is_dir() => (root, dir),
+            (Ok(_), Ok(_)) => {
+                return ToolOutput::error(format!(
+                    "{} is not a directory",
+                    args.path.unwrap_or_default()
+                ))
+            }
+            (Err(e), _) | (_, Err(e)) => return ToolOutput::error(e),
+        };
+
+        let cancel = ctx.cancel.clone();
+        let found =
+            tokio::task::spawn_blocking(move || find_files(&root, &dir, &matcher, &cancel)).await;
+        match found {
+            Ok(Ok(paths)) if paths.is_empty() => ToolOutput::success("No files matched."),
+            Ok(Ok(paths)) => ToolOutput::success(paths),
+            Ok(Err(e)) => ToolOutput::error(e),
+            Err(e) => ToolOutput::error(format!("glob failed: {e}")),
+        }
+    }
+}
+
+/// Paths of the files below `dir` matching `matcher`, one per line and
+/// relative to `root`.
+fn find_files(
+    root: &Path,
+    dir: &Path,
+    matcher: &GlobMatcher,
+    cancel: &CancellationToken,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut found = 0;
+    for entry in workspace_walker(dir).build() {
+        if cancel.is_cancelled() {
+            return Err("interrupted".to_string());
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::debug!("skipping unreadable entry: {e}");
+                continue;
+            }
+        };
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        if !matcher.is_match(relative) {
+            continue;
+        }
+        if found == MAX_RESULTS {
+            out.push_str(&format!(
+                "[results truncated at {MAX_RESULTS} paths; use a narrower pattern or path]\n"
+            ));
+            break;
+        }
+        found += 1;
+        out.push_str(&display_path(root, entry.path()).to_string_lossy());
+        out.push('\n');
+    }
+    Ok(out)
+}
@@ -0,0 +1,240 @@
+//! `grep`: search file contents in the workspace with a regular expression.
+//!
+//! Walks the same files as `glob`, skipping ignored, hidden, binary and very
+//! large ones. Matches are printed like `rg -n`: `path:line:text`, with
+//! context lines as `path-line-text` and `--` between separate groups.
+
+use super::{
+    compile_glob, display_path, parse_arguments, resolve_workspace_path, workspace_walker,
+    ToolContext, ToolHandler, ToolOutput, ToolSpec,
+};
+use async_trait::async_trait;
+use globset::GlobMatcher;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+/// Most matching lines returned by one call.
+const MAX_MATCHES: usize = 200;
+
+/// Upper bound for `context`.
+const MAX_CONTEXT_LINES: usize = 10;
+
+/// Longer lines are cut off at this many characters.
+const MAX_LINE_CHARS: usize = 500;
+
+/// Larger files are not searched.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Bytes at the start of a file checked for NUL bytes.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+pub(crate) struct GrepTool;
+
+#[derive(Debug, Deserialize)]
+struct GrepArgs {
+    pattern: String,
+    path: Option<String>,
+    glob: Option<String>,
+    #[serde(default)]
+    context: usize,
+    #[serde(default)]
+    case_insensitive: bool,
+}
+
+#[async_trait]
+impl ToolHandler for GrepTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "grep".to_string(),
+            description: format!(
+                "Search the contents of files in the workspace with a regular expression (Rust \
+                 regex syntax). Ignored, hidden and binary files are skipped. Matches are \
+                 returned as path:line:text, relative to the working directory; at most \
+                 {MAX_MATCHES} are returned. Use it to find exact identifiers and strings."
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "Regular expression to look for in each line."
+                    },
+                    "path": {
+                        "type": "string",
+                        "description": "File or directory to search, relative to the working directory (default: the working directory)."
+                    },
+                    "glob": {
+                        "type": "string",
+                        "description": "Only search files matching this glob, e.g. \"*.rs\"."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "minimum": 0,
+                        "maximum": MAX_CONTEXT_LINES,
+                        "description": "Lines to show before and after each match (default 0)."
+                    },
+                    "case_insensitive": {
+                        "type": "boolean",
+                        "description": "Ignore case when matching."
+                    }
+                },
+                "required": ["pattern"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let args: GrepArgs = match parse_arguments("grep", arguments) {
+            Ok(args) => args,
+            Err(output) => return output,
+        };
+        let regex = match RegexBuilder::new(&args.pattern)
+            .case_insensitive(args.case_insensitive)
+            .build()
+        {
+            Ok(regex) => regex,
+            Err(e) => return ToolOutput::error(format!("invalid pattern: {e}")),
+        };
+        let filter = match args.glob.as_deref().map(compile_glob).transpose() {
+            Ok(filter) => filter,
+            Err(e) => return ToolOutput::error(e),
+        };
+        let (root, path) = match (
+            resolve_workspace_path(ctx.cwd, "."),
+            resolve_workspace_path(ctx.cwd, args.path.as_deref().unwrap_or(".")),
+        ) {
+            (Ok(root), Ok(path)) if path.exists() => (root, path),
+            (Ok(_), Ok(_)) => {
+                return ToolOutput::error(format!(
+                    "{} does not exist",
+                    args.path.unwrap_or_default()
+                ))
+            }
+            (Err(e), _) | (_, Err(e)) => return ToolOutput::error(e),
+        };
+
+        let search = Search {
+            root,
+            path,
+            regex,
+            filter,
+            context: args.context.min(MAX_CONTEXT_LINES),
+        };
+        let cancel = ctx.cancel.clone();
+        match tokio::task::spawn_blocking(move || search.run(&cancel)).await {
+            Ok(Ok(out)) if out.is_empty() => ToolOutput::success("No matches."),
+            Ok(Ok(out)) => ToolOutput::success(out),
+            Ok(Err(e)) => ToolOutput::error(e),
+            Err(e) => ToolOutput::error(format!("grep failed: {e}")),
+        }
+    }
+}
+
+struct Search {
+    /// Working directory; paths are shown relative to it.
+    root: PathBuf,
+    /// File or directory to search.
+    path: PathBuf,
+    regex: Regex,
+    /// Glob the path of a file, relative to `path`, must match.
+    filter: Option<GlobMatcher>,
+    context: usize,
+}
+
+impl Search {
+    fn run(&self, cancel: &CancellationToken) -> Result<String, String> {
+        let mut out = String::new();
+        let mut remaining = MAX_MATCHES;
+        for entry in workspace_walker(&self.path).build() {
+            if cancel.is_cancelled() {
+                return Err("interrupted".to_string());
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::debug!("skipping unreadable entry: {e}");
+                    continue;
+                }
+            };
+            if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                continue;
+            }
+            if let Some(filter) = &self.filter {
+                // A single file searched is matched by its name.
+                let relative = match entry.path().strip_prefix(&self.path) {
+                    Ok(relative) if !relative.as_os_str().is_empty() => relative,
+                    _ => Path::new(entry.file_name()),
+                };
+                if !filter.is_match(relative) {
+                    continue;
+                }
+            }
+            if entry
+                .metadata()
+                .map_or(true, |metadata| metadata.len() > MAX_FILE_BYTES)
+            {
+                continue;
+            }
+            let Ok(bytes) = std::fs::read(entry.path()) else {
+                continue;
+            };
+            if bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+                continue;
+            }
+
+            let text = String::from_utf8_lossy(&bytes);
+            let lines: Vec<&str> = text.lines().collect();
+            let matches: Vec<usize> = (0..lines.len())
+                .filter(|&i| self.regex.is_match(lines[i]))
+                .collect();
+            let truncated = matches.len() > remaining;
+            let matches = &matches[..matches.len().min(remaining)];
+            remaining -= matches.len();
+
+            let display = display_path(&self.root, entry.path());
+            self.print_file(&mut out, &display.to_string_lossy(), &lines, matches);
+            if truncated {
+                out.push_str(&format!(
+                    "[results truncated at {MAX_MATCHES} matches; narrow the pattern, path or glob]\n"
+                ));
+                break;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Append the lines of `matches` in a file, with their context.
+    fn print_file(&self, out: &mut String, path: &str, lines: &[&str], matches: &[usize]) {
+        let mut last_printed: Option<usize> = None;
+        for &line in matches {
+            let from = line.saturating_sub(self.context);
+            let to = (line + self.context).min(lines.len() - 1);
+            let from = match last_printed {
+                Some(last) if last >= from => last + 1,
+                _ => {
+                    if self.context > 0 && !out.is_empty() {
+                        out.push_str("--\n");
+                    }
+                    from
+                }
+            };
+            for (i, text) in lines.iter().enumerate().take(to + 1).skip(from) {
+                let separator = if matches.binary_search(&i).is_ok() {
+                    ':'
+                } else {
+                    '-'
+                };
+                let text = match text.char_indices().nth(MAX_LINE_CHARS) {
+                    Some((cut, _)) => format!("{}... [line truncated]", &text[..cut]),
+                    None => text.to_string(),
+                };
+                out.push_str(&format!("{path}{separator}{}{separator}{text}\n", i + 1));
+            }
+            last_printed = Some(to);
+        }
+    }
+}
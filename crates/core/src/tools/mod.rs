@@ -0,0 +1,250 @@
+//! Tools the model can call.
+//!
+//! Every tool implements [`ToolHandler`] and is registered by name in a
+//! [`ToolRegistry`]. The engine advertises the registered [`ToolSpec`]s with
+//! each request and dispatches the model's function calls to the handlers.
+
+mod apply_patch;
+mod delegate;
+mod exec;
+mod glob;
+mod grep;
+mod read_file;
+mod semantic_search;
+mod update_plan;
+
+use crate::{
+    config::Config,
+    corty::Session,
+    protocol::{AskForApproval, ReviewDecision, SandboxPolicy},
+};
+use async_trait::async_trait;
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::WalkBuilder;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Description of a tool as advertised to the model
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    /// JSON Schema of the arguments object.
+    pub parameters: Value,
+}
+
+/// What a tool call gets to work with
+pub(crate) struct ToolContext<'a> {
+    pub sess: &'a Session,
+    /// Submission that started the turn; progress events carry its id.
+    pub sub_id: &'a str,
+    /// Id of the call being handled.
+    pub call_id: &'a str,
+    /// Working directory of the session.
+    pub cwd: &'a Path,
+    /// Restrictions for commands the tool runs.
+    pub sandbox_policy: &'a SandboxPolicy,
+    /// When the user must approve what the tool does.
+    pub approval_policy: AskForApproval,
+    /// Cancelled when the turn is interrupted or runs out of time;
+    /// long-running tools should stop promptly.
+    pub cancel: &'a CancellationToken,
+    /// Cancelled only when the user interrupts the turn.
+    pub interrupt: &'a CancellationToken,
+}
+
+/// Result of a tool call, returned to the model as text
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ToolOutput {
+    pub content: String,
+    pub success: bool,
+}
+
+impl ToolOutput {
+    pub(crate) fn success(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            success: true,
+        }
+    }
+
+    pub(crate) fn error(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            success: false,
+        }
+    }
+}
+
+#[async_trait]
+pub(crate) trait ToolHandler: Send + Sync {
+    fn spec(&self) -> ToolSpec;
+
+    /// Run the tool. Failures are reported to the model through
+    /// [`ToolOutput::error`] so it can correct itself.
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput;
+}
+
+/// Tools available to a session, keyed by name
+#[derive(Clone, Default)]
+pub(crate) struct ToolRegistry {
+    handlers: BTreeMap<String, Arc<dyn ToolHandler>>,
+}
+
+impl ToolRegistry {
+    /// Registry with every built-in tool `config` enables.
+    pub(crate) fn with_builtins(config: &Config) -> Self {
+        let mut registry = Self::default();
+        registry.register(Arc::new(apply_patch::ApplyPatchTool));
+        registry.register(Arc::new(delegate::DelegateTool));
+        registry.register(Arc::new(exec::ExecTool));
+        registry.register(Arc::new(glob::GlobTool));
+        registry.register(Arc::new(grep::GrepTool));
+        registry.register(Arc::new(read_file::ReadFileTool));
+        registry.register(Arc::new(update_plan::UpdatePlanTool));
+        if let Some(index_dir) = &config.index_dir {
+            registry.register(Arc::new(semantic_search::SemanticSearchTool::new(
+                index_dir.clone(),
+                config.embedding.clone(),
+            )));
+        }
+        registry
+    }
+
+    pub(crate) fn register(&mut self, handler: Arc<dyn ToolHandler>) {
+        self.handlers.insert(handler.spec().name, handler);
+    }
+
+    /// Drop every tool whose name `keep` rejects.
+    pub(crate) fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.handlers.retain(|name, _| keep(name));
+    }
+
+    /// Specs of all registered tools, sorted by name.
+    pub(crate) fn specs(&self) -> Vec<ToolSpec> {
+        self.handlers
+            .values()
+            .map(|handler| handler.spec())
+            .collect()
+    }
+
+    /// Run the tool `name` with the raw JSON `arguments` from the model.
+    pub(crate) async fn dispatch(
+        &self,
+        ctx: &ToolContext<'_>,
+        name: &str,
+        arguments: &str,
+    ) -> ToolOutput {
+        let Some(handler) = self.handlers.get(name) else {
+            return ToolOutput::error(format!("unknown tool `{name}`"));
+        };
+        let arguments = if arguments.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            match serde_json::from_str(arguments) {
+                Ok(arguments) => arguments,
+                Err(e) => return ToolOutput::error(format!("invalid arguments for `{name}`: {e}")),
+            }
+        };
+        handler.handle(ctx, arguments).await
+    }
+}
+
+/// Deserialize the arguments of `tool` into `T`.
+pub(crate) fn parse_arguments<T: DeserializeOwned>(
+    tool: &str,
+    arguments: Value,
+) -> Result<T, ToolOutput> {
+    serde_json::from_value(arguments)
+        .map_err(|e| ToolOutput::error(format!("invalid arguments for `{tool}`: {e}")))
+}
+
+/// Turn the user's answer to an approval request into whether the tool may
+/// go on; `action` completes "The user declined to ...".
+pub(crate) fn check_decision(decision: ReviewDecision, action: &str) -> Result<(), ToolOutput> {
+    match decision {
+        ReviewDecision::Approved | ReviewDecision::ApprovedForSession => Ok(()),
+        ReviewDecision::Denied => Err(ToolOutput::error(format!(
+            "The user declined to {action}. Do not try again the same way; ask them how to proceed."
+        ))),
+        ReviewDecision::Abort => Err(ToolOutput::error("Aborted by the user.")),
+    }
+}
+
+/// Resolve `path` against `cwd` and make sure the result stays inside `cwd`.
+///
+/// Components are followed one by one: symlinks are resolved as they are
+/// met, so `..` after one applies to its target, and a symlink whose target
+/// does not exist is rejected, since writing through it could create a file
+/// anywhere. The path itself does not need to exist.
+pub(crate) fn resolve_workspace_path(cwd: &Path, path: &str) -> Result<PathBuf, String> {
+    let root = cwd
+        .canonicalize()
+        .map_err(|e| format!("cannot access working directory {}: {e}", cwd.display()))?;
+
+    let mut resolved = root.clone();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                resolved = PathBuf::from(component.as_os_str())
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(part) => {
+                resolved.push(part);
+                let is_symlink = std::fs::symlink_metadata(&resolved)
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                if is_symlink {
+                    resolved = resolved.canonicalize().map_err(|_| {
+                        format!("{path} goes through a symlink whose target does not exist")
+                    })?;
+                }
+            }
+        }
+    }
+
+    if resolved.starts_with(&root) {
+        Ok(resolved)
+    } else {
+        Err(format!("{path} is outside the working directory"))
+    }
+}
+
+/// Walker over the files below `dir`, skipping hidden files and whatever
+/// `.gitignore` and `.ignore` files exclude, the way `rg` does.
+pub(crate) fn workspace_walker(dir: &Path) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(dir);
+    // Honour `.gitignore` even where the workspace is not a git repository.
+    builder
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    builder
+}
+
+/// Compile a glob for paths relative to the searched directory. Patterns
+/// without a `/` match file names at any depth, as in `.gitignore`.
+pub(crate) fn compile_glob(pattern: &str) -> Result<GlobMatcher, String> {
+    let pattern = if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{pattern}")
+    };
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| format!("invalid glob: {e}"))
+}
+
+/// `path` relative to `root` when it lies below it.
+pub(crate) fn display_path(root: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(root).unwrap_or(path).to_path_buf()
+}
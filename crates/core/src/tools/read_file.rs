@@ -0,0 +1,153 @@
+//! `read_file`: line-numbered contents of a text file in the workspace.
+
+use super::{
+    parse_arguments, resolve_workspace_path, ToolContext, ToolHandler, ToolOutput, ToolSpec,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
+
+/// Most lines returned by one call.
+const MAX_LINES: usize = 2000;
+
+/// Most bytes of file content returned by one call.
+const MAX_OUTPUT_BYTES: usize = 100 * 1024;
+
+/// Longer lines are cut off at this many characters.
+const MAX_LINE_CHARS: usize = 2000;
+
+/// Bytes at the start of the file checked for NUL bytes.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+pub(crate) struct ReadFileTool;
+
+#[derive(Debug, Deserialize)]
+struct ReadFileArgs {
+    path: String,
+    start_line: Option<usize>,
+    end_line: Option<usize>,
+}
+
+#[async_trait]
+impl ToolHandler for ReadFileTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "read_file".to_string(),
+            description: format!(
+                "Read a text file from the workspace. Lines are returned prefixed with their \
+                 1-based line number. At most {MAX_LINES} lines are returned per call; use \
+                 start_line and end_line to read other parts of large files."
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path of the file, relative to the working directory."
+                    },
+                    "start_line": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "First line to return (1-based, default 1)."
+                    },
+                    "end_line": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Last line to return, inclusive (default: end of file)."
+                    }
+                },
+                "required": ["path"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let args: ReadFileArgs = match parse_arguments("read_file", arguments) {
+            Ok(args) => args,
+            Err(output) => return output,
+        };
+        let start = args.start_line.unwrap_or(1);
+        if start == 0 || args.end_line.is_some_and(|end| end < start) {
+            return ToolOutput::error(format!(
+                "invalid line range {}-{}",
+                start,
+                args.end_line.map(|end| end.to_string()).unwrap_or_default()
+            ));
+        }
+        let path = match resolve_workspace_path(ctx.cwd, &args.path) {
+            Ok(path) => path,
+            Err(e) => return ToolOutput::error(e),
+        };
+        match read_lines(&path, start, args.end_line, ctx.cancel).await {
+            Ok(content) => ToolOutput::success(content),
+            Err(e) => ToolOutput::error(format!("cannot read {}: {e}", args.path)),
+        }
+    }
+}
+
+/// Read lines `start..=end` of `path`, numbered, within the output limits.
+async fn read_lines(
+    path: &Path,
+    start: usize,
+    end: Option<usize>,
+    cancel: &CancellationToken,
+) -> std::io::Result<String> {
+    let file = tokio::fs::File::open(path).await?;
+    if file.metadata().await?.is_dir() {
+        return Err(std::io::Error::other("is a directory"));
+    }
+    let mut reader = BufReader::new(file);
+    let head = reader.fill_buf().await?;
+    if head[..head.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+        return Err(std::io::Error::other("binary files cannot be read"));
+    }
+
+    let mut out = String::new();
+    let mut line = Vec::new();
+    let mut line_no = 0;
+    let mut returned = 0;
+    loop {
+        // Skipping to a late start line in a big file can take a while.
+        if cancel.is_cancelled() {
+            return Err(std::io::Error::other("interrupted"));
+        }
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        line_no += 1;
+        if line_no < start {
+            continue;
+        }
+        if end.is_some_and(|end| line_no > end) {
+            return Ok(out);
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        let text = match text.char_indices().nth(MAX_LINE_CHARS) {
+            Some((cut, _)) => format!("{}... [line truncated]", &text[..cut]),
+            None => text.to_string(),
+        };
+        let numbered = format!("{line_no:>6}\t{text}\n");
+        if returned == MAX_LINES || out.len() + numbered.len() > MAX_OUTPUT_BYTES {
+            out.push_str(&format!(
+                "[output truncated; call read_file with start_line={line_no} to continue]\n"
+            ));
+            return Ok(out);
+        }
+        out.push_str(&numbered);
+        returned += 1;
+    }
+
+    if line_no < start && !(line_no == 0 && start == 1) {
+        return Err(std::io::Error::other(format!(
+            "start_line {start} is past the end of the file ({line_no} lines)"
+        )));
+    }
+    Ok(out)
+}
@@ -0,0 +1,185 @@
+//! `semantic_search`: find code by meaning in the project's vector index.
+//!
+//! The index is built by `corty index` into a table named after the
+//! project directory. This complements `grep`: it finds code that does what
+//! the query describes even when no identifier matches literally.
+
+use super::{
+    compile_glob, parse_arguments, resolve_workspace_path, ToolContext, ToolHandler, ToolOutput,
+    ToolSpec,
+};
+use crate::storage::{project_table_name, EmbeddingProvider, SearchHit, VectorDB};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use tokio::sync::OnceCell;
+
+/// Results returned when the call does not set `limit`.
+const DEFAULT_LIMIT: usize = 10;
+
+/// Upper bound for `limit`.
+const MAX_LIMIT: usize = 50;
+
+/// Candidates fetched per wanted result when filtering by path, since the
+/// glob is applied after the search.
+const PATH_FILTER_OVERFETCH: usize = 5;
+
+/// Longer chunks are cut off at this many characters.
+const MAX_CHUNK_CHARS: usize = 4000;
+
+pub(crate) struct SemanticSearchTool {
+    index_dir: PathBuf,
+    embedding: EmbeddingProvider,
+    /// Connected on first use; loading the embedding model takes a while.
+    db: OnceCell<VectorDB>,
+}
+
+impl SemanticSearchTool {
+    pub(crate) fn new(index_dir: PathBuf, embedding: EmbeddingProvider) -> Self {
+        Self {
+            index_dir,
+            embedding,
+            db: OnceCell::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SemanticSearchArgs {
+    query: String,
+    limit: Option<usize>,
+    path_glob: Option<String>,
+    language: Option<String>,
+}
+
+#[async_trait]
+impl ToolHandler for SemanticSearchTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "semantic_search".to_string(),
+            description: format!(
+                "Search the project's code index by meaning, e.g. \"where are retries with \
+                 backoff handled\". Returns the most similar code chunks with their path, line \
+                 range, enclosing symbol and similarity score, best first ({DEFAULT_LIMIT} by default). Use grep \
+                 instead to find exact identifiers. Needs an index built with `corty index`."
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Natural-language description of the code to find."
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": MAX_LIMIT,
+                        "description": "Number of chunks to return."
+                    },
+                    "path_glob": {
+                        "type": "string",
+                        "description": "Only return chunks from files matching this glob, e.g. \"src/**/*.rs\"."
+                    },
+                    "language": {
+                        "type": "string",
+                        "description": "Only return chunks in this language, e.g. \"rust\" or \"python\"."
+                    }
+                },
+                "required": ["query"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let args: SemanticSearchArgs = match parse_arguments("semantic_search", arguments) {
+            Ok(args) => args,
+            Err(output) => return output,
+        };
+        let limit = args.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let path_filter = match args.path_glob.as_deref().map(compile_glob).transpose() {
+            Ok(filter) => filter,
+            Err(e) => return ToolOutput::error(e),
+        };
+        let root = match resolve_workspace_path(ctx.cwd, ".") {
+            Ok(root) => root,
+            Err(e) => return ToolOutput::error(e),
+        };
+
+        let db = match self
+            .db
+            .get_or_try_init(|| async {
+                VectorDB::connect(&self.index_dir.to_string_lossy(), &self.embedding).await
+            })
+            .await
+        {
+            Ok(db) => db,
+            Err(e) => return ToolOutput::error(format!("cannot open the code index: {e}")),
+        };
+        let table = match db.find_table(&project_table_name(&root)).await {
+            Ok(Some(table)) => table,
+            Ok(None) => {
+                return ToolOutput::error(
+                    "this project has not been indexed; run `corty index .` first, or use grep",
+                )
+            }
+            Err(e) => return ToolOutput::error(format!("cannot open the code index: {e}")),
+        };
+
+        let language_filter = args
+            .language
+            .map(|language| format!("language = '{}'", language.replace('\'', "''")));
+        let fetch = if path_filter.is_some() {
+            limit * PATH_FILTER_OVERFETCH
+        } else {
+            limit
+        };
+        let search = db.search(&table, &args.query, fetch, language_filter.as_deref());
+        let hits = tokio::select! {
+            hits = search => hits,
+            _ = ctx.cancel.cancelled() => return ToolOutput::error("interrupted"),
+        };
+        let hits: Vec<SearchHit> = match hits {
+            Ok(hits) => hits
+                .into_iter()
+                .filter(|hit| {
+                    path_filter
+                        .as_ref()
+                        .is_none_or(|filter| filter.is_match(&hit.path))
+                })
+                .take(limit)
+                .collect(),
+            Err(e) => return ToolOutput::error(format!("search failed: {e}")),
+        };
+
+        if hits.is_empty() {
+            return ToolOutput::success("No matching code in the index.");
+        }
+        ToolOutput::success(
+            hits.iter()
+                .enumerate()
+                .map(|(i, hit)| format_hit(i + 1, hit))
+                .collect::<String>(),
+        )
+    }
+}
+
+/// `rank. path:start-end symbol (language, score 0.83)` followed by the
+/// chunk.
+fn format_hit(rank: usize, hit: &SearchHit) -> String {
+    let mut location = format!("{}:{}-{}", hit.path, hit.start_line, hit.end_line);
+    if let Some(symbol) = &hit.symbol {
+        location.push_str(&format!(" `{symbol}`"));
+    }
+    let content = match hit.content.char_indices().nth(MAX_CHUNK_CHARS) {
+        Some((cut, _)) => format!("{}\n... [chunk truncated]", &hit.content[..cut]),
+        None => hit.content.clone(),
+    };
+    format!(
+        "{rank}. {location} ({}, score {:.2})\n{}\n\n",
+        hit.language,
+        hit.score,
+        content.trim_end()
+    )
+}
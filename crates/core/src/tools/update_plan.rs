@@ -0,0 +1,78 @@
+//! `update_plan`: let the model keep a checklist of the steps of its task.
+//!
+//! The tool does nothing but validate the plan and publish it as a
+//! [`PlanUpdateEvent`], so frontends can show what the agent intends to do
+//! next. The model keeps the plan in its own context.
+
+use super::{parse_arguments, ToolContext, ToolHandler, ToolOutput, ToolSpec};
+use crate::protocol::{EventMsg, PlanUpdateEvent, StepStatus};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+pub(crate) struct UpdatePlanTool;
+
+#[async_trait]
+impl ToolHandler for UpdatePlanTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "update_plan".to_string(),
+            description: "Record your plan for the task as an ordered checklist, and update it \
+                          as you go. Send the whole plan every time. At most one step may be \
+                          in_progress. Use it for tasks with several steps or files; skip it \
+                          for simple questions."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "explanation": {
+                        "type": "string",
+                        "description": "Short note on why the plan was made or changed."
+                    },
+                    "plan": {
+                        "type": "array",
+                        "minItems": 1,
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "step": {"type": "string"},
+                                "status": {
+                                    "type": "string",
+                                    "enum": ["pending", "in_progress", "done"]
+                                }
+                            },
+                            "required": ["step", "status"],
+                            "additionalProperties": false
+                        }
+                    }
+                },
+                "required": ["plan"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn handle(&self, ctx: &ToolContext<'_>, arguments: Value) -> ToolOutput {
+        let update: PlanUpdateEvent = match parse_arguments("update_plan", arguments) {
+            Ok(update) => update,
+            Err(output) => return output,
+        };
+        if update.plan.is_empty() {
+            return ToolOutput::error("the plan must have at least one step");
+        }
+        let in_progress = update
+            .plan
+            .iter()
+            .filter(|item| item.status == StepStatus::InProgress)
+            .count();
+        if in_progress > 1 {
+            return ToolOutput::error(format!(
+                "{in_progress} steps are in_progress; at most one may be"
+            ));
+        }
+
+        ctx.sess
+            .send_event(ctx.sub_id, EventMsg::PlanUpdate(update))
+            .await;
+        ToolOutput::success("Plan updated.")
+    }
+}
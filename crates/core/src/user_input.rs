@@ -0,0 +1,225 @@
+//! Resolution of user [`InputItem`]s into model-visible content.
+//!
+//! File references are read from disk at submission time so the model sees
+//! the exact bytes the user pointed at; images are inlined as `data:` URLs.
+
+use crate::{
+    error::{CortyErr, Result},
+    models::{ContentItem, ResponseItem},
+    protocol::InputItem,
+};
+use base64::Engine;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+/// Largest amount of text attached from a single file reference.
+pub(crate) const MAX_FILE_BYTES: usize = 256 * 1024;
+
+/// Why a file with NUL bytes is refused.
+const BINARY_FILE: &str = "binary files cannot be attached";
+
+/// Largest image accepted for a `LocalImage` item.
+pub(crate) const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// Build the user message for `items`, reading referenced files relative to `cwd`.
+pub(crate) async fn resolve_input(items: Vec<InputItem>, cwd: &Path) -> Result<ResponseItem> {
+    let mut content = Vec::with_capacity(items.len());
+    for item in items {
+        let resolved = match item {
+            InputItem::Text { text } => ContentItem::InputText { text },
+            InputItem::File { path } => read_text_file(cwd, &path, None).await?,
+            InputItem::FileRange {
+                path,
+                start_line,
+                end_line,
+            } => read_text_file(cwd, &path, Some((start_line, end_line))).await?,
+            InputItem::LocalImage { path } => read_image(cwd, &path).await?,
+        };
+        content.push(resolved);
+    }
+
+    Ok(ResponseItem::Message {
+        role: "user".to_string(),
+        content,
+    })
+}
+
+fn invalid(path: &Path, reason: impl Into<String>) -> CortyErr {
+    CortyErr::InvalidInput {
+        path: path.to_path_buf(),
+        reason: reason.into(),
+    }
+}
+
+fn resolve_path(cwd: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        cwd.join(path)
+    }
+}
+
+/// Read a text file, or the 1-based inclusive `range` of its lines, and
+/// wrap it in a `<file>` block.
+async fn read_text_file(
+    cwd: &Path,
+    path: &Path,
+    range: Option<(usize, usize)>,
+) -> Result<ContentItem> {
+    if let Some((start, end)) = range {
+        if start == 0 || end < start {
+            return Err(invalid(
+                path,
+                format!("invalid line range {}-{}", start, end),
+            ));
+        }
+    }
+
+    let full_path = resolve_path(cwd, path);
+    let file = tokio::fs::File::open(&full_path)
+        .await
+        .map_err(|e| invalid(path, e.to_string()))?;
+    let total_bytes = file
+        .metadata()
+        .await
+        .map_err(|e| invalid(path, e.to_string()))?
+        .len();
+
+    let (mut body, truncated) = match range {
+        None => {
+            let mut buf = Vec::new();
+            file.take(MAX_FILE_BYTES as u64)
+                .read_to_end(&mut buf)
+                .await
+                .map_err(|e| invalid(path, e.to_string()))?;
+            if buf.contains(&0) {
+                return Err(invalid(path, BINARY_FILE));
+            }
+            let truncated = total_bytes > buf.len() as u64;
+            if truncated {
+                cut_at_boundary(&mut buf);
+            }
+            (String::from_utf8_lossy(&buf).into_owned(), truncated)
+        }
+        Some((start, end)) => {
+            let mut lines = BufReader::new(file).split(b'\n');
+            let mut body = String::new();
+            let mut line_no = 0;
+            let mut truncated = false;
+            while let Some(mut line) = lines
+                .next_segment()
+                .await
+                .map_err(|e| invalid(path, e.to_string()))?
+            {
+                if line.contains(&0) {
+                    return Err(invalid(path, BINARY_FILE));
+                }
+                line_no += 1;
+                if line_no < start {
+                    continue;
+                }
+                if line_no > end {
+                    break;
+                }
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                let line = String::from_utf8_lossy(&line);
+                if body.len() + line.len() + 1 > MAX_FILE_BYTES {
+                    truncated = true;
+                    break;
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+            if line_no < start {
+                return Err(invalid(
+                    path,
+                    format!("file has {} lines, range starts at {}", line_no, start),
+                ));
+            }
+            (body, truncated)
+        }
+    };
+
+    if !body.ends_with('\n') {
+        body.push('\n');
+    }
+    if truncated {
+        body.push_str(&format!(
+            "[truncated: attachment limited to {} bytes]\n",
+            MAX_FILE_BYTES
+        ));
+    }
+
+    let lines_attr = range
+        .map(|(start, end)| format!(" lines=\"{}-{}\"", start, end))
+        .unwrap_or_default();
+    Ok(ContentItem::InputText {
+        text: format!(
+            "<file path=\"{}\"{}>\n{}</file>",
+            path.display(),
+            lines_attr,
+            body
+        ),
+    })
+}
+
+/// Cut `buf`, the start of a longer file, back to its last line break, or
+/// if it has none, to before a character split at its end.
+fn cut_at_boundary(buf: &mut Vec<u8>) {
+    if let Some(newline) = buf.iter().rposition(|&b| b == b'\n') {
+        buf.truncate(newline + 1);
+        return;
+    }
+    // A UTF-8 character takes at most 4 bytes, so it starts in the last 4.
+    let tail = buf.len().saturating_sub(4);
+    if let Some(start) = (tail..buf.len()).rev().find(|&i| buf[i] & 0xC0 != 0x80) {
+        let width = match buf[start] {
+            b if b >= 0xF0 => 4,
+            b if b >= 0xE0 => 3,
+            b if b >= 0xC0 => 2,
+            _ => 1,
+        };
+        if start + width > buf.len() {
+            buf.truncate(start);
+        }
+    }
+}
+
+/// Read an image and encode it as a base64 `data:` URL.
+async fn read_image(cwd: &Path, path: &Path) -> Result<ContentItem> {
+    let mime = image_mime_type(path)
+        .ok_or_else(|| invalid(path, "unsupported image type (use png, jpeg, gif or webp)"))?;
+
+    let full_path = resolve_path(cwd, path);
+    let size = tokio::fs::metadata(&full_path)
+        .await
+        .map_err(|e| invalid(path, e.to_string()))?
+        .len();
+    if size > MAX_IMAGE_BYTES {
+        return Err(invalid(
+            path,
+            format!("image is {} bytes, limit is {}", size, MAX_IMAGE_BYTES),
+        ));
+    }
+
+    let bytes = tokio::fs::read(&full_path)
+        .await
+        .map_err(|e| invalid(path, e.to_string()))?;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    Ok(ContentItem::InputImage {
+        image_url: format!("data:{};base64,{}", mime, encoded),
+    })
+}
+
+fn image_mime_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
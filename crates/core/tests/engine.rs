@@ -0,0 +1,1879 @@
+//! Tests for the engine loop against a local mock provider
+
+#[cfg(test)]
+mod tests {
+    use corty_core::{
+        config::Config,
+        corty::Corty,
+        indexer::index_path,
+        protocol::{
+            ApiKeySource, AskForApproval, Event, EventMsg, ExecOutputStream, InputItem,
+            ModelProviderInfo, Op, ProviderError, ReviewDecision, StepStatus, Submission,
+            TokenUsage, TurnAbortReason, WireApi,
+        },
+        session::{list_sessions, load_session},
+        storage::{EmbeddingProvider, VectorDB, DEFAULT_HASHING_DIMENSIONS},
+    };
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc::{unbounded_channel, Receiver, Sender, UnboundedSender},
+    };
+
+    /// Serve `body` as an SSE response to every request and return the base URL.
+    async fn mock_provider(body: String) -> String {
+        mock_provider_with(body, false).await
+    }
+
+    /// Like `mock_provider`, but optionally keep the response open after
+    /// `body` so the turn never completes on its own.
+    async fn mock_provider_with(body: String, stall: bool) -> String {
+        mock_provider_recording(body, stall, None).await
+    }
+
+    /// Like `mock_provider_with`, but forward each raw request to `requests`.
+    async fn mock_provider_recording(
+        body: String,
+        stall: bool,
+        requests: Option<UnboundedSender<String>>,
+    ) -> String {
+        mock_provider_sequence(vec![body], stall, requests).await
+    }
+
+    /// Serve `bodies` in order, one per request, repeating the last one.
+    async fn mock_provider_sequence(
+        bodies: Vec<String>,
+        stall: bool,
+        requests: Option<UnboundedSender<String>>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut served = 0;
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let body = bodies[served.min(bodies.len() - 1)].clone();
+                served += 1;
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let request = read_request(&mut socket).await;
+                    if let Some(requests) = requests {
+                        let _ = requests.send(request);
+                    }
+                    let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n";
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(body.as_bytes()).await;
+                    if stall {
+                        tokio::time::sleep(Duration::from_secs(3600)).await;
+                    }
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        format!("http://{}/v1", addr)
+    }
+
+    /// Read request headers and the body announced by `content-length`.
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let content_length = text[..header_end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if buf.len() >= header_end + 4 + content_length {
+                    break;
+                }
+            }
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
+    fn provider(base_url: String, wire_api: WireApi) -> ModelProviderInfo {
+        ModelProviderInfo {
+            id: "ollama".to_string(),
+            base_url,
+            api_key: ApiKeySource::None,
+            wire_api,
+        }
+    }
+
+    fn spawn_engine(provider: ModelProviderInfo) -> (Sender<Submission>, Receiver<Event>) {
+        spawn_engine_with(provider, [])
+    }
+
+    /// Engine using `provider`, with `others` registered too.
+    fn spawn_engine_with(
+        provider: ModelProviderInfo,
+        others: impl IntoIterator<Item = ModelProviderInfo>,
+    ) -> (Sender<Submission>, Receiver<Event>) {
+        let mut config = Config {
+            model_provider: provider,
+            data_dir: None,
+            ..Config::default()
+        };
+        for provider in others {
+            config.model_providers.insert(provider.id.clone(), provider);
+        }
+        Corty::spawn(config).unwrap()
+    }
+
+    async fn next_event(rx: &mut Receiver<Event>) -> Event {
+        tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("timed out waiting for event")
+            .expect("event channel closed")
+    }
+
+    /// Submit a user message and collect events up to `TaskComplete`.
+    async fn run_user_turn(
+        tx: &Sender<Submission>,
+        rx: &mut Receiver<Event>,
+        id: &str,
+        text: &str,
+    ) -> Vec<Event> {
+        let items = vec![InputItem::Text {
+            text: text.to_string(),
+        }];
+        run_user_turn_with(tx, rx, id, items).await
+    }
+
+    /// Submit `items` as user input and collect events up to `TaskComplete`.
+    async fn run_user_turn_with(
+        tx: &Sender<Submission>,
+        rx: &mut Receiver<Event>,
+        id: &str,
+        items: Vec<InputItem>,
+    ) -> Vec<Event> {
+        tx.send(Submission {
+            id: id.to_string(),
+            op: Op::UserInput { items },
+        })
+        .await
+        .unwrap();
+
+        let mut events = Vec::new();
+        loop {
+            let event = next_event(rx).await;
+            let done = matches!(event.msg, EventMsg::TaskComplete(_));
+            events.push(event);
+            if done {
+                return events;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_turn_events_are_correlated() {
+        let body = [
+            r#"data: {"choices":[{"delta":{"content":"Hello"}}]}"#,
+            r#"data: {"choices":[{"delta":{"content":" world"}}]}"#,
+            "data: [DONE]",
+        ]
+        .map(|line| format!("{}\n\n", line))
+        .concat();
+        let base_url = mock_provider(body).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url, WireApi::Chat));
+
+        let events = run_user_turn(&tx, &mut rx, "42", "hi").await;
+
+        assert!(events.iter().all(|event| event.id == "42"));
+        assert!(matches!(events[0].msg, EventMsg::TaskStarted));
+        let deltas: String = events
+            .iter()
+            .filter_map(|event| match &event.msg {
+                EventMsg::AgentMessageDelta(delta) => Some(delta.delta.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deltas, "Hello world");
+        match &events.last().unwrap().msg {
+            EventMsg::TaskComplete(complete) => {
+                assert_eq!(complete.last_agent_message.as_deref(), Some("Hello world"));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_responses_turn() {
+        let body = [
+            r#"data: {"type":"response.output_text.delta","delta":"Hi"}"#,
+            r#"data: {"type":"response.output_item.done","item":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"Hi"}]}}"#,
+            r#"data: {"type":"response.completed","response":{"id":"resp_1"}}"#,
+        ]
+        .map(|line| format!("{}\n\n", line))
+        .concat();
+        let base_url = mock_provider(body).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url, WireApi::Responses));
+
+        let events = run_user_turn(&tx, &mut rx, "1", "hello").await;
+
+        assert!(events.iter().any(|event| matches!(
+            &event.msg,
+            EventMsg::AgentMessage(message) if message.message == "Hi"
+        )));
+        // No usage in `response.completed`, so it is estimated.
+        assert!(events.iter().any(|event| matches!(
+            &event.msg,
+            EventMsg::TokenCount(count) if count.estimated && count.turn.output_tokens > 0
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_token_usage_is_accumulated() {
+        let body = [
+            r#"data: {"choices":[{"delta":{"content":"Hi"}}]}"#,
+            r#"data: {"choices":[],"usage":{"prompt_tokens":100,"completion_tokens":7,"total_tokens":107,"prompt_tokens_details":{"cached_tokens":64},"completion_tokens_details":{"reasoning_tokens":3}}}"#,
+            "data: [DONE]",
+        ]
+        .map(|line| format!("{}\n\n", line))
+        .concat();
+        let base_url = mock_provider(body).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url, WireApi::Chat));
+
+        let token_count = |events: Vec<Event>| {
+            events
+                .into_iter()
+                .find_map(|event| match event.msg {
+                    EventMsg::TokenCount(count) => Some(count),
+                    _ => None,
+                })
+                .expect("no TokenCount event")
+        };
+        let first = token_count(run_user_turn(&tx, &mut rx, "1", "hi").await);
+        let second = token_count(run_user_turn(&tx, &mut rx, "2", "again").await);
+
+        assert!(!first.estimated);
+        assert_eq!(
+            first.turn,
+            TokenUsage {
+                input_tokens: 100,
+                cached_input_tokens: 64,
+                output_tokens: 7,
+                reasoning_output_tokens: 3,
+                total_tokens: 107,
+            }
+        );
+        assert_eq!(second.turn, first.turn);
+        assert_eq!(second.session.total_tokens, 214);
+    }
+
+    #[tokio::test]
+    async fn test_configure_session_ack() {
+        let base_url = mock_provider(String::new()).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url.clone(), WireApi::Chat));
+
+        tx.send(Submission {
+            id: "cfg".to_string(),
+            op: Op::ConfigureSession {
+                provider: provider(base_url, WireApi::Chat),
+                model: "llama3".to_string(),
+                cwd: std::env::temp_dir(),
+            },
+        })
+        .await
+        .unwrap();
+
+        let event = next_event(&mut rx).await;
+        assert_eq!(event.id, "cfg");
+        match event.msg {
+            EventMsg::SessionConfigured(configured) => {
+                assert_eq!(configured.model, "llama3");
+                assert_eq!(configured.provider_id, "ollama");
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_configure_session_rejects_unknown_provider() {
+        let base_url = mock_provider(String::new()).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url.clone(), WireApi::Chat));
+
+        let mut unknown = provider(base_url, WireApi::Chat);
+        unknown.id = "nope".to_string();
+        tx.send(Submission {
+            id: "cfg".to_string(),
+            op: Op::ConfigureSession {
+                provider: unknown,
+                model: String::new(),
+                cwd: std::env::temp_dir(),
+            },
+        })
+        .await
+        .unwrap();
+
+        match next_event(&mut rx).await.msg {
+            EventMsg::ConfigureSessionError(error) => assert_eq!(
+                error,
+                ProviderError::UnknownProvider {
+                    id: "nope".to_string()
+                }
+            ),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_configure_session_rejects_changed_provider() {
+        let base_url = mock_provider(String::new()).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url, WireApi::Chat));
+
+        // A registered id must not carry another host or key.
+        let mut changed = provider("https://attacker.example".to_string(), WireApi::Chat);
+        changed.api_key = ApiKeySource::Env {
+            var: "AWS_SECRET_ACCESS_KEY".to_string(),
+        };
+        tx.send(Submission {
+            id: "cfg".to_string(),
+            op: Op::ConfigureSession {
+                provider: changed,
+                model: String::new(),
+                cwd: std::env::temp_dir(),
+            },
+        })
+        .await
+        .unwrap();
+
+        match next_event(&mut rx).await.msg {
+            EventMsg::ConfigureSessionError(error) => assert_eq!(
+                error,
+                ProviderError::ProviderMismatch {
+                    id: "ollama".to_string()
+                }
+            ),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_configure_session_rejects_bad_base_url() {
+        let base_url = mock_provider(String::new()).await;
+        let mut broken = provider("ftp://example.com".to_string(), WireApi::Chat);
+        broken.id = "broken".to_string();
+        let (tx, mut rx) = spawn_engine_with(provider(base_url, WireApi::Chat), [broken.clone()]);
+
+        tx.send(Submission {
+            id: "cfg".to_string(),
+            op: Op::ConfigureSession {
+                provider: broken,
+                model: String::new(),
+                cwd: std::env::temp_dir(),
+            },
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            next_event(&mut rx).await.msg,
+            EventMsg::ConfigureSessionError(ProviderError::InvalidBaseUrl { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_keeps_partial_output() {
+        let body = format!(
+            "{}\n\n",
+            r#"data: {"choices":[{"delta":{"content":"Partial"}}]}"#
+        );
+        let base_url = mock_provider_with(body, true).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url, WireApi::Chat));
+
+        tx.send(Submission {
+            id: "1".to_string(),
+            op: Op::UserInput {
+                items: vec![InputItem::Text {
+                    text: "write a novel".to_string(),
+                }],
+            },
+        })
+        .await
+        .unwrap();
+        loop {
+            if let EventMsg::AgentMessageDelta(_) = next_event(&mut rx).await.msg {
+                break;
+            }
+        }
+
+        tx.send(Submission {
+            id: "2".to_string(),
+            op: Op::Interrupt,
+        })
+        .await
+        .unwrap();
+
+        let event = next_event(&mut rx).await;
+        assert_eq!(event.id, "1");
+        match event.msg {
+            EventMsg::AgentMessage(message) => {
+                assert!(message.message.starts_with("Partial"));
+                assert!(message.message.contains("interrupted"));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        let event = next_event(&mut rx).await;
+        assert_eq!(event.id, "1");
+        match event.msg {
+            EventMsg::TurnAborted(aborted) => {
+                assert_eq!(aborted.reason, TurnAbortReason::Interrupted)
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_range_is_attached() {
+        let body = format!("{}\n\n", "data: [DONE]");
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_recording(body, false, Some(requests_tx)).await;
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+        let config = Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            cwd: dir.path().to_path_buf(),
+            data_dir: None,
+            ..Config::default()
+        };
+        let (tx, mut rx) = Corty::spawn(config).unwrap();
+
+        let items = vec![InputItem::FileRange {
+            path: "notes.txt".into(),
+            start_line: 2,
+            end_line: 3,
+        }];
+        let events = run_user_turn_with(&tx, &mut rx, "1", items).await;
+
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event.msg, EventMsg::Error(_))));
+        let request = requests_rx.recv().await.unwrap();
+        assert!(request.contains(r#"<file path=\"notes.txt\" lines=\"2-3\">\ntwo\nthree\n</file>"#));
+    }
+
+    #[tokio::test]
+    async fn test_attachments_decode_lossily_and_refuse_binary_files() {
+        let body = format!("{}\n\n", "data: [DONE]");
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_recording(body, false, Some(requests_tx)).await;
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("latin1.txt"), b"one\ncaf\xe9\nthree\n").unwrap();
+        std::fs::write(dir.path().join("blob.bin"), b"one\ntw\0o\n").unwrap();
+        // Cut after 256 KiB, in the middle of an `é`.
+        let long = format!("a{}", "é".repeat(200 * 1024));
+        std::fs::write(dir.path().join("long.txt"), long).unwrap();
+        let config = Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            cwd: dir.path().to_path_buf(),
+            data_dir: None,
+            ..Config::default()
+        };
+        let (tx, mut rx) = Corty::spawn(config).unwrap();
+
+        let items = vec![InputItem::FileRange {
+            path: "latin1.txt".into(),
+            start_line: 2,
+            end_line: 2,
+        }];
+        run_user_turn_with(&tx, &mut rx, "1", items).await;
+        let request = requests_rx.recv().await.unwrap();
+        assert!(request.contains("lines=\\\"2-2\\\">\\ncaf\u{FFFD}\\n</file>"));
+
+        let items = vec![InputItem::FileRange {
+            path: "blob.bin".into(),
+            start_line: 1,
+            end_line: 2,
+        }];
+        let events = run_user_turn_with(&tx, &mut rx, "2", items).await;
+        assert!(events.iter().any(|event| matches!(
+            &event.msg,
+            EventMsg::Error(error) if error.message.contains("binary files cannot be attached")
+        )));
+
+        let items = vec![InputItem::File {
+            path: "long.txt".into(),
+        }];
+        run_user_turn_with(&tx, &mut rx, "3", items).await;
+        let request = requests_rx.recv().await.unwrap();
+        assert!(request.contains("[truncated: attachment limited to"));
+        assert!(!request.contains('\u{FFFD}'));
+    }
+
+    #[tokio::test]
+    async fn test_missing_file_fails_turn() {
+        let base_url = mock_provider(String::new()).await;
+        let (tx, mut rx) = spawn_engine(provider(base_url, WireApi::Chat));
+
+        let items = vec![InputItem::File {
+            path: "/definitely/not/here.txt".into(),
+        }];
+        let events = run_user_turn_with(&tx, &mut rx, "1", items).await;
+
+        assert!(events.iter().any(|event| matches!(
+            &event.msg,
+            EventMsg::Error(error) if error.message.starts_with("cannot attach")
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_session_is_recorded_and_resumed() {
+        let body = [
+            r#"data: {"choices":[{"delta":{"content":"Noted"}}]}"#,
+            "data: [DONE]",
+        ]
+        .map(|line| format!("{}\n\n", line))
+        .concat();
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_recording(body, false, Some(requests_tx)).await;
+        let data_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            data_dir: Some(data_dir.path().to_path_buf()),
+            ..Config::default()
+        };
+
+        let (tx, mut rx) = Corty::spawn(config.clone()).unwrap();
+        run_user_turn(&tx, &mut rx, "1", "remember the word pelican").await;
+        drop(tx);
+
+        // The recorder writes in the background; wait for the reply to land.
+        let mut sessions = Vec::new();
+        for _ in 0..100 {
+            sessions = list_sessions(data_dir.path()).unwrap();
+            if sessions.len() == 1 && load_session(&sessions[0].path).unwrap().items.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(sessions.len(), 1);
+        let saved = load_session(&sessions[0].path).unwrap();
+        assert_eq!(saved.items.len(), 2);
+
+        let (tx, mut rx) = Corty::spawn(Config {
+            resume_session: Some(sessions[0].path.clone()),
+            ..config
+        })
+        .unwrap();
+        run_user_turn(&tx, &mut rx, "2", "what was the word?").await;
+
+        requests_rx.recv().await.unwrap();
+        let resumed_request = requests_rx.recv().await.unwrap();
+        assert!(resumed_request.contains("pelican"));
+        assert!(resumed_request.contains("Noted"));
+        assert_eq!(list_sessions(data_dir.path()).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_history_is_shared_and_capped() {
+        let base_url = mock_provider(String::new()).await;
+        let data_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            data_dir: Some(data_dir.path().to_path_buf()),
+            history_max_bytes: 1024,
+            ..Config::default()
+        };
+        let (tx_a, mut rx_a) = Corty::spawn(config.clone()).unwrap();
+        let (tx_b, _rx_b) = Corty::spawn(config.clone()).unwrap();
+
+        let add = |i: usize| Submission {
+            id: format!("h{i}"),
+            op: Op::AddToHistory {
+                text: format!("prompt number {i}"),
+            },
+        };
+        for i in 0..19 {
+            let tx = if i % 2 == 0 { &tx_a } else { &tx_b };
+            tx.send(add(i)).await.unwrap();
+        }
+        // Let the other engine finish its appends before the last one.
+        drop(tx_b);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        tx_a.send(add(19)).await.unwrap();
+
+        tx_a.send(Submission {
+            id: "cfg".to_string(),
+            op: Op::ConfigureSession {
+                provider: config.model_provider.clone(),
+                model: String::new(),
+                cwd: std::env::temp_dir(),
+            },
+        })
+        .await
+        .unwrap();
+        let count = match next_event(&mut rx_a).await.msg {
+            EventMsg::SessionConfigured(configured) => configured.history_entry_count,
+            other => panic!("unexpected event: {:?}", other),
+        };
+        assert!(count > 0 && count < 20, "history not capped: {count}");
+        let history = std::fs::read(data_dir.path().join("history.jsonl")).unwrap();
+        assert!(history.len() <= 1024);
+
+        tx_a.send(Submission {
+            id: "get".to_string(),
+            op: Op::GetHistoryEntry { offset: count - 1 },
+        })
+        .await
+        .unwrap();
+        let event = next_event(&mut rx_a).await;
+        assert_eq!(event.id, "get");
+        match event.msg {
+            EventMsg::HistoryEntry(response) => {
+                assert_eq!(response.entry.unwrap().text, "prompt number 19");
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        tx_a.send(Submission {
+            id: "get".to_string(),
+            op: Op::GetHistoryEntry { offset: count },
+        })
+        .await
+        .unwrap();
+        match next_event(&mut rx_a).await.msg {
+            EventMsg::HistoryEntry(response) => assert!(response.entry.is_none()),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history_is_compacted() {
+        let body = [
+            r#"data: {"choices":[{"delta":{"content":"Sure"}}]}"#,
+            "data: [DONE]",
+        ]
+        .map(|line| format!("{}\n\n", line))
+        .concat();
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_recording(body, false, Some(requests_tx)).await;
+        let config = Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            data_dir: None,
+            auto_compact_token_limit: Some(1),
+            ..Config::default()
+        };
+        let (tx, mut rx) = Corty::spawn(config).unwrap();
+
+        // The two most recent turns are kept, so only the third one compacts.
+        for (id, text) in [("1", "first prompt"), ("2", "second prompt")] {
+            let events = run_user_turn(&tx, &mut rx, id, text).await;
+            assert!(!events
+                .iter()
+                .any(|event| matches!(event.msg, EventMsg::ContextCompacted(_))));
+        }
+        let events = run_user_turn(&tx, &mut rx, "3", "third prompt").await;
+        let compacted = events
+            .iter()
+            .find_map(|event| match &event.msg {
+                EventMsg::ContextCompacted(compacted) => Some(compacted.clone()),
+                _ => None,
+            })
+            .expect("no ContextCompacted event");
+        assert_eq!(compacted.items_summarized, 2);
+
+        let requests: Vec<String> = std::iter::from_fn(|| requests_rx.try_recv().ok()).collect();
+        assert_eq!(requests.len(), 4);
+        assert!(!requests[3].contains("first prompt"));
+        assert!(requests[3].contains("compacted"));
+        assert!(requests[3].contains("second prompt"));
+
+        tx.send(Submission {
+            id: "c".to_string(),
+            op: Op::Compact,
+        })
+        .await
+        .unwrap();
+        let mut saw_compacted = false;
+        loop {
+            match next_event(&mut rx).await.msg {
+                EventMsg::ContextCompacted(_) => saw_compacted = true,
+                EventMsg::TaskComplete(_) => break,
+                _ => {}
+            }
+        }
+        assert!(saw_compacted);
+    }
+
+    #[tokio::test]
+    async fn test_history_is_compacted_within_a_tool_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let text: String = (1..=400)
+            .map(|i| format!("line {i} of a file that fills the context\n"))
+            .collect();
+        std::fs::write(dir.path().join("big.txt"), text).unwrap();
+        let sure = format!(
+            "{}\n\ndata: [DONE]\n\n",
+            r#"data: {"choices":[{"delta":{"content":"Sure"}}]}"#
+        );
+        let base_url = mock_provider_sequence(
+            vec![
+                sure.clone(),
+                sure.clone(),
+                chat_tool_call_body("read_file", &json!({"path": "big.txt"}).to_string()),
+                sure,
+            ],
+            false,
+            None,
+        )
+        .await;
+        let (tx, mut rx) = tool_loop_engine(
+            base_url,
+            Config {
+                cwd: dir.path().to_path_buf(),
+                auto_compact_token_limit: Some(1000),
+                ..Config::default()
+            },
+        );
+
+        for (id, text) in [("1", "first prompt"), ("2", "second prompt")] {
+            run_user_turn(&tx, &mut rx, id, text).await;
+        }
+        let events = run_user_turn(&tx, &mut rx, "3", "read big.txt").await;
+        let tool_end = events
+            .iter()
+            .position(|event| matches!(event.msg, EventMsg::ToolCallEnd(_)))
+            .expect("no ToolCallEnd");
+        let compacted = events
+            .iter()
+            .position(|event| matches!(event.msg, EventMsg::ContextCompacted(_)))
+            .expect("no ContextCompacted event");
+        assert!(compacted > tool_end);
+    }
+
+    #[tokio::test]
+    async fn test_summaries_are_compacted_again() {
+        let body = [
+            r#"data: {"choices":[{"delta":{"content":"Sure"}}]}"#,
+            "data: [DONE]",
+        ]
+        .map(|line| format!("{}\n\n", line))
+        .concat();
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_recording(body, false, Some(requests_tx)).await;
+        let config = Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            data_dir: None,
+            auto_compact_token_limit: Some(1),
+            ..Config::default()
+        };
+        let (tx, mut rx) = Corty::spawn(config).unwrap();
+        let items_summarized = |events: &[Event]| {
+            events.iter().find_map(|event| match &event.msg {
+                EventMsg::ContextCompacted(compacted) => Some(compacted.items_summarized),
+                _ => None,
+            })
+        };
+
+        for (id, text) in [("1", "first prompt"), ("2", "second prompt")] {
+            run_user_turn(&tx, &mut rx, id, text).await;
+        }
+        let events = run_user_turn(&tx, &mut rx, "3", "third prompt").await;
+        assert_eq!(items_summarized(&events), Some(2));
+
+        // The summary is not a turn, so the second turn goes along with it.
+        tx.send(Submission {
+            id: "c".to_string(),
+            op: Op::Compact,
+        })
+        .await
+        .unwrap();
+        let mut events = Vec::new();
+        loop {
+            let event = next_event(&mut rx).await;
+            let done = matches!(event.msg, EventMsg::TaskComplete(_));
+            events.push(event);
+            if done {
+                break;
+            }
+        }
+        assert_eq!(items_summarized(&events), Some(3));
+
+        let events = run_user_turn(&tx, &mut rx, "4", "fourth prompt").await;
+        assert_eq!(items_summarized(&events), Some(3));
+        let requests: Vec<String> = std::iter::from_fn(|| requests_rx.try_recv().ok()).collect();
+        let last = requests.last().unwrap();
+        assert!(!last.contains("third prompt"));
+        assert!(last.contains("fourth prompt"));
+        assert_eq!(last.matches("were compacted").count(), 1);
+    }
+
+    /// Chat Completions SSE body calling `tool` with `arguments`.
+    fn chat_tool_call_body(tool: &str, arguments: &str) -> String {
+        let call = json!({
+            "choices": [{"delta": {"tool_calls": [{
+                "index": 0,
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": tool, "arguments": arguments},
+            }]}}]
+        });
+        format!("data: {}\n\ndata: [DONE]\n\n", call)
+    }
+
+    fn tool_loop_engine(base_url: String, config: Config) -> (Sender<Submission>, Receiver<Event>) {
+        Corty::spawn(Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            data_dir: None,
+            ..config
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_are_answered_until_final_message() {
+        let final_body = format!(
+            "{}\n\ndata: [DONE]\n\n",
+            r#"data: {"choices":[{"delta":{"content":"done"}}]}"#
+        );
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_sequence(
+            vec![
+                chat_tool_call_body("no_such_tool", r#"{"x":1}"#),
+                final_body,
+            ],
+            false,
+            Some(requests_tx),
+        )
+        .await;
+        let (tx, mut rx) = tool_loop_engine(base_url, Config::default());
+
+        let events = run_user_turn(&tx, &mut rx, "1", "go").await;
+
+        let begin = events
+            .iter()
+            .find_map(|event| match &event.msg {
+                EventMsg::ToolCallBegin(begin) => Some(begin.clone()),
+                _ => None,
+            })
+            .expect("no ToolCallBegin");
+        assert_eq!(begin.tool, "no_such_tool");
+        assert_eq!(begin.arguments["x"], 1);
+        assert!(events.iter().any(|event| matches!(
+            &event.msg,
+            EventMsg::ToolCallEnd(end) if !end.success && end.output.contains("unknown tool")
+        )));
+        match &events.last().unwrap().msg {
+            EventMsg::TaskComplete(complete) => {
+                assert_eq!(complete.last_agent_message.as_deref(), Some("done"))
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        requests_rx.recv().await.unwrap();
+        let second_request = requests_rx.recv().await.unwrap();
+        assert!(second_request.contains(r#""tool_calls""#));
+        assert!(second_request.contains(r#""tool_call_id":"call_1""#));
+    }
+
+    /// Run a turn against a model that calls the same tool forever and
+    /// return why it was stopped.
+    async fn endless_tool_calls(config: Config, arguments: &str) -> (TurnAbortReason, usize) {
+        let base_url = mock_provider_sequence(
+            vec![chat_tool_call_body("no_such_tool", arguments)],
+            false,
+            None,
+        )
+        .await;
+        let (tx, mut rx) = tool_loop_engine(base_url, config);
+        tx.send(Submission {
+            id: "1".to_string(),
+            op: Op::UserInput {
+                items: vec![InputItem::Text {
+                    text: "loop".to_string(),
+                }],
+            },
+        })
+        .await
+        .unwrap();
+
+        let mut tool_calls = 0;
+        loop {
+            match next_event(&mut rx).await.msg {
+                EventMsg::ToolCallEnd(_) => tool_calls += 1,
+                EventMsg::TurnAborted(aborted) => return (aborted.reason, tool_calls),
+                EventMsg::TaskComplete(_) => panic!("turn was not stopped"),
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_turn_limits_stop_tool_loops() {
+        let (reason, tool_calls) = endless_tool_calls(Config::default(), "{}").await;
+        assert_eq!(reason, TurnAbortReason::RepeatedToolCall);
+        assert_eq!(tool_calls, 3);
+
+        let config = Config {
+            max_tool_iterations: 2,
+            max_repeated_tool_calls: 100,
+            ..Config::default()
+        };
+        let (reason, tool_calls) = endless_tool_calls(config, "{}").await;
+        assert_eq!(reason, TurnAbortReason::MaxIterations);
+        assert_eq!(tool_calls, 2);
+    }
+
+    #[tokio::test]
+    async fn test_turn_times_out() {
+        let body = format!(
+            "{}\n\n",
+            r#"data: {"choices":[{"delta":{"content":"Thinking"}}]}"#
+        );
+        let base_url = mock_provider_with(body, true).await;
+        let (tx, mut rx) = tool_loop_engine(
+            base_url,
+            Config {
+                max_turn_duration: Duration::from_millis(200),
+                ..Config::default()
+            },
+        );
+
+        let events = run_user_turn_until_aborted(&tx, &mut rx).await;
+        assert!(events.iter().any(|event| matches!(
+            &event.msg,
+            EventMsg::TurnAborted(aborted) if aborted.reason == TurnAbortReason::Timeout
+        )));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_turn_timeout_is_recorded_as_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = json!({
+            "choices": [{"delta": {"tool_calls": [
+                {
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "exec", "arguments": r#"{"command":["sleep","30"]}"#},
+                },
+                {
+                    "index": 1,
+                    "id": "call_2",
+                    "type": "function",
+                    "function": {"name": "exec", "arguments": r#"{"command":["true"]}"#},
+                },
+            ]}}]
+        });
+        let final_body = format!(
+            "{}\n\ndata: [DONE]\n\n",
+            r#"data: {"choices":[{"delta":{"content":"ok"}}]}"#
+        );
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_sequence(
+            vec![format!("data: {}\n\ndata: [DONE]\n\n", calls), final_body],
+            false,
+            Some(requests_tx),
+        )
+        .await;
+        let (tx, mut rx) = tool_loop_engine(
+            base_url,
+            Config {
+                cwd: dir.path().to_path_buf(),
+                approval_policy: AskForApproval::Never,
+                max_turn_duration: Duration::from_millis(300),
+                ..Config::default()
+            },
+        );
+
+        let events = run_user_turn_until_aborted(&tx, &mut rx).await;
+        assert!(matches!(
+            &events.last().unwrap().msg,
+            EventMsg::TurnAborted(aborted) if aborted.reason == TurnAbortReason::Timeout
+        ));
+        let (success, output) = tool_call_end(&events);
+        assert!(!success);
+        assert!(
+            output.starts_with("Command killed because the turn ran out of time."),
+            "{output}"
+        );
+        run_user_turn(&tx, &mut rx, "2", "go on").await;
+
+        requests_rx.recv().await.unwrap();
+        let second_request = requests_rx.recv().await.unwrap();
+        assert!(second_request.contains("turn stopped (time limit reached)"));
+        assert!(!second_request.contains("interrupted by the user"));
+    }
+
+    async fn run_user_turn_until_aborted(
+        tx: &Sender<Submission>,
+        rx: &mut Receiver<Event>,
+    ) -> Vec<Event> {
+        tx.send(Submission {
+            id: "1".to_string(),
+            op: Op::UserInput {
+                items: vec![InputItem::Text {
+                    text: "think hard".to_string(),
+                }],
+            },
+        })
+        .await
+        .unwrap();
+        let mut events = Vec::new();
+        loop {
+            let event = next_event(rx).await;
+            let done = matches!(
+                event.msg,
+                EventMsg::TurnAborted(_) | EventMsg::TaskComplete(_)
+            );
+            events.push(event);
+            if done {
+                return events;
+            }
+        }
+    }
+
+    /// Have the model call `tool` once and return the events of the turn.
+    async fn tool_events(cwd: &std::path::Path, tool: &str, arguments: Value) -> Vec<Event> {
+        let config = Config {
+            cwd: cwd.to_path_buf(),
+            data_dir: None,
+            approval_policy: AskForApproval::Never,
+            ..Config::default()
+        };
+        tool_turn(config, tool, arguments, ReviewDecision::Denied).await
+    }
+
+    /// Have the model call `tool` once under `config`, answering every
+    /// approval request with `decision`, and return the events of the turn.
+    async fn tool_turn(
+        config: Config,
+        tool: &str,
+        arguments: Value,
+        decision: ReviewDecision,
+    ) -> Vec<Event> {
+        let final_body = format!(
+            "{}\n\ndata: [DONE]\n\n",
+            r#"data: {"choices":[{"delta":{"content":"ok"}}]}"#
+        );
+        let base_url = mock_provider_sequence(
+            vec![
+                chat_tool_call_body(tool, &arguments.to_string()),
+                final_body,
+            ],
+            false,
+            None,
+        )
+        .await;
+        let (tx, mut rx) = Corty::spawn(Config {
+            model_provider: provider(base_url, WireApi::Chat),
+            ..config
+        })
+        .unwrap();
+
+        tx.send(Submission {
+            id: "1".to_string(),
+            op: Op::UserInput {
+                items: vec![InputItem::Text {
+                    text: "use the tool".to_string(),
+                }],
+            },
+        })
+        .await
+        .unwrap();
+        let mut events = Vec::new();
+        loop {
+            let event = next_event(&mut rx).await;
+            let op = match &event.msg {
+                EventMsg::ExecApprovalRequest(request) => Some(Op::ExecApproval {
+                    id: request.call_id.clone(),
+                    decision,
+                }),
+                EventMsg::ApplyPatchApprovalRequest(request) => Some(Op::PatchApproval {
+                    id: request.call_id.clone(),
+                    decision,
+                }),
+                _ => None,
+            };
+            if let Some(op) = op {
+                tx.send(Submission {
+                    id: "2".to_string(),
+                    op,
+                })
+                .await
+                .unwrap();
+            }
+            let done = matches!(
+                event.msg,
+                EventMsg::TaskComplete(_) | EventMsg::TurnAborted(_)
+            );
+            events.push(event);
+            if done {
+                return events;
+            }
+        }
+    }
+
+    fn tool_call_end(events: &[Event]) -> (bool, String) {
+        events
+            .iter()
+            .find_map(|event| match &event.msg {
+                EventMsg::ToolCallEnd(end) => Some((end.success, end.output.clone())),
+                _ => None,
+            })
+            .expect("no ToolCallEnd")
+    }
+
+    fn approval_requests(events: &[Event]) -> Vec<&EventMsg> {
+        events
+            .iter()
+            .map(|event| &event.msg)
+            .filter(|msg| {
+                matches!(
+                    msg,
+                    EventMsg::ExecApprovalRequest(_) | EventMsg::ApplyPatchApprovalRequest(_)
+                )
+            })
+            .collect()
+    }
+
+    /// Have the model call `tool` once and return the tool output.
+    async fn tool_output(cwd: &std::path::Path, tool: &str, arguments: Value) -> (bool, String) {
+        tool_call_end(&tool_events(cwd, tool, arguments).await)
+    }
+
+    #[tokio::test]
+    async fn test_read_file_tool() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines: Vec<String> = (1..=3000).map(|i| format!("line {i}")).collect();
+        std::fs::write(dir.path().join("big.txt"), lines.join("\n")).unwrap();
+        std::fs::write(dir.path().join("blob.bin"), [0u8, 1, 2, 3]).unwrap();
+
+        let (success, output) = tool_output(
+            dir.path(),
+            "read_file",
+            json!({"path": "big.txt", "start_line": 2, "end_line": 3}),
+        )
+        .await;
+        assert!(success);
+        assert_eq!(output, "     2\tline 2\n     3\tline 3\n");
+
+        let (success, output) =
+            tool_output(dir.path(), "read_file", json!({"path": "big.txt"})).await;
+        assert!(success);
+        assert!(output.contains("  2000\tline 2000\n"));
+        assert!(!output.contains("line 2001\n"));
+        assert!(output.ends_with("call read_file with start_line=2001 to continue]\n"));
+
+        let (success, output) =
+            tool_output(dir.path(), "read_file", json!({"path": "blob.bin"})).await;
+        assert!(!success);
+        assert!(output.contains("binary"));
+
+        let (success, output) =
+            tool_output(dir.path(), "read_file", json!({"path": "../outside.txt"})).await;
+        assert!(!success);
+        assert!(output.contains("outside the working directory"));
+    }
+
+    #[tokio::test]
+    async fn test_apply_patch_tool() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("main.rs"),
+            "fn main() {\n    let name = \"world\";  \n    println!(\"Hello, {}!\", name);\n}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("old.txt"), "obsolete\n").unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "pub fn a() {}\n").unwrap();
+
+        // Context lines differ from the file in whitespace and still match.
+        let patch = "*** Begin Patch
+*** Update File: main.rs
+@@ fn main() {
+ let name = \"world\";
+-    println!(\"Hello, {}!\", name);
++    println!(\"Hello, {name}!\");
+ }
+*** Add File: docs/NOTES.md
++notes
+*** Delete File: old.txt
+*** Update File: lib.rs
+*** Move to: src/lib.rs
+@@
+-pub fn a() {}
++pub fn b() {}
+*** End Patch";
+
+        let (success, output) = tool_output(
+            dir.path(),
+            "apply_patch",
+            json!({"patch": patch, "dry_run": true}),
+        )
+        .await;
+        assert!(success, "{output}");
+        assert!(output.contains("M main.rs\nA docs/NOTES.md\nD old.txt\nR lib.rs -> src/lib.rs\n"));
+        assert!(output.contains("+    println!(\"Hello, {name}!\");"));
+        assert!(dir.path().join("old.txt").exists());
+        assert!(!dir.path().join("docs").exists());
+
+        let (success, output) =
+            tool_output(dir.path(), "apply_patch", json!({"patch": patch})).await;
+        assert!(success, "{output}");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("main.rs")).unwrap(),
+            "fn main() {\n    let name = \"world\";  \n    println!(\"Hello, {name}!\");\n}\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("docs/NOTES.md")).unwrap(),
+            "notes\n"
+        );
+        assert!(!dir.path().join("old.txt").exists());
+        assert!(!dir.path().join("lib.rs").exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("src/lib.rs")).unwrap(),
+            "pub fn b() {}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_patch_is_all_or_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "one\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "two\n").unwrap();
+
+        let patch = "*** Begin Patch
+*** Update File: a.txt
+-one
++ONE
+*** Update File: b.txt
+-three
++THREE
+*** End Patch";
+        let (success, output) =
+            tool_output(dir.path(), "apply_patch", json!({"patch": patch})).await;
+        assert!(!success);
+        assert!(output.contains("b.txt: hunk 1 does not match"), "{output}");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "one\n"
+        );
+
+        let escape = "*** Begin Patch\n*** Add File: ../evil.txt\n+x\n*** End Patch";
+        let (success, output) =
+            tool_output(dir.path(), "apply_patch", json!({"patch": escape})).await;
+        assert!(!success);
+        assert!(output.contains("outside the working directory"));
+    }
+
+    #[tokio::test]
+    async fn test_apply_patch_keeps_missing_final_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "one\ntwo").unwrap();
+
+        let patch = "*** Begin Patch\n*** Update File: a.txt\n-one\n+ONE\n*** End Patch";
+        let (success, output) =
+            tool_output(dir.path(), "apply_patch", json!({"patch": patch})).await;
+        assert!(success, "{output}");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "ONE\ntwo"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_apply_patch_rejects_dangling_symlink_out_of_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let target = outside.path().join("planted.txt");
+        std::os::unix::fs::symlink(&target, dir.path().join("link.txt")).unwrap();
+
+        let patch = "*** Begin Patch\n*** Add File: link.txt\n+x\n*** End Patch";
+        let (success, output) =
+            tool_output(dir.path(), "apply_patch", json!({"patch": patch})).await;
+        assert!(!success);
+        assert!(output.contains("symlink"), "{output}");
+        assert!(!target.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_grep_and_glob_tools() {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in [
+            (".gitignore", "target/\n"),
+            ("src/lib.rs", "pub mod parser;\n\npub fn parse() {}\n"),
+            (
+                "src/parser.rs",
+                "// parser\nfn helper() {}\npub fn parse_line() {}\n",
+            ),
+            ("target/debug/build.rs", "pub fn parse() {}\n"),
+            (".cache/stale.rs", "pub fn parse() {}\n"),
+        ] {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let (success, output) = tool_output(dir.path(), "glob", json!({"pattern": "*.rs"})).await;
+        assert!(success);
+        assert_eq!(output, "src/lib.rs\nsrc/parser.rs\n");
+
+        let (success, output) = tool_output(
+            dir.path(),
+            "grep",
+            json!({"pattern": "pub fn parse", "context": 1}),
+        )
+        .await;
+        assert!(success);
+        assert_eq!(
+            output,
+            "src/lib.rs-2-\nsrc/lib.rs:3:pub fn parse() {}\n--\n\
+             src/parser.rs-2-fn helper() {}\nsrc/parser.rs:3:pub fn parse_line() {}\n"
+        );
+
+        let (success, output) = tool_output(
+            dir.path(),
+            "grep",
+            json!({"pattern": "PARSER", "case_insensitive": true, "glob": "parser.rs"}),
+        )
+        .await;
+        assert!(success);
+        assert_eq!(output, "src/parser.rs:1:// parser\n");
+
+        let (success, output) =
+            tool_output(dir.path(), "grep", json!({"pattern": "no such text"})).await;
+        assert!(success);
+        assert_eq!(output, "No matches.");
+
+        let (success, output) = tool_output(dir.path(), "grep", json!({"pattern": "("})).await;
+        assert!(!success);
+        assert!(output.starts_with("invalid pattern"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_update_plan_tool() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = tool_events(
+            dir.path(),
+            "update_plan",
+            json!({
+                "explanation": "Two files to change",
+                "plan": [
+                    {"step": "Update the parser", "status": "done"},
+                    {"step": "Update the tests", "status": "in_progress"}
+                ]
+            }),
+        )
+        .await;
+        let update = events
+            .iter()
+            .find_map(|event| match &event.msg {
+                EventMsg::PlanUpdate(update) => Some(update.clone()),
+                _ => None,
+            })
+            .expect("no PlanUpdate");
+        assert_eq!(update.explanation.as_deref(), Some("Two files to change"));
+        assert_eq!(
+            update
+                .plan
+                .iter()
+                .map(|item| (item.step.as_str(), item.status))
+                .collect::<Vec<_>>(),
+            [
+                ("Update the parser", StepStatus::Done),
+                ("Update the tests", StepStatus::InProgress)
+            ]
+        );
+        assert_eq!(tool_call_end(&events), (true, "Plan updated.".to_string()));
+
+        let (success, output) = tool_output(
+            dir.path(),
+            "update_plan",
+            json!({"plan": [
+                {"step": "a", "status": "in_progress"},
+                {"step": "b", "status": "in_progress"}
+            ]}),
+        )
+        .await;
+        assert!(!success);
+        assert!(output.contains("at most one"), "{output}");
+    }
+
+    /// Paths and scores of the hits in a `semantic_search` output, in order.
+    fn search_hits(output: &str) -> Vec<(String, f32)> {
+        output
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+            .map(|line| {
+                let (_, rest) = line.split_once(". ").unwrap();
+                let (location, _) = rest.split_once(' ').unwrap();
+                let (path, _) = location.split_once(':').unwrap();
+                let (_, score) = rest.rsplit_once("score ").unwrap();
+                (
+                    path.to_string(),
+                    score.trim_end_matches(')').parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_tool() {
+        let dir = tempfile::tempdir().unwrap();
+        let index_dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let embedding = EmbeddingProvider::Hashing {
+            dimensions: DEFAULT_HASHING_DIMENSIONS,
+        };
+        let config = Config {
+            cwd: root.clone(),
+            data_dir: None,
+            approval_policy: AskForApproval::Never,
+            index_dir: Some(index_dir.path().to_path_buf()),
+            embedding: embedding.clone(),
+            ..Config::default()
+        };
+        let search = |arguments: Value| {
+            let config = config.clone();
+            async move {
+                tool_call_end(
+                    &tool_turn(config, "semantic_search", arguments, ReviewDecision::Denied).await,
+                )
+            }
+        };
+
+        let (success, output) = search(json!({"query": "retry backoff delay"})).await;
+        assert!(!success);
+        assert!(output.contains("has not been indexed"), "{output}");
+
+        // Nine exact matches outside `src`, then one close match and two
+        // unrelated files inside it.
+        std::fs::create_dir_all(root.join("vendor")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        for i in 0..9 {
+            std::fs::write(
+                root.join(format!("vendor/{i}.txt")),
+                "retry backoff delay\n",
+            )
+            .unwrap();
+        }
+        std::fs::write(root.join("src/a.txt"), "retry backoff delay jitter\n").unwrap();
+        std::fs::write(root.join("src/b.txt"), "parse tokens\n").unwrap();
+        std::fs::write(
+            root.join("src/c.py"),
+            "def parse_tokens(text):\n    return text.split()\n",
+        )
+        .unwrap();
+        let db = VectorDB::connect(&index_dir.path().to_string_lossy(), &embedding)
+            .await
+            .unwrap();
+        index_path(&db, &root, &root, |_| {}).await.unwrap();
+
+        // Best first, cut to the limit.
+        let (success, output) = search(json!({"query": "retry backoff delay", "limit": 3})).await;
+        assert!(success, "{output}");
+        let hits = search_hits(&output);
+        assert_eq!(hits.len(), 3, "{output}");
+        assert!(hits.iter().all(|(path, _)| path.starts_with("vendor/")));
+        let (success, output) = search(json!({"query": "retry backoff delay", "limit": 12})).await;
+        assert!(success, "{output}");
+        let hits = search_hits(&output);
+        assert_eq!(hits.len(), 12);
+        assert!(
+            hits.windows(2).all(|pair| pair[0].1 >= pair[1].1),
+            "{output}"
+        );
+        assert_eq!(hits[9].0, "src/a.txt");
+
+        // The glob is applied to `limit` times five candidates: two results
+        // reach only the tenth hit, three reach all of `src`.
+        let (success, output) =
+            search(json!({"query": "retry backoff delay", "limit": 2, "path_glob": "src/**"}))
+                .await;
+        assert!(success, "{output}");
+        let paths: Vec<String> = search_hits(&output)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, vec!["src/a.txt"]);
+        let (success, output) =
+            search(json!({"query": "retry backoff delay", "limit": 3, "path_glob": "src/**"}))
+                .await;
+        assert!(success, "{output}");
+        let mut paths: Vec<String> = search_hits(&output)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths.remove(0), "src/a.txt");
+        paths.sort();
+        assert_eq!(paths, vec!["src/b.txt", "src/c.py"]);
+
+        let (success, output) =
+            search(json!({"query": "retry backoff delay", "language": "python"})).await;
+        assert!(success, "{output}");
+        let paths: Vec<String> = search_hits(&output)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, vec!["src/c.py"]);
+    }
+
+    #[tokio::test]
+    async fn test_delegate_tool_runs_sub_agent() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "the answer is 42\n").unwrap();
+        let final_body = |text: &str| {
+            format!(
+                "data: {}\n\ndata: [DONE]\n\n",
+                json!({"choices": [{"delta": {"content": text}}]})
+            )
+        };
+        let (requests_tx, mut requests_rx) = unbounded_channel();
+        let base_url = mock_provider_sequence(
+            vec![
+                chat_tool_call_body(
+                    "delegate",
+                    &json!({"task": "find the answer in notes.txt"}).to_string(),
+                ),
+                chat_tool_call_body("read_file", &json!({"path": "notes.txt"}).to_string()),
+                final_body("The answer is 42 (notes.txt:1)."),
+                final_body("done"),
+            ],
+            false,
+            Some(requests_tx),
+        )
+        .await;
+        let (tx, mut rx) = tool_loop_engine(
+            base_url,
+            Config {
+                cwd: dir.path().to_path_buf(),
+                ..Config::default()
+            },
+        );
+
+        let events = run_user_turn(&tx, &mut rx, "1", "ask a sub-agent").await;
+
+        // Only the report reaches the parent.
+        assert_eq!(
+            tool_call_end(&events),
+            (true, "The answer is 42 (notes.txt:1).".to_string())
+        );
+        let nested: Vec<&EventMsg> = events
+            .iter()
+            .filter_map(|event| match &event.msg {
+                EventMsg::SubAgent(sub_agent) => {
+                    assert_eq!(sub_agent.call_id, "call_1");
+                    Some(&*sub_agent.msg)
+                }
+                _ => None,
+            })
+            .collect();
+        assert!(nested
+            .iter()
+            .any(|msg| matches!(msg, EventMsg::ToolCallBegin(begin) if begin.tool == "read_file")));
+        assert!(nested.iter().any(
+            |msg| matches!(msg, EventMsg::ToolCallEnd(end) if end.output.contains("answer is 42"))
+        ));
+
+        // The sub-agent starts without the conversation and with read-only tools.
+        requests_rx.recv().await.unwrap();
+        let sub_agent_request = requests_rx.recv().await.unwrap();
+        assert!(sub_agent_request.contains("find the answer in notes.txt"));
+        assert!(!sub_agent_request.contains("ask a sub-agent"));
+        assert!(sub_agent_request.contains(r#""name":"read_file""#));
+        assert!(!sub_agent_request.contains(r#""name":"exec""#));
+        assert!(!sub_agent_request.contains(r#""name":"delegate""#));
+    }
+
+    #[tokio::test]
+    async fn test_sub_agent_stops_with_the_parent_turn() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_url = mock_provider_sequence(
+            vec![
+                chat_tool_call_body(
+                    "delegate",
+                    &json!({"task": "think about it forever"}).to_string(),
+                ),
+                // The sub-agent's response never completes.
+                format!(
+                    "{}\n\n",
+                    r#"data: {"choices":[{"delta":{"content":"Thinking"}}]}"#
+                ),
+            ],
+            true,
+            None,
+        )
+        .await;
+        let (tx, mut rx) = tool_loop_engine(
+            base_url,
+            Config {
+                cwd: dir.path().to_path_buf(),
+                max_turn_duration: Duration::from_millis(300),
+                ..Config::default()
+            },
+        );
+
+        let events = run_user_turn_until_aborted(&tx, &mut rx).await;
+        assert_eq!(
+            tool_call_end(&events),
+            (
+                false,
+                "the sub-agent was stopped: time limit reached".to_string()
+            )
+        );
+        assert!(matches!(
+            &events.last().unwrap().msg,
+            EventMsg::TurnAborted(aborted) if aborted.reason == TurnAbortReason::Timeout
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_exec_tool() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = tool_events(
+            dir.path(),
+            "exec",
+            json!({"command": ["sh", "-c", "echo out; echo err >&2; exit 3"]}),
+        )
+        .await;
+
+        let mut streamed = (String::new(), String::new());
+        for event in &events {
+            if let EventMsg::ExecCommandOutputDelta(delta) = &event.msg {
+                assert_eq!(delta.call_id, "call_1");
+                match delta.stream {
+                    ExecOutputStream::Stdout => streamed.0.push_str(&delta.chunk),
+                    ExecOutputStream::Stderr => streamed.1.push_str(&delta.chunk),
+                }
+            }
+        }
+        assert_eq!(streamed, ("out\n".to_string(), "err\n".to_string()));
+
+        let end = events
+            .iter()
+            .find_map(|event| match &event.msg {
+                EventMsg::ToolCallEnd(end) => Some(end.clone()),
+                _ => None,
+            })
+            .expect("no ToolCallEnd");
+        assert!(!end.success);
+        assert!(end.output.starts_with("Exit code: 3\n"), "{}", end.output);
+        assert!(end.output.contains("out\n") && end.output.contains("err\n"));
+
+        let (success, output) = tool_output(
+            dir.path(),
+            "exec",
+            json!({"command": ["seq", "1", "100000"]}),
+        )
+        .await;
+        assert!(success);
+        assert!(output.contains("Output:\n1\n2\n3\n"));
+        assert!(output.contains(" bytes omitted ...]\n"));
+        assert!(output.ends_with("99999\n100000\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exec_tool_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let started = std::time::Instant::now();
+        let (success, output) = tool_output(
+            dir.path(),
+            "exec",
+            json!({"command": ["sh", "-c", "echo started; sleep 30"], "timeout_ms": 200}),
+        )
+        .await;
+        assert!(!success);
+        assert!(
+            output.starts_with("Command timed out after 0.2s"),
+            "{output}"
+        );
+        assert!(output.contains("started"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_exec_tool_does_not_wait_for_background_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let started = std::time::Instant::now();
+        let (success, output) = tool_output(
+            dir.path(),
+            "exec",
+            json!({
+                "command": ["sh", "-c", "echo started; sleep 1000 & echo $! > sleeper.pid"],
+                "timeout_ms": 200
+            }),
+        )
+        .await;
+        assert!(success, "{output}");
+        assert!(output.contains("started"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // The sleeper held the pipes and was killed with the process group.
+        let pid = std::fs::read_to_string(dir.path().join("sleeper.pid")).unwrap();
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(
+            stat.map_or(true, |stat| stat.contains(") Z ")),
+            "sleeper {} is still running",
+            pid.trim()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exec_approval() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            cwd: dir.path().to_path_buf(),
+            data_dir: Some(data_dir.path().to_path_buf()),
+            approval_policy: AskForApproval::Always,
+            ..Config::default()
+        };
+        let command = json!({"command": ["echo", "approved"]});
+
+        let events = tool_turn(
+            config.clone(),
+            "exec",
+            command.clone(),
+            ReviewDecision::Approved,
+        )
+        .await;
+        match approval_requests(&events)[..] {
+            [EventMsg::ExecApprovalRequest(request)] => {
+                assert_eq!(request.command, ["echo", "approved"]);
+                assert_eq!(request.cwd, dir.path());
+            }
+            ref other => panic!("unexpected approval requests: {other:?}"),
+        }
+        let (success, output) = tool_call_end(&events);
+        assert!(success, "{output}");
+        assert!(output.contains("approved\n"));
+
+        let events = tool_turn(config, "exec", command, ReviewDecision::Denied).await;
+        let (success, output) = tool_call_end(&events);
+        assert!(!success);
+        assert!(output.contains("declined"), "{output}");
+
+        // The recorder writes in the background; wait for the decision.
+        let mut recorded = String::new();
+        for _ in 0..100 {
+            recorded = list_sessions(data_dir.path())
+                .unwrap()
+                .iter()
+                .map(|session| std::fs::read_to_string(&session.path).unwrap())
+                .collect();
+            if recorded.contains(r#""decision":"denied""#) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(recorded.contains(r#""decision":"approved""#), "{recorded}");
+        assert!(recorded.contains(r#""decision":"denied""#), "{recorded}");
+    }
+
+    #[tokio::test]
+    async fn test_exec_asks_to_retry_without_sandbox_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            cwd: dir.path().to_path_buf(),
+            data_dir: None,
+            approval_policy: AskForApproval::OnFailure,
+            ..Config::default()
+        };
+
+        // Fails like a sandbox denial the first time only.
+        let script = "test -e marker || { touch marker; echo 'Permission denied' >&2; exit 1; }";
+        let events = tool_turn(
+            config.clone(),
+            "exec",
+            json!({"command": ["sh", "-c", script]}),
+            ReviewDecision::Approved,
+        )
+        .await;
+        match approval_requests(&events)[..] {
+            [EventMsg::ExecApprovalRequest(request)] => assert!(request.reason.is_some()),
+            ref other => panic!("unexpected approval requests: {other:?}"),
+        }
+        let (success, output) = tool_call_end(&events);
+        assert!(success, "{output}");
+
+        // An ordinary failure is reported without asking.
+        let events = tool_turn(
+            config,
+            "exec",
+            json!({"command": ["sh", "-c", "echo 'test failed'; exit 1"]}),
+            ReviewDecision::Approved,
+        )
+        .await;
+        assert!(approval_requests(&events).is_empty());
+        let (success, output) = tool_call_end(&events);
+        assert!(!success);
+        assert!(output.starts_with("Exit code: 1\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_approval_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            cwd: dir.path().to_path_buf(),
+            data_dir: None,
+            approval_policy: AskForApproval::UnlessTrusted,
+            ..Config::default()
+        };
+
+        // Known safe commands run without asking.
+        let events = tool_turn(
+            config.clone(),
+            "exec",
+            json!({"command": ["ls"]}),
+            ReviewDecision::Denied,
+        )
+        .await;
+        assert!(approval_requests(&events).is_empty());
+        assert!(tool_call_end(&events).0);
+
+        // A repository's config can make git run programs, so git always
+        // asks.
+        for command in [
+            json!(["git", "status"]),
+            json!(["git", "diff", "--output=out.txt"]),
+            json!(["git", "log", "-p", "--ext-diff"]),
+            json!(["git", "show", "-c", "HEAD"]),
+        ] {
+            let events = tool_turn(
+                config.clone(),
+                "exec",
+                json!({"command": command}),
+                ReviewDecision::Denied,
+            )
+            .await;
+            assert_eq!(approval_requests(&events).len(), 1, "{command}");
+            assert!(!tool_call_end(&events).0);
+        }
+        assert!(!dir.path().join("out.txt").exists());
+
+        let patch = "*** Begin Patch\n*** Add File: new.txt\n+hello\n*** End Patch";
+        let events = tool_turn(
+            config,
+            "apply_patch",
+            json!({"patch": patch}),
+            ReviewDecision::Denied,
+        )
+        .await;
+        match approval_requests(&events)[..] {
+            [EventMsg::ApplyPatchApprovalRequest(request)] => {
+                assert_eq!(request.changes.len(), 1);
+            }
+            ref other => panic!("unexpected approval requests: {other:?}"),
+        }
+        assert!(!tool_call_end(&events).0);
+        assert!(!dir.path().join("new.txt").exists());
+    }
+}
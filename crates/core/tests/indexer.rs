@@ -0,0 +1,305 @@
+//! Tests for walking and chunking files for the code index
+
+#[cfg(test)]
+mod tests {
+    use corty_core::{
+        indexer::{
+            chunk_file, collect_files, index_path, language_for, read_source, IndexWatcher,
+            WatchEvent, WATCH_DEBOUNCE,
+        },
+        protocol::{ApiKeySource, ModelProviderInfo, WireApi},
+        storage::{project_table_name, EmbeddingProvider, VectorDB},
+    };
+    use std::{path::Path, time::Duration};
+
+    #[test]
+    fn test_line_windows_overlap_and_cover_the_file() {
+        let text: String = (1..=130).map(|i| format!("line {i}\n")).collect();
+        let chunks = chunk_file("notes.txt", "text", &text);
+        let ranges: Vec<(u32, u32)> = chunks
+            .iter()
+            .map(|chunk| (chunk.start_line, chunk.end_line))
+            .collect();
+        assert_eq!(ranges, vec![(1, 60), (51, 110), (101, 130)]);
+        assert!(chunks[0].content.starts_with("line 1\n"));
+        assert!(chunks[2].content.ends_with("line 130"));
+    }
+
+    #[test]
+    fn test_blank_files_have_no_chunks() {
+        assert!(chunk_file("empty.txt", "text", "\n  \n\n").is_empty());
+    }
+
+    #[test]
+    fn test_language_for() {
+        assert_eq!(language_for(Path::new("src/main.rs")), "rust");
+        assert_eq!(language_for(Path::new("app.TSX")), "typescript");
+        assert_eq!(language_for(Path::new("Makefile")), "text");
+    }
+
+    #[test]
+    fn test_collect_files_skips_ignored_and_read_source_skips_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        std::fs::write(dir.path().join("target/out.rs"), "fn out() {}").unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "fn lib() {}").unwrap();
+        std::fs::write(dir.path().join("logo.png"), b"\x89PNG\0\0").unwrap();
+
+        let files = collect_files(dir.path());
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["lib.rs", "logo.png"]);
+        assert_eq!(
+            read_source(&dir.path().join("lib.rs")).unwrap().as_deref(),
+            Some("fn lib() {}")
+        );
+        assert_eq!(read_source(&dir.path().join("logo.png")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rust_items_become_chunks_with_their_docs() {
+        let text = "//! Parsing.\n\nuse std::fmt;\n\n/// A point.\n#[derive(Debug)]\nstruct Point {\n    x: i32,\n}\n\nfn origin() -> Point {\n    Point { x: 0 }\n}\n";
+        let chunks = chunk_file("src/point.rs", "rust", text);
+        let summary: Vec<_> = chunks
+            .iter()
+            .map(|chunk| (chunk.start_line, chunk.end_line, chunk.symbol.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, 3, None),
+                (5, 9, Some("Point")),
+                (11, 13, Some("origin"))
+            ]
+        );
+        assert!(chunks[1]
+            .content
+            .starts_with("/// A point.\n#[derive(Debug)]"));
+    }
+
+    #[test]
+    fn test_long_items_are_split_at_methods_with_the_signature() {
+        let body: String = (0..50)
+            .map(|i| format!("        let _x{i} = {i};\n"))
+            .collect();
+        let text = format!(
+            "impl Parser {{\n    fn first(&self) {{\n{body}    }}\n\n    fn second(&self) {{\n{body}    }}\n}}\n"
+        );
+        let chunks = chunk_file("src/parser.rs", "rust", &text);
+        let symbols: Vec<_> = chunks.iter().map(|chunk| chunk.symbol.as_deref()).collect();
+        assert_eq!(symbols, vec![Some("Parser::first"), Some("Parser::second")]);
+        assert_eq!(chunks[1].start_line, 55);
+        assert!(chunks[1]
+            .content
+            .starts_with("impl Parser {\n    fn second(&self) {"));
+    }
+
+    #[test]
+    fn test_python_methods_carry_the_class_signature() {
+        let body: String = (0..50).map(|i| format!("        x{i} = {i}\n")).collect();
+        let text = format!(
+            "class Repo:\n    \"\"\"Stores things.\"\"\"\n\n    @property\n    def name(self):\n{body}\n    def save(self):\n{body}"
+        );
+        let chunks = chunk_file("repo.py", "python", &text);
+        let symbols: Vec<_> = chunks.iter().map(|chunk| chunk.symbol.as_deref()).collect();
+        assert_eq!(
+            symbols,
+            vec![Some("Repo"), Some("Repo.name"), Some("Repo.save")]
+        );
+        assert!(chunks[1].content.starts_with("class Repo:\n    @property"));
+    }
+
+    #[test]
+    fn test_unparsable_files_fall_back_to_line_windows() {
+        let chunks = chunk_file("broken.rs", "rust", "fn broken( {\n");
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].symbol, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reindex_only_touches_changed_files() {
+        let project = tempfile::tempdir().unwrap();
+        let index = tempfile::tempdir().unwrap();
+        let db = VectorDB::connect(
+            &index.path().to_string_lossy(),
+            &EmbeddingProvider::Hashing { dimensions: 32 },
+        )
+        .await
+        .unwrap();
+        let root = project.path();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(root.join("b.rs"), "fn b() {}\n").unwrap();
+        std::fs::write(root.join("c.rs"), "fn c() {}\n").unwrap();
+
+        let first = index_path(&db, root, root, |_| {}).await.unwrap();
+        assert_eq!((first.added, first.updated, first.removed), (3, 0, 0));
+        assert_eq!(first.chunks, 3);
+
+        std::fs::write(root.join("b.rs"), "fn b() -> u32 { 1 }\n").unwrap();
+        std::fs::remove_file(root.join("c.rs")).unwrap();
+        std::fs::write(root.join("d.rs"), "fn d() {}\n").unwrap();
+        let second = index_path(&db, root, root, |_| {}).await.unwrap();
+        assert_eq!(
+            (
+                second.added,
+                second.updated,
+                second.removed,
+                second.unchanged
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(second.chunks, 2);
+
+        // Indexing one file leaves the rest of the project alone.
+        let single = index_path(&db, root, &root.join("a.rs"), |_| {})
+            .await
+            .unwrap();
+        assert_eq!((single.unchanged, single.removed), (1, 0));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reindex_rebuilds_a_deleted_table() {
+        let project = tempfile::tempdir().unwrap();
+        let index = tempfile::tempdir().unwrap();
+        let db = VectorDB::connect(
+            &index.path().to_string_lossy(),
+            &EmbeddingProvider::Hashing { dimensions: 32 },
+        )
+        .await
+        .unwrap();
+        let root = project.path();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(root.join("b.rs"), "fn b() {}\n").unwrap();
+        index_path(&db, root, root, |_| {}).await.unwrap();
+
+        let table_name = project_table_name(root);
+        std::fs::remove_dir_all(index.path().join(format!("{table_name}.lance"))).unwrap();
+        let rebuilt = index_path(&db, root, root, |_| {}).await.unwrap();
+        assert_eq!((rebuilt.added, rebuilt.unchanged), (2, 0));
+        let table = db.find_table(&table_name).await.unwrap().unwrap();
+        assert_eq!(table.count_rows(None).await.unwrap(), 2);
+
+        // Changed and removed files replace their chunks by id.
+        std::fs::write(root.join("a.rs"), "fn a() -> u8 { 0 }\n").unwrap();
+        std::fs::remove_file(root.join("b.rs")).unwrap();
+        let second = index_path(&db, root, root, |_| {}).await.unwrap();
+        assert_eq!((second.updated, second.removed), (1, 1));
+        let table = db.find_table(&table_name).await.unwrap().unwrap();
+        assert_eq!(table.count_rows(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_index_rejects_another_embedder() {
+        let project = tempfile::tempdir().unwrap();
+        let index = tempfile::tempdir().unwrap();
+        let root = project.path();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        let index_dir = index.path().to_string_lossy();
+        let db = VectorDB::connect(&index_dir, &EmbeddingProvider::Hashing { dimensions: 32 })
+            .await
+            .unwrap();
+        index_path(&db, root, root, |_| {}).await.unwrap();
+
+        let db = VectorDB::connect(&index_dir, &EmbeddingProvider::Hashing { dimensions: 64 })
+            .await
+            .unwrap();
+        let err = index_path(&db, root, root, |_| {}).await.unwrap_err();
+        assert!(err.to_string().contains("was built with"), "{err}");
+        let table = db.find_table(&project_table_name(root)).await;
+        assert!(table.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_removing_many_files_deletes_all_their_chunks() {
+        let project = tempfile::tempdir().unwrap();
+        let index = tempfile::tempdir().unwrap();
+        let db = VectorDB::connect(
+            &index.path().to_string_lossy(),
+            &EmbeddingProvider::Hashing { dimensions: 32 },
+        )
+        .await
+        .unwrap();
+        let root = project.path();
+        std::fs::create_dir(root.join("gen")).unwrap();
+        for i in 0..300 {
+            std::fs::write(
+                root.join(format!("gen/f{i}.rs")),
+                format!("fn f{i}() {{}}\n"),
+            )
+            .unwrap();
+        }
+        std::fs::write(root.join("keep.rs"), "fn keep() {}\n").unwrap();
+        index_path(&db, root, root, |_| {}).await.unwrap();
+
+        std::fs::remove_dir_all(root.join("gen")).unwrap();
+        let summary = index_path(&db, root, root, |_| {}).await.unwrap();
+        assert_eq!((summary.removed, summary.unchanged), (300, 1));
+        let table = db
+            .find_table(&project_table_name(root))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.count_rows(None).await.unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_watcher_reindexes_touched_files() {
+        let project = tempfile::tempdir().unwrap();
+        let index = tempfile::tempdir().unwrap();
+        let embedding = EmbeddingProvider::Hashing { dimensions: 32 };
+        let root = project.path().canonicalize().unwrap();
+        std::fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        let db = VectorDB::connect(&index.path().to_string_lossy(), &embedding)
+            .await
+            .unwrap();
+        index_path(&db, &root, &root, |_| {}).await.unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let _watcher = IndexWatcher::spawn(&root, index.path(), embedding, tx).unwrap();
+        std::fs::write(root.join("build.log"), "ignored").unwrap();
+        std::fs::write(root.join("a.rs"), "fn a() -> u8 { 0 }\n").unwrap();
+
+        let mut events = Vec::new();
+        while !matches!(events.last(), Some(WatchEvent::Finished(_))) {
+            let event = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+                .await
+                .expect("no index update")
+                .unwrap();
+            events.push(event);
+        }
+        match &events[..] {
+            [WatchEvent::Started { paths: 1 }, WatchEvent::Finished(summary)] => {
+                assert_eq!((summary.updated, summary.added), (1, 0));
+            }
+            other => panic!("unexpected events: {other:?}"),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_watcher_leaves_unindexed_projects_alone() {
+        let project = tempfile::tempdir().unwrap();
+        let index = tempfile::tempdir().unwrap();
+        let root = project.path().canonicalize().unwrap();
+        // Connecting to this embedder would fail.
+        let embedding = EmbeddingProvider::OpenAi {
+            provider: ModelProviderInfo {
+                id: "nowhere".to_string(),
+                base_url: "http://127.0.0.1:1/v1".to_string(),
+                api_key: ApiKeySource::None,
+                wire_api: WireApi::Chat,
+            },
+            model: "embed".to_string(),
+        };
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let _watcher = IndexWatcher::spawn(&root, index.path(), embedding, tx).unwrap();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+
+        let event = tokio::time::timeout(WATCH_DEBOUNCE * 4, rx.recv()).await;
+        assert!(event.is_err(), "unexpected event: {event:?}");
+    }
+}
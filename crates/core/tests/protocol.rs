@@ -0,0 +1,112 @@
+//! Tests for the submission/event wire format
+
+#[cfg(test)]
+mod tests {
+    use corty_core::protocol::{
+        AgentMessageDeltaEvent, Event, EventMsg, FileChange, Op, PlanItem, PlanUpdateEvent,
+        StepStatus, Submission, TokenCountEvent, TokenUsage,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_submission_round_trip() {
+        let raw = json!({
+            "id": "1",
+            "op": { "type": "add_to_history", "text": "hello" }
+        });
+        let submission: Submission = serde_json::from_value(raw).unwrap();
+        assert_eq!(submission.id, "1");
+        assert_eq!(
+            submission.op,
+            Op::AddToHistory {
+                text: "hello".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_event_uses_type_tag() {
+        let event = Event {
+            id: "7".to_string(),
+            msg: EventMsg::AgentMessageDelta(AgentMessageDeltaEvent {
+                delta: "Hel".to_string(),
+            }),
+        };
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "id": "7",
+                "msg": { "type": "agent_message_delta", "delta": "Hel" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_unit_event_serialization() {
+        let event = Event {
+            id: "2".to_string(),
+            msg: EventMsg::TaskStarted,
+        };
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            value,
+            json!({ "id": "2", "msg": { "type": "task_started" } })
+        );
+    }
+
+    #[test]
+    fn test_token_count_round_trip() {
+        let usage = TokenUsage {
+            input_tokens: 120,
+            cached_input_tokens: 100,
+            output_tokens: 30,
+            reasoning_output_tokens: 10,
+            total_tokens: 150,
+        };
+        let count = TokenCountEvent {
+            turn: usage.clone(),
+            session: usage,
+            estimated: false,
+        };
+        let json = serde_json::to_string(&EventMsg::TokenCount(count.clone())).unwrap();
+        match serde_json::from_str::<EventMsg>(&json).unwrap() {
+            EventMsg::TokenCount(parsed) => assert_eq!(parsed, count),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_file_change_serialization() {
+        let change = FileChange::Update {
+            unified_diff: "@@ -1 +1 @@\n-a\n+b\n".to_string(),
+            move_path: None,
+        };
+        let value = serde_json::to_value(&change).unwrap();
+        assert_eq!(value["type"], "update");
+        assert_eq!(serde_json::from_value::<FileChange>(value).unwrap(), change);
+    }
+
+    #[test]
+    fn test_plan_update_serialization() {
+        let update = PlanUpdateEvent {
+            explanation: None,
+            plan: vec![PlanItem {
+                step: "Add the parser".to_string(),
+                status: StepStatus::InProgress,
+            }],
+        };
+        let value = serde_json::to_value(EventMsg::PlanUpdate(update.clone())).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "type": "plan_update",
+                "plan": [{ "step": "Add the parser", "status": "in_progress" }]
+            })
+        );
+        match serde_json::from_value::<EventMsg>(value).unwrap() {
+            EventMsg::PlanUpdate(parsed) => assert_eq!(parsed, update),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+}
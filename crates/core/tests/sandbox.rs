@@ -0,0 +1,146 @@
+//! Tests for command confinement
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use corty_core::{protocol::SandboxPolicy, sandbox::sandboxed_command};
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    /// A directory outside the temp dirs, which are always writable.
+    fn scratch_dir() -> TempDir {
+        tempfile::tempdir_in(env!("CARGO_TARGET_TMPDIR")).unwrap()
+    }
+
+    fn write_file(cwd: &Path, policy: &SandboxPolicy, target: &Path) -> bool {
+        let script = format!("echo hi > '{}'", target.display());
+        sandboxed_command("sh", &["-c".to_string(), script], cwd, policy)
+            .unwrap()
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    #[test]
+    fn test_workspace_write_policy() {
+        let workspace = scratch_dir();
+        let outside = scratch_dir();
+        let policy = SandboxPolicy::default();
+
+        assert!(write_file(
+            workspace.path(),
+            &policy,
+            &workspace.path().join("inside.txt")
+        ));
+        assert!(!write_file(
+            workspace.path(),
+            &policy,
+            &outside.path().join("outside.txt")
+        ));
+        assert!(!outside.path().join("outside.txt").exists());
+
+        let policy = SandboxPolicy::WorkspaceWrite {
+            writable_roots: vec![outside.path().to_path_buf()],
+            network_access: false,
+        };
+        assert!(write_file(
+            workspace.path(),
+            &policy,
+            &outside.path().join("outside.txt")
+        ));
+    }
+
+    #[test]
+    fn test_read_only_and_full_access_policies() {
+        let workspace = scratch_dir();
+        let target = workspace.path().join("file.txt");
+
+        assert!(!write_file(
+            workspace.path(),
+            &SandboxPolicy::ReadOnly,
+            &target
+        ));
+        let output = sandboxed_command(
+            "cat",
+            &["Cargo.toml".to_string()],
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &SandboxPolicy::ReadOnly,
+        )
+        .unwrap()
+        .output()
+        .unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("corty-core"));
+
+        assert!(write_file(
+            workspace.path(),
+            &SandboxPolicy::DangerFullAccess,
+            &target
+        ));
+    }
+
+    /// Environment variable telling [`network_probe`] what to connect to.
+    const PROBE_TARGET: &str = "CORTY_SANDBOX_PROBE";
+
+    /// Not a test on its own: connects to `$CORTY_SANDBOX_PROBE` when this
+    /// binary is run again under a sandbox by [`can_connect`].
+    #[test]
+    fn network_probe() {
+        let Ok(target) = std::env::var(PROBE_TARGET) else {
+            return;
+        };
+        if let Some(path) = target.strip_prefix("unix:") {
+            std::os::unix::net::UnixStream::connect(path).unwrap();
+        } else {
+            std::net::TcpStream::connect(target).unwrap();
+        }
+    }
+
+    fn can_connect(cwd: &Path, policy: &SandboxPolicy, target: &str) -> bool {
+        let program = std::env::current_exe().unwrap();
+        let args = ["--exact", "tests::network_probe", "--test-threads=1"].map(String::from);
+        sandboxed_command(program.to_str().unwrap(), &args, cwd, policy)
+            .unwrap()
+            .env(PROBE_TARGET, target)
+            .output()
+            .unwrap()
+            .status
+            .success()
+    }
+
+    #[test]
+    fn test_network_access_is_limited_to_unix_sockets() {
+        let workspace = scratch_dir();
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = tcp.local_addr().unwrap().to_string();
+        let socket = workspace.path().join("probe.sock");
+        let _unix = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        let unix = format!("unix:{}", socket.display());
+
+        let policy = SandboxPolicy::default();
+        assert!(!policy.has_full_network_access());
+        assert!(!can_connect(workspace.path(), &policy, &tcp));
+        assert!(can_connect(workspace.path(), &policy, &unix));
+
+        let policy = SandboxPolicy::WorkspaceWrite {
+            writable_roots: Vec::new(),
+            network_access: true,
+        };
+        assert!(can_connect(workspace.path(), &policy, &tcp));
+    }
+
+    #[test]
+    fn test_policy_wire_format() {
+        let policy: SandboxPolicy =
+            serde_json::from_str(r#"{"mode":"workspace-write","network_access":true}"#).unwrap();
+        assert_eq!(
+            policy,
+            SandboxPolicy::WorkspaceWrite {
+                writable_roots: Vec::new(),
+                network_access: true,
+            }
+        );
+        assert!(policy.has_full_network_access());
+        assert!(!policy.has_full_disk_write_access());
+    }
+}
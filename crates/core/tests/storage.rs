@@ -0,0 +1,109 @@
+//! Tests for the index's chunk rows and embeddings
+
+#[cfg(test)]
+mod tests {
+    use corty_core::{
+        model_provider_info::built_in_model_providers,
+        storage::{
+            content_hash, file_filter, hash_embedding, CodeChunk, EmbeddingProvider,
+            DEFAULT_HASHING_DIMENSIONS,
+        },
+    };
+
+    #[test]
+    fn test_code_chunk_metadata() {
+        let chunk = CodeChunk::new(
+            "src/lib.rs",
+            "rust",
+            3,
+            5,
+            "fn main() {}\n",
+            Some("main".to_string()),
+        );
+        assert_eq!(chunk.id, "src/lib.rs:3-5");
+        assert_eq!(chunk.content_hash, content_hash("fn main() {}\n"));
+        assert_eq!(chunk.symbol.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(
+            content_hash(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_ne!(content_hash("a"), content_hash("b"));
+    }
+
+    #[test]
+    fn test_file_filter_escapes_quotes() {
+        assert_eq!(file_filter("it's.rs"), "path = 'it''s.rs'");
+    }
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| a * b).sum()
+    }
+
+    #[test]
+    fn test_hash_embedding_is_deterministic_and_normalized() {
+        let text = "fn retry_with_backoff(delay: Duration)";
+        let vector = hash_embedding(text, DEFAULT_HASHING_DIMENSIONS);
+        assert_eq!(vector.len(), DEFAULT_HASHING_DIMENSIONS);
+        assert_eq!(vector, hash_embedding(text, DEFAULT_HASHING_DIMENSIONS));
+        assert!((cosine(&vector, &vector) - 1.0).abs() < 1e-5);
+
+        assert_eq!(hash_embedding(text, 64).len(), 64);
+        assert!(hash_embedding("", 64).iter().all(|x| *x == 0.0));
+        assert!(hash_embedding(text, 0).is_empty());
+    }
+
+    #[test]
+    fn test_hash_embedding_ranks_shared_words_higher() {
+        let query = hash_embedding("retry backoff", DEFAULT_HASHING_DIMENSIONS);
+        let close = hash_embedding("Retry with exponential BACKOFF", DEFAULT_HASHING_DIMENSIONS);
+        let far = hash_embedding("parse the config file", DEFAULT_HASHING_DIMENSIONS);
+        assert!(cosine(&query, &close) > cosine(&query, &far));
+    }
+
+    #[test]
+    fn test_embedding_provider_selection() {
+        let providers = built_in_model_providers();
+        let parse = |spec: &str| EmbeddingProvider::parse(spec, &providers);
+
+        assert_eq!(
+            parse("hashing").unwrap(),
+            EmbeddingProvider::Hashing {
+                dimensions: DEFAULT_HASHING_DIMENSIONS
+            }
+        );
+        assert_eq!(
+            parse("sentence-transformers").unwrap(),
+            EmbeddingProvider::default()
+        );
+        assert_eq!(
+            parse("sentence-transformers:all-MiniLM-L12-v2").unwrap(),
+            EmbeddingProvider::SentenceTransformers {
+                model: Some("all-MiniLM-L12-v2".to_string())
+            }
+        );
+        assert_eq!(
+            parse("ollama:nomic-embed-text").unwrap(),
+            EmbeddingProvider::OpenAi {
+                provider: providers["ollama"].clone(),
+                model: "nomic-embed-text".to_string()
+            }
+        );
+        for spec in [
+            "hashing",
+            "sentence-transformers",
+            "sentence-transformers:all-MiniLM-L12-v2",
+            "ollama:nomic-embed-text",
+        ] {
+            assert_eq!(parse(spec).unwrap().to_string(), spec);
+        }
+
+        let err = parse("nowhere:model").unwrap_err().to_string();
+        assert_eq!(err, "unknown model provider `nowhere`");
+        let err = parse("bert").unwrap_err().to_string();
+        assert!(err.starts_with("unknown embedder `bert`"), "{err}");
+    }
+}
@@ -2,10 +2,18 @@ use crate::{
     event::{AppEvent, AppEventSender},
     utils::{mouse_capture::MouseCapture, scroll_event_helper::ScrollEventHelper},
     widgets::{
-        constants::{self, ERROR_TOGGLE_MOUSE_MODE},
+        constants::{
+            self, ERROR_ENGINE_BUSY, ERROR_ENGINE_STOPPED, ERROR_INDEX_UPDATE_PREFIX,
+            ERROR_TOGGLE_MOUSE_MODE, INDEX_UPDATE_FILE_ERRORS_PREFIX, INDEX_UPDATE_FINISHED_PREFIX,
+            INDEX_UPDATE_STARTED_PREFIX,
+        },
         ChatWidget, ChatWidgetState, Toaster, ToasterState, WelcomeWidget,
     },
 };
+use corty_core::{
+    indexer::WatchEvent,
+    protocol::{Event as CortyEvent, EventMsg, Op, Submission},
+};
 use ratatui::{
     crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind},
     layout::{Constraint, Direction, Layout, Margin},
@@ -20,7 +28,9 @@ use std::{
 };
 use tokio::time::{interval, Duration as TokioDuration};
 use tokio::{
-    sync::mpsc::{channel, Receiver},
+    sync::mpsc::{
+        channel, error::TrySendError, unbounded_channel, Receiver, Sender, UnboundedReceiver,
+    },
     task::JoinHandle,
 };
 
@@ -34,18 +44,50 @@ pub(crate) enum AppState<'a> {
 
 pub(crate) struct App<'a> {
     app_event_rx: Receiver<AppEvent>,
+    op_rx: UnboundedReceiver<Op>,
     app_event_tx: AppEventSender,
     app_state: AppState<'a>,
     blocking_task: Option<JoinHandle<()>>,
     shutdown_flag: Arc<AtomicBool>,
     fullscreen_mode: bool,
     toaster_state: ToasterState,
+    corty_tx: Sender<Submission>,
+    next_submission_id: u64,
 }
 
 impl<'a> App<'a> {
-    pub fn new() -> Self {
+    pub fn new(
+        corty_tx: Sender<Submission>,
+        mut corty_rx: Receiver<CortyEvent>,
+        mut index_rx: Receiver<WatchEvent>,
+    ) -> Self {
         let (tx, rx) = channel(100);
-        let app_event_tx = AppEventSender::new(tx);
+
+        // Forward engine events into the application event loop. This goes
+        // through the raw channel so events keep the order the engine sent them in.
+        {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while let Some(event) = corty_rx.recv().await {
+                    if tx.send(AppEvent::CortyEvent(event)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while let Some(event) = index_rx.recv().await {
+                    if tx.send(AppEvent::IndexWatch(event)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let (op_tx, op_rx) = unbounded_channel();
+        let app_event_tx = AppEventSender::new(tx, op_tx);
 
         let shutdown_flag = Arc::new(AtomicBool::new(false));
         let shutdown_flag_clone = shutdown_flag.clone();
@@ -117,14 +159,60 @@ impl<'a> App<'a> {
         Self {
             app_event_tx,
             app_event_rx: rx,
+            op_rx,
             app_state,
             blocking_task: task,
             shutdown_flag,
             fullscreen_mode: false,
             toaster_state: ToasterState::new(),
+            corty_tx,
+            next_submission_id: 0,
+        }
+    }
+
+    /// Send an operation to the engine under a fresh submission id
+    fn submit_op(&mut self, op: Op) {
+        self.next_submission_id += 1;
+        let submission = Submission {
+            id: self.next_submission_id.to_string(),
+            op,
+        };
+        match self.corty_tx.try_send(submission) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.toaster_state.warning(ERROR_ENGINE_BUSY),
+            Err(TrySendError::Closed(_)) => self.toaster_state.error(ERROR_ENGINE_STOPPED),
         }
     }
 
+    /// Report a background index update as a toast
+    fn show_index_update(&mut self, event: WatchEvent) {
+        match event {
+            WatchEvent::Started { paths } => self.toaster_state.info(format!(
+                "{INDEX_UPDATE_STARTED_PREFIX}{paths} changed path{}",
+                if paths == 1 { "" } else { "s" }
+            )),
+            WatchEvent::Finished(summary) => {
+                if !summary.errors.is_empty() {
+                    let (path, error) = &summary.errors[0];
+                    self.toaster_state.warning(format!(
+                        "{INDEX_UPDATE_FILE_ERRORS_PREFIX}{}: {error}",
+                        path.display()
+                    ));
+                }
+                if summary.added + summary.updated + summary.removed > 0 {
+                    self.toaster_state.success(format!(
+                        "{INDEX_UPDATE_FINISHED_PREFIX}{} added, {} updated, {} removed",
+                        summary.added, summary.updated, summary.removed
+                    ));
+                }
+            }
+            WatchEvent::Failed(message) => self
+                .toaster_state
+                .error(format!("{ERROR_INDEX_UPDATE_PREFIX}{message}")),
+        }
+        self.app_event_tx.send(AppEvent::Redraw);
+    }
+
     fn render(&mut self, frame: &mut Frame<'_>) {
         let padded_area = frame.area().inner(Margin {
             vertical: constants::APP_PADDING,
@@ -190,6 +278,9 @@ impl<'a> App<'a> {
 
         loop {
             tokio::select! {
+                Some(op) = self.op_rx.recv() => {
+                    self.submit_op(op);
+                }
                 Some(event) = self.app_event_rx.recv() => {
                     match event {
                         AppEvent::Redraw => {
@@ -227,19 +318,19 @@ impl<'a> App<'a> {
                         AppEvent::Scroll(scroll_delta) => {
                             self.dispatch_scroll_event(scroll_delta);
                         }
-                        AppEvent::Core => {
-                            self.app_event_tx.send(AppEvent::CoreAI);
-                        }
-                        AppEvent::CoreAI => match &mut self.app_state {
-                            AppState::Chat { widget, .. } => {
-                                widget.handle_core_event(AppEvent::CoreAI);
+                        AppEvent::CortyEvent(event) => {
+                            if let EventMsg::Error(error) = &event.msg {
+                                self.app_event_tx.send(AppEvent::Error(error.message.clone()));
                             }
-                        },
-                        AppEvent::AiProcessingComplete => match &mut self.app_state {
-                            AppState::Chat { widget, .. } => {
-                                widget.handle_core_event(AppEvent::AiProcessingComplete);
+                            match &mut self.app_state {
+                                AppState::Chat { widget, .. } => {
+                                    widget.handle_corty_event(event);
+                                }
                             }
-                        },
+                        }
+                        AppEvent::IndexWatch(event) => {
+                            self.show_index_update(event);
+                        }
                         AppEvent::ExitRequest => break,
                         AppEvent::MouseCaptureChanged(is_active) => {
                             match &mut self.app_state {
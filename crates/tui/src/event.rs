@@ -1,6 +1,10 @@
+use corty_core::{
+    indexer::WatchEvent,
+    protocol::{Event, Op},
+};
 use ratatui::crossterm::event::KeyEvent;
 use std::sync::mpsc::{channel as std_channel, Sender as StdSender};
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
 
 /// Application-wide events that drive the UI state machine
 #[derive(Debug)]
@@ -8,11 +12,11 @@ pub(crate) enum AppEvent {
     /// Request to redraw the UI
     Redraw,
 
-    /// Core processing event
-    Core,
+    /// Event received from the Corty engine
+    CortyEvent(Event),
 
-    /// AI core processing event
-    CoreAI,
+    /// Progress of the background code index updates
+    IndexWatch(WatchEvent),
 
     /// Scroll event with delta (positive = down, negative = up)
     Scroll(i32),
@@ -31,9 +35,6 @@ pub(crate) enum AppEvent {
 
     /// Error event with message
     Error(String),
-
-    /// AI processing has completed
-    AiProcessingComplete,
 }
 
 /// Thread-safe event sender that can be used from both async and sync contexts
@@ -41,11 +42,15 @@ pub(crate) enum AppEvent {
 pub struct AppEventSender {
     tx: Sender<AppEvent>,
     blocking_tx: StdSender<AppEvent>,
+    /// Operations for the engine. They go through a channel of their own,
+    /// unlike events, so they reach the engine in the order they were sent.
+    op_tx: UnboundedSender<Op>,
 }
 
 impl AppEventSender {
-    /// Creates a new event sender with both async and sync channels
-    pub fn new(tx: Sender<AppEvent>) -> Self {
+    /// Creates a new event sender with both async and sync channels, and
+    /// `op_tx` for operations
+    pub fn new(tx: Sender<AppEvent>, op_tx: UnboundedSender<Op>) -> Self {
         let (blocking_tx, blocking_rx) = std_channel();
 
         // Spawn a task to forward events from the blocking channel to the async channel
@@ -59,7 +64,16 @@ impl AppEventSender {
             }
         });
 
-        Self { tx, blocking_tx }
+        Self {
+            tx,
+            blocking_tx,
+            op_tx,
+        }
+    }
+
+    /// Submit an operation to the Corty engine, after any sent before it
+    pub fn submit(&self, op: Op) {
+        let _ = self.op_tx.send(op);
     }
 
     /// Send an event through the appropriate channel based on the current context
@@ -10,6 +10,12 @@
 //! - Real-time message updates and AI responses
 
 use color_eyre::eyre::Result;
+use corty_core::{
+    config::Config,
+    corty::Corty,
+    indexer::{IndexWatcher, WatchEvent},
+};
+use tokio::sync::mpsc::{channel, Sender};
 
 mod app;
 mod event;
@@ -23,21 +29,39 @@ pub mod widgets;
 /// Run the terminal user interface application
 ///
 /// This is the main entry point for the TUI. It initializes the terminal,
-/// sets up the application state, and runs the main event loop.
+/// starts the Corty engine with `config`, and runs the main event loop.
+/// While it runs, the project's code index follows changes to its files.
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - Terminal initialization fails
+/// - The Corty engine cannot be started
 /// - The event loop encounters an unrecoverable error
 /// - Terminal restoration fails
-pub async fn run_tui() -> Result<()> {
+pub async fn run_tui(config: Config) -> Result<()> {
+    // Keep the code index current; the session works without it
+    let (index_tx, index_rx) = channel(16);
+    let _watcher = watch_index(&config, index_tx);
+
+    // Start the engine first so a failure leaves the terminal untouched
+    let (corty_tx, corty_rx) = Corty::spawn(config)?;
+
     // Initialize terminal and mouse capture
     let (mut terminal, mut mouse_capture) = tui::init()?;
 
     // Create and run the application
-    let mut app = app::App::new();
+    let mut app = app::App::new(corty_tx, corty_rx, index_rx);
     app.run(&mut terminal, &mut mouse_capture).await?;
 
     Ok(())
 }
+
+/// Watches the working directory for changes to re-index, if there is an
+/// index directory.
+fn watch_index(config: &Config, events: Sender<WatchEvent>) -> Option<IndexWatcher> {
+    let index_dir = config.index_dir.as_ref()?;
+    IndexWatcher::spawn(&config.cwd, index_dir, config.embedding.clone(), events)
+        .inspect_err(|e| log::warn!("cannot watch {} for changes: {e}", config.cwd.display()))
+        .ok()
+}
@@ -4,7 +4,8 @@
 //! by typing '/' followed by the command name in the chat input.
 
 use crate::widgets::constants::{
-    SLASH_COMMAND_ASK_AI_DESC, SLASH_COMMAND_CLEAR_DESC, SLASH_COMMAND_FULLSCREEN_DESC,
+    SLASH_COMMAND_ASK_AI_DESC, SLASH_COMMAND_CLEAR_DESC, SLASH_COMMAND_COMPACT_DESC,
+    SLASH_COMMAND_FULLSCREEN_DESC,
 };
 use strum::IntoEnumIterator;
 use strum_macros::{AsRefStr, EnumIter, EnumString, IntoStaticStr};
@@ -21,6 +22,8 @@ pub enum SlashCommand {
     AskAI,
     /// Toggle fullscreen mode
     Fullscreen,
+    /// Summarize older turns to free up context
+    Compact,
 }
 
 impl SlashCommand {
@@ -30,6 +33,7 @@ impl SlashCommand {
             SlashCommand::Clear => SLASH_COMMAND_CLEAR_DESC,
             SlashCommand::AskAI => SLASH_COMMAND_ASK_AI_DESC,
             SlashCommand::Fullscreen => SLASH_COMMAND_FULLSCREEN_DESC,
+            SlashCommand::Compact => SLASH_COMMAND_COMPACT_DESC,
         }
     }
 
@@ -44,6 +48,7 @@ impl SlashCommand {
             SlashCommand::Clear => false,
             SlashCommand::AskAI => true,
             SlashCommand::Fullscreen => false,
+            SlashCommand::Compact => false,
         }
     }
 }
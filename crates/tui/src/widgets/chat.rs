@@ -6,9 +6,12 @@ use super::{
 };
 use crate::event::{AppEvent, AppEventSender};
 use crate::slash_command::SlashCommand;
+use corty_core::protocol::{
+    Event, EventMsg, FileChange, InputItem, Op, ReviewDecision, StepStatus, TurnAbortReason,
+};
 use ratatui::{
     buffer::Buffer,
-    crossterm::event::KeyEvent,
+    crossterm::event::{KeyCode, KeyEvent},
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     widgets::{Block, BorderType, Borders, Widget},
@@ -21,6 +24,13 @@ pub(crate) struct ChatWidgetState {
     // Reserved for future state management
 }
 
+/// Approval request waiting for the user's answer, by call id
+#[derive(Clone, Debug)]
+enum PendingApproval {
+    Exec(String),
+    Patch(String),
+}
+
 /// Main chat widget that handles user input and displays message history
 pub(crate) struct ChatWidget<'a> {
     app_event_tx: AppEventSender,
@@ -33,6 +43,7 @@ pub(crate) struct ChatWidget<'a> {
     ai_working_start: Option<std::time::Instant>,
     ai_processing: bool,
     saved_textarea_content: Option<Vec<String>>,
+    pending_approval: Option<PendingApproval>,
 }
 
 impl<'a> ChatWidget<'a> {
@@ -53,6 +64,7 @@ impl<'a> ChatWidget<'a> {
             ai_working_start: None,
             ai_processing: false,
             saved_textarea_content: None,
+            pending_approval: None,
         };
         this.set_placeholder();
         this.set_border();
@@ -107,7 +119,9 @@ impl<'a> ChatWidget<'a> {
             ""
         };
 
-        let title = if self.ai_processing {
+        let title = if self.pending_approval.is_some() {
+            CHAT_TITLE_APPROVAL.to_string()
+        } else if self.ai_processing {
             format!("{}{}", fullscreen_indicator, CHAT_TITLE_AI_PROCESSING)
         } else if let Some(cmd) = self.active_command {
             match cmd {
@@ -126,7 +140,9 @@ impl<'a> ChatWidget<'a> {
             )
         };
 
-        let border_color = if self.ai_processing {
+        let border_color = if self.pending_approval.is_some() {
+            colors::PRIMARY_TEXT
+        } else if self.ai_processing {
             Color::Rgb(100, 100, 100) // Gray when processing
         } else if self.active_command.is_some() {
             colors::PRIMARY_TEXT
@@ -144,73 +160,221 @@ impl<'a> ChatWidget<'a> {
         );
     }
 
-    /// Handle core AI events
-    pub(crate) fn handle_core_event(&mut self, event: AppEvent) {
-        match event {
-            AppEvent::CoreAI => {
-                // Add AI working indicator
-                self.add_ai_working();
-                self.ai_working_start = Some(std::time::Instant::now());
-                self.ai_processing = true;
-
-                // Save current textarea content and clear it
-                if !self.textarea.is_empty() {
-                    self.saved_textarea_content = Some(self.textarea.lines().to_vec());
-                    self.clear_text_area();
-                }
+    /// Send a user message to the engine and show the working indicator
+    fn submit_user_message(&mut self, text: String) {
+        self.add_user_message(text.clone());
+        self.app_event_tx.submit(Op::UserInput {
+            items: vec![InputItem::Text { text: text.clone() }],
+        });
+        self.app_event_tx.submit(Op::AddToHistory { text });
+        self.start_ai_processing();
+    }
 
-                self.set_placeholder();
-                self.set_border();
-                self.request_redraw();
+    /// Enter the processing state while the engine works on a task
+    fn start_ai_processing(&mut self) {
+        // Only one AI working indicator at a time
+        self.remove_ai_working();
+        self.add_ai_working();
+        self.ai_working_start = Some(std::time::Instant::now());
+        self.ai_processing = true;
+
+        // Save current textarea content and clear it
+        if !self.textarea.is_empty() {
+            self.saved_textarea_content = Some(self.textarea.lines().to_vec());
+            self.clear_text_area();
+        }
 
-                // Simulate AI processing with a delay
-                let app_event_tx = self.app_event_tx.clone();
-                tokio::spawn(async move {
-                    // Simulate processing delay
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        self.set_placeholder();
+        self.set_border();
+        self.request_redraw();
+    }
 
-                    // Simulate an error for demonstration purposes
-                    app_event_tx.send(AppEvent::Error(AI_ERROR_CONNECTION_TIMEOUT.to_string()));
+    /// Leave the processing state once the engine reports the task complete
+    fn finish_ai_processing(&mut self) {
+        // Remove AI working indicator
+        self.remove_ai_working();
+        self.ai_working_start = None;
+        self.ai_processing = false;
+        self.pending_approval = None;
+
+        // Restore saved textarea content if any
+        if let Some(content) = self.saved_textarea_content.take() {
+            for line in content {
+                self.textarea.insert_str(&line);
+                self.textarea.insert_newline();
+            }
+            // Remove the extra newline at the end
+            if !self.textarea.is_empty() {
+                self.textarea.delete_char();
+            }
+        }
 
-                    // Signal processing complete
-                    app_event_tx.send(AppEvent::AiProcessingComplete);
-                });
+        self.set_placeholder();
+        self.set_border();
+        self.request_redraw();
+    }
+
+    /// Add a message above the working indicator while a task is running
+    fn add_agent_message_while_processing(&mut self, message: String) {
+        self.remove_ai_working();
+        self.add_agent_message(message);
+        if self.ai_processing {
+            self.add_ai_working();
+        }
+    }
+
+    /// Handle events coming from the Corty engine
+    pub(crate) fn handle_corty_event(&mut self, event: Event) {
+        match event.msg {
+            EventMsg::AgentMessage(message) => {
+                self.add_agent_message_while_processing(message.message);
+                self.request_redraw();
             }
-            AppEvent::AiProcessingComplete => {
-                // Remove AI working indicator
-                self.remove_ai_working();
-                self.ai_working_start = None;
-                self.ai_processing = false;
-
-                // Restore saved textarea content if any
-                if let Some(content) = self.saved_textarea_content.take() {
-                    for line in content {
-                        self.textarea.insert_str(&line);
-                        self.textarea.insert_newline();
-                    }
-                    // Remove the extra newline at the end
-                    if !self.textarea.is_empty() {
-                        self.textarea.delete_char();
+            EventMsg::Error(error) => {
+                self.add_agent_message_while_processing(format!(
+                    "{}{}",
+                    AI_ERROR_RESPONSE_PREFIX, error.message
+                ));
+                self.request_redraw();
+            }
+            EventMsg::ContextCompacted(compacted) => {
+                self.add_agent_message_while_processing(format!(
+                    "{}~{} -> ~{} tokens ({} items summarized)",
+                    AI_CONTEXT_COMPACTED_PREFIX,
+                    compacted.tokens_before,
+                    compacted.tokens_after,
+                    compacted.items_summarized
+                ));
+                self.request_redraw();
+            }
+            EventMsg::ToolCallBegin(call) => {
+                self.add_agent_message_while_processing(format!(
+                    "{}{} {}",
+                    AI_TOOL_CALL_PREFIX, call.tool, call.arguments
+                ));
+                self.request_redraw();
+            }
+            EventMsg::SubAgent(sub_agent) => {
+                let message = match *sub_agent.msg {
+                    EventMsg::ToolCallBegin(call) => format!(
+                        "{}{}{} {}",
+                        AI_SUB_AGENT_PREFIX, AI_TOOL_CALL_PREFIX, call.tool, call.arguments
+                    ),
+                    EventMsg::AgentMessage(message) => {
+                        format!("{}{}", AI_SUB_AGENT_PREFIX, message.message)
                     }
+                    EventMsg::Error(error) => format!(
+                        "{}{}{}",
+                        AI_SUB_AGENT_PREFIX, AI_ERROR_RESPONSE_PREFIX, error.message
+                    ),
+                    _ => return,
+                };
+                self.add_agent_message_while_processing(message);
+                self.request_redraw();
+            }
+            EventMsg::PlanUpdate(update) => {
+                let mut message = AI_PLAN_TITLE.to_string();
+                if let Some(explanation) = update.explanation {
+                    message.push_str(&format!(" - {explanation}"));
                 }
-
-                self.set_placeholder();
-                self.set_border();
-
-                // Add error response
-                self.add_agent_message(AI_ERROR_RESPONSE.to_string());
+                for item in update.plan {
+                    let marker = match item.status {
+                        StepStatus::Pending => PLAN_STEP_PENDING,
+                        StepStatus::InProgress => PLAN_STEP_IN_PROGRESS,
+                        StepStatus::Done => PLAN_STEP_DONE,
+                    };
+                    message.push_str(&format!("\n{marker}{}", item.step));
+                }
+                self.add_agent_message_while_processing(message);
                 self.request_redraw();
             }
+            EventMsg::ExecApprovalRequest(request) => {
+                let mut message = format!(
+                    "{}`{}` in {}",
+                    AI_EXEC_APPROVAL_PREFIX,
+                    request.command.join(" "),
+                    request.cwd.display()
+                );
+                if let Some(reason) = request.reason {
+                    message.push_str(&format!("\n{reason}"));
+                }
+                self.ask_for_approval(PendingApproval::Exec(request.call_id), message);
+            }
+            EventMsg::ApplyPatchApprovalRequest(request) => {
+                let mut paths: Vec<String> = request
+                    .changes
+                    .iter()
+                    .map(|(path, change)| {
+                        let kind = match change {
+                            FileChange::Add { .. } => "add",
+                            FileChange::Delete => "delete",
+                            FileChange::Update { .. } => "update",
+                        };
+                        format!("{kind} {}", path.display())
+                    })
+                    .collect();
+                paths.sort();
+                let mut message = format!("{}\n{}", AI_PATCH_APPROVAL_PREFIX, paths.join("\n"));
+                if let Some(reason) = request.reason {
+                    message.push_str(&format!("\n{reason}"));
+                }
+                self.ask_for_approval(PendingApproval::Patch(request.call_id), message);
+            }
+            EventMsg::TaskComplete(_) => {
+                self.finish_ai_processing();
+            }
+            EventMsg::TurnAborted(aborted) => {
+                let message = match aborted.reason {
+                    TurnAbortReason::Interrupted => AI_TURN_ABORTED_MESSAGE.to_string(),
+                    reason => format!("{}{}", AI_TURN_STOPPED_PREFIX, reason),
+                };
+                self.add_agent_message_while_processing(message);
+                self.finish_ai_processing();
+            }
             _ => {}
         }
     }
 
+    /// Show an approval request and wait for the user's answer
+    fn ask_for_approval(&mut self, pending: PendingApproval, message: String) {
+        self.add_agent_message_while_processing(message);
+        self.pending_approval = Some(pending);
+        self.set_border();
+        self.request_redraw();
+    }
+
+    /// Answer the pending approval request, if `key_event` is an answer
+    fn handle_approval_key_event(&mut self, key_event: KeyEvent) {
+        let decision = match key_event.code {
+            KeyCode::Char('y') => ReviewDecision::Approved,
+            KeyCode::Char('a') => ReviewDecision::ApprovedForSession,
+            KeyCode::Char('n') => ReviewDecision::Denied,
+            KeyCode::Esc => ReviewDecision::Abort,
+            _ => return,
+        };
+        let op = match self.pending_approval.take() {
+            Some(PendingApproval::Exec(id)) => Op::ExecApproval { id, decision },
+            Some(PendingApproval::Patch(id)) => Op::PatchApproval { id, decision },
+            None => return,
+        };
+        self.app_event_tx.submit(op);
+        self.set_border();
+        self.request_redraw();
+    }
+
     /// Handle keyboard events, delegating to history or input as appropriate
     pub(crate) fn handle_key_event(&mut self, key_event: KeyEvent) {
+        if self.pending_approval.is_some() {
+            self.handle_approval_key_event(key_event);
+            return;
+        }
+
         // Allow certain shortcuts even when AI is processing
         if self.ai_processing {
-            // Only allow escape to cancel (if we add cancellation support later)
-            // For now, block all input during processing
+            // Only Escape gets through: it interrupts the running task
+            if key_event.code == KeyCode::Esc {
+                self.app_event_tx.submit(Op::Interrupt);
+            }
             return;
         }
 
@@ -352,19 +516,19 @@ impl<'a> ChatWidget<'a> {
                         text.clone()
                     };
 
+                    self.clear_text_area();
+                    self.active_command = None;
+
                     if !prompt.trim().is_empty() {
                         self.execute_slash_command(cmd, Some(prompt));
                     }
 
-                    self.clear_text_area();
-                    self.active_command = None;
                     self.set_placeholder();
                     self.set_border();
                 } else if !text.trim().is_empty() {
                     // Default behavior: send message to AI (same as /ask-ai)
-                    self.add_user_message(text.clone());
                     self.clear_text_area();
-                    self.dispatch_event(AppEvent::Core);
+                    self.submit_user_message(text);
                 }
             }
             // Ctrl+N: insert newline
@@ -488,10 +652,13 @@ impl<'a> ChatWidget<'a> {
             SlashCommand::AskAI => {
                 if let Some(prompt) = prompt {
                     // Send the user's prompt to the AI
-                    self.add_user_message(prompt.clone());
-                    self.dispatch_event(AppEvent::Core);
+                    self.submit_user_message(prompt);
                 }
             }
+            SlashCommand::Compact => {
+                self.app_event_tx.submit(Op::Compact);
+                self.start_ai_processing();
+            }
             SlashCommand::Fullscreen => {
                 self.dispatch_event(AppEvent::ToggleFullscreen);
                 self.fullscreen_mode = !self.fullscreen_mode;
@@ -53,7 +53,9 @@ pub(crate) const MOUSE_STATUS_OFF: &str = "[OFF]";
 pub(crate) const FULLSCREEN_INDICATOR: &str = "[FULLSCREEN] ";
 
 /// Chat widget border titles
-pub(crate) const CHAT_TITLE_AI_PROCESSING: &str = "AI is processing... Input disabled";
+pub(crate) const CHAT_TITLE_AI_PROCESSING: &str = "AI is processing... Esc to interrupt";
+pub(crate) const CHAT_TITLE_APPROVAL: &str =
+    "y to allow | a to allow for this session | n to deny | Esc to stop";
 pub(crate) const CHAT_TITLE_ASK_AI_MODE: &str =
     "Ask AI mode - Type your question and press Enter | Esc to cancel";
 pub(crate) const CHAT_TITLE_NORMAL: &str =
@@ -106,6 +108,7 @@ pub(crate) const SLASH_COMMAND_ASK_AI_DESC: &str =
     "Ask the AI (same as typing without slash command)";
 pub(crate) const SLASH_COMMAND_FULLSCREEN_DESC: &str =
     "Toggle fullscreen mode (hide/show welcome widget)";
+pub(crate) const SLASH_COMMAND_COMPACT_DESC: &str = "Summarize older turns to free up context";
 
 // ============================================================================
 // Error Messages
@@ -114,10 +117,29 @@ pub(crate) const SLASH_COMMAND_FULLSCREEN_DESC: &str =
 /// Log error messages
 pub(crate) const ERROR_TOGGLE_MOUSE_MODE: &str = "Failed to toggle mouse mode: {}";
 
-/// AI error messages
-pub(crate) const AI_ERROR_CONNECTION_TIMEOUT: &str = "AI Service Error: Connection timeout - Unable to reach the AI backend. Please check your network connection and try again.";
-pub(crate) const AI_ERROR_RESPONSE: &str =
-    "Sorry, I couldn't process your request due to a connection error. Please try again later.";
+/// Engine error messages
+pub(crate) const ERROR_ENGINE_STOPPED: &str = "Corty engine stopped - restart Corty to continue";
+pub(crate) const ERROR_ENGINE_BUSY: &str = "Corty engine is busy - message was not sent";
+pub(crate) const AI_TURN_ABORTED_MESSAGE: &str = "Interrupted - the AI stopped before finishing.";
+pub(crate) const AI_ERROR_RESPONSE_PREFIX: &str = "Sorry, I couldn't process your request: ";
+pub(crate) const AI_CONTEXT_COMPACTED_PREFIX: &str = "Context compacted: ";
+pub(crate) const AI_TURN_STOPPED_PREFIX: &str = "Stopped - ";
+pub(crate) const AI_TOOL_CALL_PREFIX: &str = "Running tool: ";
+pub(crate) const AI_EXEC_APPROVAL_PREFIX: &str = "Allow this command? ";
+pub(crate) const AI_PATCH_APPROVAL_PREFIX: &str = "Allow these file changes?";
+pub(crate) const AI_PLAN_TITLE: &str = "Plan";
+pub(crate) const AI_SUB_AGENT_PREFIX: &str = "Sub-agent > ";
+
+/// Code index update messages
+pub(crate) const INDEX_UPDATE_STARTED_PREFIX: &str = "Updating the code index: ";
+pub(crate) const INDEX_UPDATE_FINISHED_PREFIX: &str = "Code index updated: ";
+pub(crate) const INDEX_UPDATE_FILE_ERRORS_PREFIX: &str = "Could not index ";
+pub(crate) const ERROR_INDEX_UPDATE_PREFIX: &str = "Code index update failed: ";
+
+/// Plan step markers
+pub(crate) const PLAN_STEP_PENDING: &str = "[ ] ";
+pub(crate) const PLAN_STEP_IN_PROGRESS: &str = "[>] ";
+pub(crate) const PLAN_STEP_DONE: &str = "[x] ";
 
 // ============================================================================
 // Toaster Constants
@@ -7,10 +7,11 @@ mod tests {
     #[test]
     fn test_slash_commands_available() {
         let commands = built_in_slash_commands();
-        assert_eq!(commands.len(), 3);
+        assert_eq!(commands.len(), 4);
         assert!(commands.contains(&SlashCommand::Clear));
         assert!(commands.contains(&SlashCommand::AskAI));
         assert!(commands.contains(&SlashCommand::Fullscreen));
+        assert!(commands.contains(&SlashCommand::Compact));
     }
 
     #[test]
@@ -31,6 +32,7 @@ mod tests {
         assert_eq!(SlashCommand::Clear.command(), "clear");
         assert_eq!(SlashCommand::AskAI.command(), "ask-ai");
         assert_eq!(SlashCommand::Fullscreen.command(), "fullscreen");
+        assert_eq!(SlashCommand::Compact.command(), "compact");
     }
 
     #[test]
@@ -38,5 +40,6 @@ mod tests {
         assert_eq!(SlashCommand::Clear.requires_input(), false);
         assert_eq!(SlashCommand::AskAI.requires_input(), true);
         assert_eq!(SlashCommand::Fullscreen.requires_input(), false);
+        assert_eq!(SlashCommand::Compact.requires_input(), false);
     }
 }